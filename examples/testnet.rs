@@ -35,7 +35,13 @@ fn main() {
 
     trainer.set_optimiser_params(optimiser::AdamWParams::default());
 
-    let settings = LocalSettings { threads: 4, test_set: None, output_directory: "checkpoints", batch_queue_size: 512 };
+    let settings = LocalSettings {
+        threads: 4,
+        test_set: None,
+        output_directory: "checkpoints",
+        batch_queue_size: 512,
+        ..Default::default()
+    };
 
     let data_loader = loader::DirectSequentialDataLoader::new(&["data/batch1.data"]);
 
@@ -0,0 +1,301 @@
+//! The files in a checkpoint's `optimiser_state/`, e.g. `weights.bin`, which are laid out as
+//! - `MAGIC`
+//! - format version (`u32`)
+//! - architecture hash (`u64`), see `architecture_hash`
+//! - number of tensors (`u64`)
+//! - for each tensor, the length of its id (`u32`), its id, and its rows and cols (`u64`s)
+//! - the values of each tensor (`f32`s, column-major), in the same order
+//! - `fnv1a` of everything prior (`u64`)
+//!
+//! Files written before this format was introduced are just concatenated tensors, each an id,
+//! a newline, rows and cols (`u64`s) and values, and can be read with `decode_legacy`.
+use std::{fs, io, path::Path};
+
+use crate::{fnv1a, Fnv1a};
+
+/// The first bytes of a checkpoint file, absent from legacy files.
+pub const MAGIC: [u8; 8] = *b"BULLETCK";
+
+/// Version of the checkpoint format written by `encode`.
+pub const VERSION: u32 = 1;
+
+/// A labelled tensor from a checkpoint file, column-major.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tensor {
+    pub id: String,
+    pub rows: usize,
+    pub cols: usize,
+    pub values: Vec<f32>,
+}
+
+/// Reads a checkpoint file, checking its checksum and architecture hash.
+/// Legacy files are not supported, see `decode_legacy`.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Tensor>> {
+    decode(&fs::read(path)?)
+}
+
+/// Writes a checkpoint file that can be loaded by bullet.
+pub fn write(path: impl AsRef<Path>, tensors: &[Tensor]) -> io::Result<()> {
+    fs::write(path, encode(tensors))
+}
+
+/// Hash of the ids and shapes (rows and cols) of a set of tensors, independent of their order.
+pub fn architecture_hash<'a>(tensors: impl IntoIterator<Item = (&'a str, usize, usize)>) -> u64 {
+    let mut tensors = tensors.into_iter().collect::<Vec<_>>();
+    tensors.sort_by_key(|(id, _, _)| *id);
+
+    let mut hasher = Fnv1a::default();
+
+    for (id, rows, cols) in tensors {
+        hasher.write(id.as_bytes());
+        hasher.write(&[0]);
+        hasher.write(&(rows as u64).to_le_bytes());
+        hasher.write(&(cols as u64).to_le_bytes());
+    }
+
+    hasher.0
+}
+
+/// Encodes the contents of a checkpoint file, see `write`.
+pub fn encode(tensors: &[Tensor]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    buf.extend_from_slice(&MAGIC);
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+    buf.extend_from_slice(&hash_of(tensors).to_le_bytes());
+    buf.extend_from_slice(&(tensors.len() as u64).to_le_bytes());
+
+    for tensor in tensors {
+        assert_eq!(tensor.rows * tensor.cols, tensor.values.len(), "Tensor [{}] has the wrong size!", tensor.id);
+        buf.extend_from_slice(&(tensor.id.len() as u32).to_le_bytes());
+        buf.extend_from_slice(tensor.id.as_bytes());
+        buf.extend_from_slice(&(tensor.rows as u64).to_le_bytes());
+        buf.extend_from_slice(&(tensor.cols as u64).to_le_bytes());
+    }
+
+    for tensor in tensors {
+        tensor.values.iter().for_each(|x| buf.extend_from_slice(&x.to_le_bytes()));
+    }
+
+    buf.extend_from_slice(&fnv1a(&buf).to_le_bytes());
+
+    buf
+}
+
+/// Decodes the contents of a checkpoint file, see `read`.
+pub fn decode(buf: &[u8]) -> io::Result<Vec<Tensor>> {
+    if !buf.starts_with(&MAGIC) {
+        return Err(invalid("Not a checkpoint file, or saved by an old version of bullet!"));
+    }
+
+    if buf.len() < MAGIC.len() + 8 {
+        return Err(invalid("Checkpoint file is truncated!"));
+    }
+
+    let (contents, checksum) = buf.split_at(buf.len() - 8);
+
+    if fnv1a(contents) != u64::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(invalid("Checkpoint file is corrupted (checksum mismatch)!"));
+    }
+
+    let mut reader = Reader { bytes: contents, offset: MAGIC.len() };
+
+    let version = u32::from_le_bytes(reader.read()?);
+    if version != VERSION {
+        return Err(invalid(format!("Unsupported checkpoint version {version}!")));
+    }
+
+    let arch_hash = u64::from_le_bytes(reader.read()?);
+    let count = u64::from_le_bytes(reader.read()?);
+
+    let mut tensors = Vec::new();
+
+    for _ in 0..count {
+        let len = u32::from_le_bytes(reader.read()?) as usize;
+        let id = String::from_utf8(reader.take(len)?.to_vec()).map_err(|_| invalid("Tensor id is not valid UTF-8!"))?;
+        let rows = u64::from_le_bytes(reader.read()?) as usize;
+        let cols = u64::from_le_bytes(reader.read()?) as usize;
+        check_shape(rows, cols)?;
+        tensors.push(Tensor { id, rows, cols, values: Vec::new() });
+    }
+
+    for tensor in &mut tensors {
+        tensor.values = reader.read_values(tensor.rows * tensor.cols)?;
+    }
+
+    if reader.offset != contents.len() {
+        return Err(invalid("Checkpoint file has trailing data!"));
+    }
+
+    if arch_hash != hash_of(&tensors) {
+        return Err(invalid("Checkpoint architecture hash does not match its tensors!"));
+    }
+
+    Ok(tensors)
+}
+
+/// Decodes a checkpoint file written before the format had a header, which has no
+/// checksum or architecture hash to check.
+pub fn decode_legacy(buf: &[u8]) -> io::Result<Vec<Tensor>> {
+    let mut reader = Reader { bytes: buf, offset: 0 };
+    let mut tensors = Vec::new();
+
+    while reader.offset < buf.len() {
+        let rest = &buf[reader.offset..];
+        let len = rest.iter().position(|&ch| ch == b'\n').ok_or_else(|| invalid("Checkpoint file is truncated!"))?;
+        let id = String::from_utf8(reader.take(len)?.to_vec()).map_err(|_| invalid("Tensor id is not valid UTF-8!"))?;
+        reader.take(1)?;
+
+        let rows = u64::from_le_bytes(reader.read()?) as usize;
+        let cols = u64::from_le_bytes(reader.read()?) as usize;
+        check_shape(rows, cols)?;
+        let values = reader.read_values(rows * cols)?;
+
+        tensors.push(Tensor { id, rows, cols, values });
+    }
+
+    Ok(tensors)
+}
+
+fn hash_of(tensors: &[Tensor]) -> u64 {
+    architecture_hash(tensors.iter().map(|tensor| (tensor.id.as_str(), tensor.rows, tensor.cols)))
+}
+
+fn check_shape(rows: usize, cols: usize) -> io::Result<()> {
+    if rows == 0 || cols == 0 || rows.checked_mul(cols).is_none() {
+        return Err(invalid(format!("Tensor has invalid shape {rows}x{cols}!")));
+    }
+
+    Ok(())
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self.offset.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| invalid("Checkpoint file is truncated!"))?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn read_values(&mut self, len: usize) -> io::Result<Vec<f32>> {
+        let bytes = self.take(len.checked_mul(4).ok_or_else(|| invalid("Tensor is too large!"))?)?;
+        Ok(bytes.chunks_exact(4).map(|word| f32::from_le_bytes(word.try_into().unwrap())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tensors() -> Vec<Tensor> {
+        vec![
+            Tensor { id: String::from("l0w"), rows: 2, cols: 3, values: vec![1.0, -2.0, 0.5, 0.0, 3.25, -0.125] },
+            Tensor { id: String::from("l0b"), rows: 2, cols: 1, values: vec![0.1, -0.1] },
+        ]
+    }
+
+    /// Writes tensors as `DenseMatrix::write_to_byte_buffer` did before checkpoints had a header.
+    fn legacy_bytes(tensors: &[(&str, usize, usize, &[f32])]) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        for (id, rows, cols, values) in tensors {
+            buf.extend_from_slice(id.as_bytes());
+            buf.push(b'\n');
+            buf.extend_from_slice(&(*rows as u64).to_le_bytes());
+            buf.extend_from_slice(&(*cols as u64).to_le_bytes());
+
+            for val in *values {
+                buf.extend_from_slice(&val.to_le_bytes());
+            }
+        }
+
+        buf
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join(format!("bullet-inference-checkpoint-{}.bin", std::process::id()));
+
+        write(&path, &tensors()).unwrap();
+        let read = read(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.unwrap(), tensors());
+    }
+
+    #[test]
+    fn layout() {
+        let buf = encode(&tensors()[1..]);
+
+        let mut expected = b"BULLETCK".to_vec();
+        expected.extend_from_slice(&1u32.to_le_bytes());
+        expected.extend_from_slice(&architecture_hash([("l0b", 2, 1)]).to_le_bytes());
+        expected.extend_from_slice(&1u64.to_le_bytes());
+        expected.extend_from_slice(&3u32.to_le_bytes());
+        expected.extend_from_slice(b"l0b");
+        expected.extend_from_slice(&2u64.to_le_bytes());
+        expected.extend_from_slice(&1u64.to_le_bytes());
+        expected.extend_from_slice(&0.1f32.to_le_bytes());
+        expected.extend_from_slice(&(-0.1f32).to_le_bytes());
+        expected.extend_from_slice(&fnv1a(&expected).to_le_bytes());
+
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn architecture_hash_ignores_order() {
+        let hash = architecture_hash([("l0w", 2, 3), ("l0b", 2, 1)]);
+        assert_eq!(hash, architecture_hash([("l0b", 2, 1), ("l0w", 2, 3)]));
+        assert_ne!(hash, architecture_hash([("l0w", 3, 2), ("l0b", 2, 1)]));
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let buf = encode(&tensors());
+
+        let mut corrupted = buf.clone();
+        corrupted[30] ^= 1;
+        assert!(decode(&corrupted).is_err());
+
+        assert!(decode(&buf[..buf.len() - 1]).is_err());
+        assert!(decode(b"BULLETCK").is_err());
+        assert!(decode(&[0; 64]).is_err());
+
+        // a valid checksum over a tensor claiming more values than there are
+        let mut truncated = buf[..buf.len() - 12].to_vec();
+        truncated.extend_from_slice(&fnv1a(&truncated).to_le_bytes());
+        assert!(decode(&truncated).is_err());
+    }
+
+    #[test]
+    fn legacy() {
+        let bytes = legacy_bytes(&[("l0w", 2, 3, &[1.0, -2.0, 0.5, 0.0, 3.25, -0.125]), ("l0b", 2, 1, &[0.1, -0.1])]);
+        assert_eq!(decode_legacy(&bytes).unwrap(), tensors());
+    }
+
+    #[test]
+    fn legacy_rejects_bad_input() {
+        for (rows, cols) in [(0, 3), (2, 0), (usize::MAX, 2)] {
+            let err = decode_legacy(&legacy_bytes(&[("l0w", rows, cols, &[])])).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let mut truncated = legacy_bytes(&[("l0w", 2, 1, &[1.0, 2.0])]);
+        truncated.pop();
+        assert!(decode_legacy(&truncated).is_err());
+    }
+}
@@ -1,11 +1,11 @@
 use bulletformat::AtaxxBoard;
 
-use super::SparseInputType;
+use super::Features;
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Ataxx147;
-impl SparseInputType for Ataxx147 {
-    type RequiredDataType = AtaxxBoard;
+impl Features for Ataxx147 {
+    type Position = AtaxxBoard;
 
     fn num_inputs(&self) -> usize {
         147
@@ -15,7 +15,7 @@ impl SparseInputType for Ataxx147 {
         49
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         for (piece, square) in <AtaxxBoard as std::iter::IntoIterator>::into_iter(*pos) {
             let pc = usize::from(piece);
             let sq = usize::from(square);
@@ -37,8 +37,8 @@ impl SparseInputType for Ataxx147 {
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Ataxx98;
-impl SparseInputType for Ataxx98 {
-    type RequiredDataType = AtaxxBoard;
+impl Features for Ataxx98 {
+    type Position = AtaxxBoard;
 
     fn num_inputs(&self) -> usize {
         98
@@ -48,7 +48,7 @@ impl SparseInputType for Ataxx98 {
         49
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         Ataxx147.map_features(pos, |stm, ntm| {
             if stm < 98 {
                 f(stm, ntm)
@@ -1,11 +1,11 @@
 use bulletformat::ChessBoard;
 
-use super::SparseInputType;
+use super::Features;
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Chess768;
-impl SparseInputType for Chess768 {
-    type RequiredDataType = ChessBoard;
+impl Features for Chess768 {
+    type Position = ChessBoard;
 
     /// The total number of inputs
     fn num_inputs(&self) -> usize {
@@ -17,7 +17,7 @@ impl SparseInputType for Chess768 {
         32
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         for (piece, square) in pos.into_iter() {
             let c = usize::from(piece & 8 > 0);
             let pc = 64 * usize::from(piece & 7);
@@ -1,6 +1,6 @@
 use bulletformat::ChessBoard;
 
-use super::{get_num_buckets, Chess768, Factorises, SparseInputType};
+use super::{get_num_buckets, Chess768, Factorises, Features};
 
 #[derive(Clone, Copy, Debug)]
 pub struct ChessBuckets {
@@ -14,8 +14,8 @@ impl ChessBuckets {
     }
 }
 
-impl SparseInputType for ChessBuckets {
-    type RequiredDataType = ChessBoard;
+impl Features for ChessBuckets {
+    type Position = ChessBoard;
 
     fn num_inputs(&self) -> usize {
         768 * self.num_buckets
@@ -25,7 +25,7 @@ impl SparseInputType for ChessBuckets {
         32
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         let our_bucket = 768 * self.buckets[usize::from(pos.our_ksq())];
         let opp_bucket = 768 * self.buckets[usize::from(pos.opp_ksq())];
 
@@ -66,8 +66,8 @@ impl ChessBucketsMirrored {
     }
 }
 
-impl SparseInputType for ChessBucketsMirrored {
-    type RequiredDataType = ChessBoard;
+impl Features for ChessBucketsMirrored {
+    type Position = ChessBoard;
 
     /// The total number of inputs
     fn num_inputs(&self) -> usize {
@@ -79,7 +79,7 @@ impl SparseInputType for ChessBucketsMirrored {
         32
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         let get = |ksq| (if ksq % 8 > 3 { 7 } else { 0 }, 768 * self.buckets[usize::from(ksq)]);
         let (stm_flip, stm_bucket) = get(pos.our_ksq());
         let (ntm_flip, ntm_bucket) = get(pos.opp_ksq());
@@ -1,6 +1,6 @@
 use bulletformat::ChessBoard;
 
-use super::{get_num_buckets, Chess768, Factorises, SparseInputType};
+use super::{get_num_buckets, Chess768, Factorises, Features};
 
 #[derive(Clone, Copy, Debug)]
 pub struct ChessBucketsMergedKings {
@@ -14,8 +14,8 @@ impl ChessBucketsMergedKings {
     }
 }
 
-impl SparseInputType for ChessBucketsMergedKings {
-    type RequiredDataType = ChessBoard;
+impl Features for ChessBucketsMergedKings {
+    type Position = ChessBoard;
 
     fn num_inputs(&self) -> usize {
         704 * self.num_buckets
@@ -25,7 +25,7 @@ impl SparseInputType for ChessBucketsMergedKings {
         32
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         let our_bucket = 704 * self.buckets[usize::from(pos.our_ksq())];
         let opp_bucket = 704 * self.buckets[usize::from(pos.opp_ksq())];
 
@@ -66,8 +66,8 @@ impl ChessBucketsMergedKingsMirrored {
     }
 }
 
-impl SparseInputType for ChessBucketsMergedKingsMirrored {
-    type RequiredDataType = ChessBoard;
+impl Features for ChessBucketsMergedKingsMirrored {
+    type Position = ChessBoard;
 
     /// The total number of inputs
     fn num_inputs(&self) -> usize {
@@ -79,7 +79,7 @@ impl SparseInputType for ChessBucketsMergedKingsMirrored {
         32
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         let get_flip = |ksq| if ksq % 8 > 3 { 7 } else { 0 };
         let stm_flip = get_flip(pos.our_ksq());
         let ntm_flip = get_flip(pos.opp_ksq());
@@ -1,25 +1,25 @@
-use super::SparseInputType;
+use super::Features;
 
-pub trait Factorises<T: SparseInputType>: SparseInputType<RequiredDataType = T::RequiredDataType> {
+pub trait Factorises<T: Features>: Features<Position = T::Position> {
     fn derive_feature(&self, input: &T, feat: usize) -> Option<usize>;
 }
 
 #[derive(Clone, Copy, Default)]
-pub struct Factorised<A: SparseInputType, B: Factorises<A>> {
+pub struct Factorised<A: Features, B: Factorises<A>> {
     normal: A,
     factoriser: B,
     offset: usize,
 }
 
-impl<A: SparseInputType, B: Factorises<A>> Factorised<A, B> {
+impl<A: Features, B: Factorises<A>> Factorised<A, B> {
     pub fn from_parts(normal: A, factoriser: B) -> Self {
         let offset = factoriser.num_inputs();
         Self { normal, factoriser, offset }
     }
 }
 
-impl<A: SparseInputType, B: Factorises<A>> SparseInputType for Factorised<A, B> {
-    type RequiredDataType = <A as SparseInputType>::RequiredDataType;
+impl<A: Features, B: Factorises<A>> Features for Factorised<A, B> {
+    type Position = <A as Features>::Position;
 
     fn num_inputs(&self) -> usize {
         self.normal.num_inputs() + self.factoriser.num_inputs()
@@ -29,7 +29,7 @@ impl<A: SparseInputType, B: Factorises<A>> SparseInputType for Factorised<A, B>
         2 * self.normal.max_active()
     }
 
-    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, mut f: F) {
+    fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::Position, mut f: F) {
         self.normal.map_features(pos, |stm, ntm| {
             f(self.offset + stm, self.offset + ntm);
 
@@ -0,0 +1,36 @@
+//! CPU inference for networks trained with bullet's `TrainerBuilder`, without the training
+//! stack or a GPU, for use as a reference implementation and as a test oracle for engines.
+//!
+//! The network is described with an `Architecture`, in the same way as the `TrainerBuilder`
+//! it was trained with, and then loaded from a `raw.bin` or a quantised network:
+//! ```rust,no_run
+//! use bullet_inference::{inputs, outputs, Activation, Architecture, Mode};
+//!
+//! let net = Architecture::default()
+//!     .input(inputs::Chess768)
+//!     .output_buckets(outputs::MaterialCount::<8>)
+//!     .quantisations(&[255, 64])
+//!     .feature_transformer(512)
+//!     .activate(Activation::SCReLU)
+//!     .add_layer(1)
+//!     .load_quantised("checkpoints/net-40/quantised.bin")
+//!     .unwrap();
+//!
+//! let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+//! let eval = net.eval_fen(fen, Mode::Integer).unwrap()[0] * 400.0;
+//! ```
+//!
+//! It also holds the file formats of networks trained with bullet, shared by the trainer,
+//! `bullet-utils` and engines, so that they all read and write them the same way.
+pub mod checkpoint;
+mod hash;
+pub mod inputs;
+pub mod metadata;
+mod network;
+pub mod outputs;
+pub mod quantise;
+mod report;
+
+pub use hash::{fnv1a, Fnv1a};
+pub use network::{Activation, Architecture, IntegerRanges, Mode, Network};
+pub use report::QuantisationReport;
@@ -0,0 +1,208 @@
+use std::{fmt, io};
+
+use crate::fnv1a;
+
+/// Version of the metadata format written by `Metadata::write_trailer`.
+pub const VERSION: u32 = 1;
+
+/// The last bytes of a quantised network with a metadata trailer.
+pub const MAGIC: [u8; 8] = *b"BLTMETA\0";
+
+const FOOTER_SIZE: usize = 16;
+
+/// Describes a quantised network, so that engines and `bullet-utils` can identify it.
+///
+/// The trailer is written after the network and its padding, as UTF-8 `key: value` lines
+/// (padded with newlines to keep the file a multiple of 64 bytes), followed by the length
+/// of the lines as a `u32`, `VERSION` as a `u32`, and `MAGIC`. Unknown keys are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// As printed by `TrainerBuilder::build`, e.g. `(768 -> 1024)x2 -> 1x8`.
+    pub architecture: String,
+    pub inputs: String,
+    pub output_buckets: usize,
+    /// Quantisation factor of each integer tensor, by id.
+    pub quantisations: Vec<(String, i32)>,
+    /// Empty if not saved as part of a checkpoint.
+    pub net_id: String,
+    pub superbatch: usize,
+    /// Number of bytes of weights, before any padding.
+    pub weights_size: usize,
+    /// `fnv1a` of the weights.
+    pub hash: u64,
+}
+
+impl Metadata {
+    /// Appends the trailer to `buf`, which should already be padded to a multiple of 64 bytes.
+    pub fn write_trailer(&self, buf: &mut Vec<u8>) {
+        let mut text = self.to_string().into_bytes();
+
+        let unaligned = (buf.len() + text.len() + FOOTER_SIZE) % 64;
+        if unaligned > 0 {
+            text.resize(text.len() + 64 - unaligned, b'\n');
+        }
+
+        buf.extend_from_slice(&text);
+        buf.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        buf.extend_from_slice(&MAGIC);
+    }
+
+    /// Reads the trailer at the end of `bytes`, if there is one, returning it along with
+    /// the offset at which it starts.
+    pub fn read_trailer(bytes: &[u8]) -> io::Result<Option<(Self, usize)>> {
+        if bytes.len() < FOOTER_SIZE || bytes[bytes.len() - MAGIC.len()..] != MAGIC {
+            return Ok(None);
+        }
+
+        let footer = &bytes[bytes.len() - FOOTER_SIZE..];
+        let len = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]) as usize;
+        let version = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]);
+
+        if version > VERSION {
+            return Err(invalid(format!("Unsupported metadata version {version}!")));
+        }
+
+        let start = (bytes.len() - FOOTER_SIZE).checked_sub(len).ok_or_else(|| invalid("Metadata is truncated!"))?;
+        let text = std::str::from_utf8(&bytes[start..bytes.len() - FOOTER_SIZE])
+            .map_err(|_| invalid("Metadata is not valid UTF-8!"))?;
+
+        let mut metadata = Self::default();
+
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line.split_once(": ").unwrap_or((line.trim_end_matches(':'), ""));
+
+            match key {
+                "architecture" => metadata.architecture = value.to_string(),
+                "inputs" => metadata.inputs = value.to_string(),
+                "output_buckets" => metadata.output_buckets = parse(key, value)?,
+                "quantisations" => {
+                    for quant in value.split_whitespace() {
+                        let (id, q) =
+                            quant.split_once('=').ok_or_else(|| invalid(format!("Invalid quantisation {quant}!")))?;
+                        metadata.quantisations.push((id.to_string(), parse(id, q)?));
+                    }
+                }
+                "net_id" => metadata.net_id = value.to_string(),
+                "superbatch" => metadata.superbatch = parse(key, value)?,
+                "weights_size" => metadata.weights_size = parse(key, value)?,
+                "hash" => {
+                    metadata.hash =
+                        u64::from_str_radix(value, 16).map_err(|_| invalid(format!("Invalid hash {value}!")))?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Some((metadata, start)))
+    }
+
+    /// Checks that `bytes`, the whole file, starts with the weights this describes.
+    pub fn verify(&self, bytes: &[u8]) -> io::Result<()> {
+        let weights = bytes.get(..self.weights_size).ok_or_else(|| invalid("File is smaller than the weights!"))?;
+
+        if fnv1a(weights) != self.hash {
+            return Err(invalid("Hash of the weights does not match the metadata!"));
+        }
+
+        Ok(())
+    }
+}
+
+/// The `key: value` lines of the trailer.
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let one_line = |value: &str| value.replace(['\n', '\r'], " ");
+
+        let quantisations = self.quantisations.iter().map(|(id, q)| format!("{id}={q}")).collect::<Vec<_>>();
+
+        writeln!(f, "architecture: {}", one_line(&self.architecture))?;
+        writeln!(f, "inputs: {}", one_line(&self.inputs))?;
+        writeln!(f, "output_buckets: {}", self.output_buckets)?;
+        writeln!(f, "quantisations: {}", quantisations.join(" "))?;
+        writeln!(f, "net_id: {}", one_line(&self.net_id))?;
+        writeln!(f, "superbatch: {}", self.superbatch)?;
+        writeln!(f, "weights_size: {}", self.weights_size)?;
+        write!(f, "hash: {:016x}", self.hash)
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| invalid(format!("Invalid value for {key}: {value}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            architecture: String::from("(768 -> 32)x2 -> 1"),
+            inputs: String::from("Chess768"),
+            output_buckets: 1,
+            quantisations: vec![(String::from("l0w"), 255), (String::from("l1w"), 64)],
+            net_id: String::from("test-net"),
+            superbatch: 40,
+            weights_size: 100,
+            hash: 0x0123_4567_89ab_cdef,
+        }
+    }
+
+    #[test]
+    fn trailer_round_trip() {
+        let mut buf = vec![7; 128];
+        metadata().write_trailer(&mut buf);
+
+        assert_eq!(buf.len() % 64, 0);
+        assert!(buf.ends_with(&MAGIC));
+
+        let (read, start) = Metadata::read_trailer(&buf).unwrap().unwrap();
+        assert_eq!(read, metadata());
+        assert_eq!(start, 128);
+    }
+
+    #[test]
+    fn trailer_values_are_one_line() {
+        let mut written = metadata();
+        written.architecture = String::from("a\nb\rc");
+
+        let mut buf = Vec::new();
+        written.write_trailer(&mut buf);
+
+        let (read, _) = Metadata::read_trailer(&buf).unwrap().unwrap();
+        assert_eq!(read.architecture, "a b c");
+        assert_eq!(read.net_id, "test-net");
+    }
+
+    #[test]
+    fn read_trailer_errors() {
+        assert!(Metadata::read_trailer(&[0; 64]).unwrap().is_none());
+
+        let mut buf = Vec::new();
+        metadata().write_trailer(&mut buf);
+
+        let mut newer = buf.clone();
+        let version = newer.len() - MAGIC.len() - 4;
+        newer[version..version + 4].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(Metadata::read_trailer(&newer).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let truncated = &buf[buf.len() - FOOTER_SIZE - 8..];
+        assert_eq!(Metadata::read_trailer(truncated).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn verify_checks_hash() {
+        let weights = [1, 2, 3, 4];
+        let mut metadata = metadata();
+        metadata.weights_size = weights.len();
+        metadata.hash = fnv1a(&weights);
+
+        assert!(metadata.verify(&[1, 2, 3, 4, 0, 0]).is_ok());
+        assert!(metadata.verify(&[1, 2, 3, 5, 0, 0]).is_err());
+        assert!(metadata.verify(&[1, 2, 3]).is_err());
+    }
+}
@@ -0,0 +1,827 @@
+use std::{fs, io, path::Path, str::FromStr};
+
+use crate::{
+    inputs::Features,
+    metadata::Metadata,
+    outputs::{OutputBuckets, Single},
+    quantise::{self, QuantTarget},
+};
+
+/// List of supported activation functions.
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activation {
+    Identity = 0,
+    ReLU = 1,
+    CReLU = 2,
+    SCReLU = 3,
+    SqrReLU = 4,
+    Sigmoid = 5,
+}
+
+impl Activation {
+    fn apply(self, x: f32) -> f32 {
+        match self {
+            Self::Identity => x,
+            Self::ReLU => x.max(0.0),
+            Self::CReLU => x.clamp(0.0, 1.0),
+            Self::SCReLU => x.clamp(0.0, 1.0).powi(2),
+            Self::SqrReLU => x.max(0.0).powi(2),
+            Self::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+        }
+    }
+
+    /// Applies the activation to `x`, in which `scale` represents 1.0.
+    fn apply_int(self, x: i64, scale: i64) -> i64 {
+        match self {
+            Self::Identity => x,
+            Self::ReLU => x.max(0),
+            Self::CReLU => x.clamp(0, scale),
+            Self::SCReLU => x.clamp(0, scale).pow(2),
+            Self::SqrReLU => x.max(0).pow(2),
+            Self::Sigmoid => panic!("Sigmoid cannot be evaluated in integer arithmetic!"),
+        }
+    }
+
+    fn squares_scale(self) -> bool {
+        matches!(self, Self::SCReLU | Self::SqrReLU)
+    }
+}
+
+/// Whether to evaluate with the float weights, or as an engine would with the quantised
+/// weights, in integer arithmetic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Float,
+    Integer,
+}
+
+/// The largest values reached by the integers of an evaluation in `Mode::Integer`, to check
+/// that they fit in the types an engine would use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IntegerRanges {
+    /// Largest magnitude reached by a feature transformer accumulator.
+    pub max_accumulator: i64,
+    /// Whether a feature transformer accumulator left the `i16` range.
+    pub accumulator_overflow: bool,
+    /// Whether the sum of a layer after the feature transformer left the `i32` range.
+    pub output_overflow: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Activate(Activation),
+    Affine(usize),
+    PairwiseMul,
+}
+
+/// Describes a network in the same way as `TrainerBuilder`, so that the weights saved by
+/// the trainer can be loaded with `load_raw` or `load_quantised`. Loading fails with
+/// `io::ErrorKind::InvalidInput` if the description is incomplete or not supported.
+#[derive(Clone)]
+pub struct Architecture<F, B = Single> {
+    inputs: Option<F>,
+    buckets: B,
+    ft_size: usize,
+    ops: Vec<Op>,
+    quantisations: Option<Vec<QuantTarget>>,
+    perspective: bool,
+    psqt_subnet: bool,
+    allow_transpose: bool,
+}
+
+impl<F, B: Default> Default for Architecture<F, B> {
+    fn default() -> Self {
+        Self {
+            inputs: None,
+            buckets: B::default(),
+            ft_size: 0,
+            ops: Vec::new(),
+            quantisations: None,
+            perspective: true,
+            psqt_subnet: false,
+            allow_transpose: true,
+        }
+    }
+}
+
+impl<F, B> Architecture<F, B> {
+    fn last_layer_size(&self) -> usize {
+        let mut size = self.ft_size * if self.perspective { 2 } else { 1 };
+
+        for op in &self.ops {
+            match op {
+                Op::Affine(out) => size = *out,
+                Op::PairwiseMul => size /= 2,
+                Op::Activate(_) => {}
+            }
+        }
+
+        size
+    }
+
+    /// Makes the first layer single-perspective.
+    pub fn single_perspective(mut self) -> Self {
+        assert!(self.ops.is_empty(), "You need to set 'single_perspective' before adding any layers!");
+        self.perspective = false;
+        self
+    }
+
+    /// Sets the input featureset.
+    pub fn input(mut self, inputs: F) -> Self {
+        assert!(self.inputs.is_none(), "Cannot set the input features more than once!");
+        self.inputs = Some(inputs);
+        self
+    }
+
+    /// Sets the output buckets.
+    pub fn output_buckets(mut self, buckets: B) -> Self {
+        self.buckets = buckets;
+        self
+    }
+
+    /// Provide a list of quantisations.
+    pub fn quantisations(mut self, quants: &[i16]) -> Self {
+        assert!(self.quantisations.is_none(), "Quantisations already set!");
+        self.quantisations = Some(quants.iter().map(|&q| QuantTarget::I16(q)).collect());
+        self
+    }
+
+    /// Provide a list of quantisations.
+    pub fn advanced_quantisations(mut self, quants: &[QuantTarget]) -> Self {
+        assert!(self.quantisations.is_none(), "Quantisations already set!");
+        self.quantisations = Some(quants.to_vec());
+        self
+    }
+
+    /// Sets the size of the feature-transformer.
+    /// Must be done before all other layers.
+    pub fn feature_transformer(mut self, size: usize) -> Self {
+        assert!(self.ops.is_empty());
+        self.ft_size = size;
+        self
+    }
+
+    pub fn disallow_transpose_in_quantised_network(mut self) -> Self {
+        self.allow_transpose = false;
+        self
+    }
+
+    fn add(mut self, op: Op) -> Self {
+        assert_ne!(self.ft_size, 0, "You must start the network with a feature transformer!");
+        self.ops.push(op);
+        self
+    }
+
+    /// Performs an affine transform with output size `size`.
+    pub fn add_layer(self, size: usize) -> Self {
+        assert!(
+            self.ops.last().is_some_and(|op| !matches!(op, Op::Affine(_))),
+            "Two affine transforms in a row is equivalent to a single affine transform!"
+        );
+        self.add(Op::Affine(size))
+    }
+
+    /// Reduces a layer of size `2N` to one of size `N` by splitting it in half
+    /// and performing the elementwise product of the two halves.
+    pub fn add_pairwise_mul(self) -> Self {
+        assert_eq!(
+            self.last_layer_size() % 2,
+            0,
+            "You can only perform pairwise mul on a layer with an even number of neurons!"
+        );
+        self.add(Op::PairwiseMul)
+    }
+
+    /// Applies the given activation function.
+    pub fn activate(self, activation: Activation) -> Self {
+        self.add(Op::Activate(activation))
+    }
+
+    /// Adds a PSQT subnet directly from inputs to output.
+    pub fn psqt_subnet(mut self) -> Self {
+        self.psqt_subnet = true;
+        self
+    }
+}
+
+impl<F: Features, B: OutputBuckets<F::Position>> Architecture<F, B> {
+    /// Loads a `raw.bin` saved by the trainer, containing the float weights as they are trained,
+    /// including any factoriser, which is merged into the inputs on loading.
+    pub fn load_raw(self, path: impl AsRef<Path>) -> io::Result<Network<F, B>> {
+        self.load(&fs::read(path)?, false)
+    }
+
+    /// Loads a network saved by `Trainer::save_quantised` with the same quantisations,
+    /// which is evaluated with integer arithmetic in `Mode::Integer` if every quantisation
+    /// is `i8` or `i16` (and there is no sigmoid). Any metadata trailer is checked against the weights and skipped.
+    pub fn load_quantised(self, path: impl AsRef<Path>) -> io::Result<Network<F, B>> {
+        self.read_quantised(&fs::read(path)?)
+    }
+
+    /// As `load_quantised`, from the contents of the file.
+    pub fn read_quantised(self, bytes: &[u8]) -> io::Result<Network<F, B>> {
+        self.load(bytes, true)
+    }
+
+    fn load(self, mut bytes: &[u8], quantised: bool) -> io::Result<Network<F, B>> {
+        let unsupported = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+
+        let inputs = self.inputs.ok_or_else(|| unsupported("Need to set the input features!"))?;
+
+        if self.ops.len() <= 1 {
+            return Err(unsupported("Require at least 2 nodes for a working arch!"));
+        }
+
+        if !self.ops.iter().any(|op| matches!(op, Op::Affine(_))) {
+            return Err(unsupported("The network needs a layer after the feature transformer!"));
+        }
+
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        if quantised {
+            if let Some((metadata, start)) = Metadata::read_trailer(bytes)? {
+                metadata.verify(bytes)?;
+                bytes = &bytes[..start];
+            }
+        }
+
+        // the factoriser inputs come first, and are merged into the others in quantised networks
+        let num_inputs = inputs.num_inputs();
+        let merged_inputs = if inputs.is_factorised() {
+            inputs.merge_factoriser(vec![0.0; num_inputs]).len()
+        } else {
+            num_inputs
+        };
+        let stored_inputs = if quantised { merged_inputs } else { num_inputs };
+
+        let merge = |weights: Vec<f32>| {
+            if quantised || !inputs.is_factorised() {
+                weights
+            } else {
+                inputs.merge_factoriser(weights)
+            }
+        };
+
+        // the shape of each affine layer, with a row for each output neuron in each bucket
+        let mut shapes = vec![(self.ft_size, stored_inputs)];
+        let mut prev_size = self.ft_size * if self.perspective { 2 } else { 1 };
+
+        for op in &self.ops {
+            match *op {
+                Op::Affine(size) => {
+                    shapes.push((size * B::BUCKETS, prev_size));
+                    prev_size = size;
+                }
+                Op::PairwiseMul => prev_size /= 2,
+                Op::Activate(_) => {}
+            }
+        }
+
+        let quants = match (&self.quantisations, quantised) {
+            (Some(quants), true) if quants.len() < shapes.len() => {
+                return Err(unsupported("Need a quantisation for each layer!"));
+            }
+            (Some(quants), true) => quants.clone(),
+            _ => vec![QuantTarget::Float; shapes.len()],
+        };
+
+        let transposed = quantised && self.allow_transpose && B::BUCKETS > 1;
+
+        let mut reader = Reader { bytes, offset: 0 };
+
+        let pst = if self.psqt_subnet {
+            let (pst, _) = reader.read("pst", 1, stored_inputs, QuantTarget::Float, false)?;
+            Some(merge(pst))
+        } else {
+            None
+        };
+
+        let mut layers = Vec::new();
+
+        for (k, &(rows, cols)) in shapes.iter().enumerate() {
+            let (mut weights, weight_ints) =
+                reader.read(&format!("l{k}w"), rows, cols, quants[k], transposed && k > 0)?;
+            let bias_quant = quantise::bias_quant(&quants, k)?;
+            let (bias, bias_ints) = reader.read(&format!("l{k}b"), rows, 1, bias_quant, false)?;
+
+            if k == 0 {
+                weights = merge(weights);
+            }
+
+            layers.push(Layer { weights, bias, ints: weight_ints.zip(bias_ints) });
+        }
+
+        // quantised networks are padded to a multiple of 64 bytes
+        let padding = bytes.len() - reader.offset;
+        if padding >= if quantised { 64 } else { 1 } {
+            return Err(invalid(format!("{padding} bytes of unexpected trailing data")));
+        }
+
+        let integer = layers.iter().all(|layer| layer.ints.is_some())
+            && quants.iter().all(|q| matches!(q, QuantTarget::I8(_) | QuantTarget::I16(_)))
+            && !self.ops.contains(&Op::Activate(Activation::Sigmoid));
+
+        let scales = integer.then(|| quants.iter().filter_map(|q| q.factor()).collect());
+
+        Ok(Network {
+            inputs,
+            buckets: self.buckets,
+            perspective: self.perspective,
+            ops: self.ops,
+            pst,
+            layers,
+            scales,
+            factoriser_inputs: num_inputs - merged_inputs,
+            merged_inputs,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    /// Reads a `rows x cols` tensor into column-major order, returning the float values
+    /// and, if quantised to integers, the integer values.
+    fn read(
+        &mut self,
+        id: &str,
+        rows: usize,
+        cols: usize,
+        quant: QuantTarget,
+        transposed: bool,
+    ) -> io::Result<(Vec<f32>, Option<Vec<i64>>)> {
+        let len = rows * cols * quant.size();
+        let chunk = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Ran out of data at [{id}]")))?;
+        self.offset += len;
+
+        let mut floats = quant.dequantise(chunk)?;
+        let mut ints = quant.factor().map(|_| quant.integers(chunk)).transpose()?;
+
+        if transposed {
+            let idx = (0..cols).flat_map(|j| (0..rows).map(move |i| cols * i + j)).collect::<Vec<_>>();
+            floats = idx.iter().map(|&k| floats[k]).collect();
+            ints = ints.map(|ints| idx.iter().map(|&k| ints[k]).collect());
+        }
+
+        Ok((floats, ints))
+    }
+}
+
+/// An affine layer, with a row of weights for each output neuron in each bucket
+/// (or just each neuron for the feature transformer), stored column-major.
+struct Layer {
+    weights: Vec<f32>,
+    bias: Vec<f32>,
+    ints: Option<(Vec<i64>, Vec<i64>)>,
+}
+
+/// A loaded network, see `Architecture`.
+pub struct Network<F, B = Single> {
+    inputs: F,
+    buckets: B,
+    perspective: bool,
+    ops: Vec<Op>,
+    pst: Option<Vec<f32>>,
+    layers: Vec<Layer>,
+    scales: Option<Vec<i64>>,
+    factoriser_inputs: usize,
+    merged_inputs: usize,
+}
+
+impl<F: Features, B: OutputBuckets<F::Position>> Network<F, B> {
+    /// Whether the network can be evaluated in `Mode::Integer`.
+    pub fn supports_integer(&self) -> bool {
+        self.scales.is_some()
+    }
+
+    /// The raw output of the network for `pos`, before any sigmoid, as `Trainer::eval_raw_output`.
+    /// In `Mode::Integer` the output is divided by the output quantisation, but not rounded.
+    pub fn eval(&self, pos: &F::Position, mode: Mode) -> Vec<f32> {
+        match mode {
+            Mode::Float => {
+                let (stm, ntm, bucket) = self.features(pos);
+                self.with_psqt(&stm, self.eval_float(&stm, &ntm, bucket))
+            }
+            Mode::Integer => self.eval_with_ranges(pos).0,
+        }
+    }
+
+    /// As `eval` in `Mode::Integer`, along with the largest values reached by its integers.
+    pub fn eval_with_ranges(&self, pos: &F::Position) -> (Vec<f32>, IntegerRanges) {
+        let (stm, ntm, bucket) = self.features(pos);
+        let mut ranges = IntegerRanges::default();
+        let out = self.eval_integer(&stm, &ntm, bucket, &mut ranges);
+        (self.with_psqt(&stm, out), ranges)
+    }
+
+    /// Evaluates each position as `eval`, split across `threads` threads.
+    pub fn eval_batch(&self, positions: &[F::Position], mode: Mode, threads: usize) -> Vec<Vec<f32>>
+    where
+        F: Sync,
+        B: Sync,
+        F::Position: Sync,
+    {
+        let chunk_size = positions.len().div_ceil(threads.max(1)).max(1);
+
+        std::thread::scope(|s| {
+            let handles = positions
+                .chunks(chunk_size)
+                .map(|chunk| s.spawn(move || chunk.iter().map(|pos| self.eval(pos, mode)).collect::<Vec<_>>()))
+                .collect::<Vec<_>>();
+
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+
+    /// The output bucket of `pos`.
+    pub fn bucket(&self, pos: &F::Position) -> usize {
+        usize::from(self.buckets.bucket(pos))
+    }
+
+    /// Evaluates a FEN (or other position string), as `Trainer::eval_raw_output`.
+    pub fn eval_fen(&self, fen: &str, mode: Mode) -> Result<Vec<f32>, String>
+    where
+        F::Position: FromStr<Err = String>,
+    {
+        let pos = format!("{fen} | 0 | 0.0").parse::<F::Position>()?;
+        Ok(self.eval(&pos, mode))
+    }
+
+    /// The active inputs of each perspective, with any factoriser merged, and the output bucket.
+    fn features(&self, pos: &F::Position) -> (Vec<usize>, Vec<usize>, usize) {
+        let mut stm = Vec::new();
+        let mut ntm = Vec::new();
+
+        self.inputs.map_features(pos, |s, n| {
+            // the factoriser inputs are already merged into the others
+            if s >= self.factoriser_inputs {
+                let (s, n) = (s - self.factoriser_inputs, n - self.factoriser_inputs);
+                assert!(s < self.merged_inputs && n < self.merged_inputs, "Input out of range!");
+                stm.push(s);
+                ntm.push(n);
+            }
+        });
+
+        (stm, ntm, self.bucket(pos))
+    }
+
+    fn with_psqt(&self, stm: &[usize], mut out: Vec<f32>) -> Vec<f32> {
+        if let Some(pst) = &self.pst {
+            let psqt = stm.iter().map(|&feat| pst[feat]).sum::<f32>();
+
+            for x in &mut out {
+                *x += psqt;
+            }
+        }
+
+        out
+    }
+
+    fn eval_float(&self, stm: &[usize], ntm: &[usize], bucket: usize) -> Vec<f32> {
+        let Layer { weights: ft_weights, bias: ft_bias, .. } = &self.layers[0];
+        let size = ft_bias.len();
+
+        let accumulate = |features: &[usize]| {
+            let mut acc = ft_bias.clone();
+
+            for &feat in features {
+                for (a, &w) in acc.iter_mut().zip(&ft_weights[size * feat..size * (feat + 1)]) {
+                    *a += w;
+                }
+            }
+
+            acc
+        };
+
+        // each perspective of the feature transformer is kept separate until the first layer
+        let mut values = vec![accumulate(stm)];
+        if self.perspective {
+            values.push(accumulate(ntm));
+        }
+
+        let mut layer = 1;
+
+        for op in &self.ops {
+            match *op {
+                Op::Activate(activation) => {
+                    values.iter_mut().flatten().for_each(|x| *x = activation.apply(*x));
+                }
+                Op::PairwiseMul => {
+                    for part in &mut values {
+                        let (first, second) = part.split_at(part.len() / 2);
+                        *part = first.iter().zip(second).map(|(a, b)| a * b).collect();
+                    }
+                }
+                Op::Affine(size) => {
+                    let input = values.concat();
+                    let Layer { weights, bias, .. } = &self.layers[layer];
+                    let rows = bias.len();
+
+                    let mut out = bias[bucket * size..(bucket + 1) * size].to_vec();
+
+                    for (j, &x) in input.iter().enumerate() {
+                        let column = &weights[rows * j + bucket * size..rows * j + (bucket + 1) * size];
+
+                        for (o, &w) in out.iter_mut().zip(column) {
+                            *o += w * x;
+                        }
+                    }
+
+                    values = vec![out];
+                    layer += 1;
+                }
+            }
+        }
+
+        values.concat()
+    }
+
+    /// Evaluates as an engine would, with the values of each layer quantised by the product
+    /// of the weight quantisations so far (as for the biases), squared by SCReLU, SqrReLU
+    /// and pairwise mul, and divided back down after each affine layer.
+    fn eval_integer(&self, stm: &[usize], ntm: &[usize], bucket: usize, ranges: &mut IntegerRanges) -> Vec<f32> {
+        let scales = self.scales.as_ref().expect("Integer inference requires i8 or i16 quantisations!");
+        let ints = |layer: usize| self.layers[layer].ints.as_ref().unwrap();
+
+        let (ft_weights, ft_bias) = ints(0);
+        let size = ft_bias.len();
+
+        let mut accumulate = |features: &[usize]| {
+            let mut acc = ft_bias.clone();
+
+            for &feat in features {
+                for (a, &w) in acc.iter_mut().zip(&ft_weights[size * feat..size * (feat + 1)]) {
+                    *a += w;
+                    ranges.accumulator_overflow |= i16::try_from(*a).is_err();
+                    ranges.max_accumulator = ranges.max_accumulator.max(a.abs());
+                }
+            }
+
+            acc
+        };
+
+        let mut values = vec![accumulate(stm)];
+        if self.perspective {
+            values.push(accumulate(ntm));
+        }
+
+        // the quantisation of the biases of the last layer, and of the current values
+        let mut bias_scale = scales[0];
+        let mut scale = scales[0];
+        let mut layer = 1;
+
+        for op in &self.ops {
+            match *op {
+                Op::Activate(activation) => {
+                    values.iter_mut().flatten().for_each(|x| *x = activation.apply_int(*x, scale));
+
+                    if activation.squares_scale() {
+                        scale *= scale;
+                    }
+                }
+                Op::PairwiseMul => {
+                    for part in &mut values {
+                        let (first, second) = part.split_at(part.len() / 2);
+                        *part = first.iter().zip(second).map(|(a, b)| a * b).collect();
+                    }
+
+                    scale *= scale;
+                }
+                Op::Affine(size) => {
+                    let input = values.concat();
+                    let (weights, bias) = ints(layer);
+                    let rows = bias.len();
+
+                    let mut sums = vec![0; size];
+
+                    for (j, &x) in input.iter().enumerate() {
+                        let column = &weights[rows * j + bucket * size..rows * j + (bucket + 1) * size];
+
+                        for (s, &w) in sums.iter_mut().zip(column) {
+                            *s += w * x;
+                            ranges.output_overflow |= i32::try_from(*s).is_err();
+                        }
+                    }
+
+                    let out = sums.iter().zip(&bias[bucket * size..]).map(|(&s, &b)| s / (scale / bias_scale) + b);
+
+                    values = vec![out.collect()];
+                    bias_scale *= scales[layer];
+                    scale = bias_scale;
+                    layer += 1;
+                }
+            }
+        }
+
+        values.concat().iter().map(|&x| x as f32 / scale as f32).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inputs::{Factorised, Factorises};
+
+    use super::*;
+
+    /// Two inputs, where the nstm index of an input is the other input.
+    #[derive(Clone, Copy, Default)]
+    struct TwoInputs;
+
+    impl Features for TwoInputs {
+        type Position = usize;
+
+        fn num_inputs(&self) -> usize {
+            2
+        }
+
+        fn max_active(&self) -> usize {
+            1
+        }
+
+        fn map_features<F: FnMut(usize, usize)>(&self, pos: &usize, mut f: F) {
+            f(*pos, 1 - *pos);
+        }
+
+        fn shorthand(&self) -> String {
+            "2".to_string()
+        }
+
+        fn description(&self) -> String {
+            "Two inputs".to_string()
+        }
+    }
+
+    /// A single input shared by both of `TwoInputs`.
+    #[derive(Clone, Copy, Default)]
+    struct Shared;
+
+    impl Features for Shared {
+        type Position = usize;
+
+        fn num_inputs(&self) -> usize {
+            1
+        }
+
+        fn max_active(&self) -> usize {
+            1
+        }
+
+        fn map_features<F: FnMut(usize, usize)>(&self, _: &usize, mut f: F) {
+            f(0, 0);
+        }
+
+        fn shorthand(&self) -> String {
+            "1".to_string()
+        }
+
+        fn description(&self) -> String {
+            "Shared input".to_string()
+        }
+    }
+
+    impl Factorises<TwoInputs> for Shared {
+        fn derive_feature(&self, _: &TwoInputs, _: usize) -> Option<usize> {
+            Some(0)
+        }
+    }
+
+    fn layers<F>(arch: Architecture<F>) -> Architecture<F> {
+        arch.feature_transformer(2).activate(Activation::CReLU).add_layer(1)
+    }
+
+    fn arch() -> Architecture<TwoInputs> {
+        layers(Architecture::default().input(TwoInputs).quantisations(&[4, 2]))
+    }
+
+    fn i16_bytes(values: &[i16]) -> Vec<u8> {
+        values.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    /// `arch` quantised, with every weight exactly representable:
+    /// - `l0w`, columns [0.25, 0.5] and [0.75, -0.25], by 4
+    /// - `l0b`, [0.25, 0], by 4
+    /// - `l1w`, [0.5, 1, -0.5, 1.5], by 2
+    /// - `l1b`, [0.125], by 8
+    fn quantised_bytes() -> Vec<u8> {
+        [i16_bytes(&[1, 2, 3, -1]), i16_bytes(&[1, 0]), i16_bytes(&[1, 2, -1, 3]), i16_bytes(&[1])].concat()
+    }
+
+    #[test]
+    fn read_transposed() {
+        // 2x3, row-major: rows [1, 2, 3] and [4, 5, 6]
+        let bytes = i16_bytes(&[1, 2, 3, 4, 5, 6]);
+        let mut reader = Reader { bytes: &bytes, offset: 0 };
+
+        let (floats, ints) = reader.read("l1w", 2, 3, QuantTarget::I16(2), true).unwrap();
+        assert_eq!(ints.unwrap(), [1, 4, 2, 5, 3, 6]);
+        assert_eq!(floats, [0.5, 2.0, 1.0, 2.5, 1.5, 3.0]);
+        assert_eq!(reader.offset, bytes.len());
+
+        assert!(reader.read("l1b", 1, 1, QuantTarget::I16(2), false).is_err());
+    }
+
+    #[test]
+    fn float_and_integer_eval_match() {
+        let net = arch().read_quantised(&quantised_bytes()).unwrap();
+        assert!(net.supports_integer());
+
+        // stm accumulator [0.5, 0.5], nstm [1, -0.25], so 0.125 + 0.25 + 0.5 - 0.5 + 0
+        assert_eq!(net.eval(&0, Mode::Float), [0.375]);
+        assert_eq!(net.eval(&0, Mode::Integer), [0.375]);
+
+        // stm accumulator [1, -0.25], nstm [0.5, 0.5], so 0.125 + 0.5 + 0 - 0.25 + 0.75
+        assert_eq!(net.eval(&1, Mode::Float), [1.125]);
+        assert_eq!(net.eval(&1, Mode::Integer), [1.125]);
+
+        let (_, ranges) = net.eval_with_ranges(&1);
+        assert_eq!(ranges, IntegerRanges { max_accumulator: 4, accumulator_overflow: false, output_overflow: false });
+    }
+
+    #[test]
+    fn integer_overflows() {
+        let bytes = [i16_bytes(&[i16::MAX, 0, 0, 0]), i16_bytes(&[1, 0]), i16_bytes(&[1, 2, -1, 3]), i16_bytes(&[1])];
+        let (_, ranges) = arch().read_quantised(&bytes.concat()).unwrap().eval_with_ranges(&0);
+        assert!(ranges.accumulator_overflow && !ranges.output_overflow);
+        assert_eq!(ranges.max_accumulator, 32768);
+
+        // 4 inputs of 255^2 by weights of i16::MAX overflow an i32
+        let arch = Architecture::<_>::default()
+            .input(TwoInputs)
+            .quantisations(&[255, 64])
+            .feature_transformer(2)
+            .activate(Activation::SCReLU)
+            .add_layer(1);
+        let bytes = [i16_bytes(&[255; 4]), i16_bytes(&[0, 0]), i16_bytes(&[i16::MAX; 4]), i16_bytes(&[1])];
+        let (_, ranges) = arch.read_quantised(&bytes.concat()).unwrap().eval_with_ranges(&0);
+        assert!(!ranges.accumulator_overflow && ranges.output_overflow);
+    }
+
+    #[test]
+    fn factoriser_is_merged() {
+        // the factoriser column comes first, so the merged columns are [0.5, 0.25] and [1, -0.5]
+        let l0w = [0.25, 0.5, 0.25, -0.25, 0.75, -1.0];
+        let raw = [f32_bytes(&l0w), f32_bytes(&[0.25, 0.0, 0.5, 1.0, -0.5, 1.5, 0.125])].concat();
+
+        let factorised = Factorised::from_parts(TwoInputs, Shared);
+        let raw = layers(Architecture::default().input(factorised).quantisations(&[4, 2])).load(&raw, false).unwrap();
+
+        let merged = [f32_bytes(&[0.5, 0.25, 1.0, -0.5]), f32_bytes(&[0.25, 0.0, 0.5, 1.0, -0.5, 1.5, 0.125])].concat();
+        let merged = layers(Architecture::default().input(factorised)).read_quantised(&merged).unwrap();
+
+        for pos in [0, 1] {
+            assert_eq!(raw.eval(&pos, Mode::Float), merged.eval(&pos, Mode::Float));
+        }
+
+        assert!(!raw.supports_integer() && !merged.supports_integer());
+    }
+
+    #[test]
+    fn load_skips_padding_and_metadata() {
+        let mut bytes = quantised_bytes();
+        let weights_size = bytes.len();
+        quantise::pad(&mut bytes);
+        assert!(arch().read_quantised(&bytes).is_ok());
+
+        let metadata = Metadata { weights_size, hash: crate::fnv1a(&bytes[..weights_size]), ..Default::default() };
+        metadata.write_trailer(&mut bytes);
+        assert!(arch().read_quantised(&bytes).is_ok());
+
+        let corrupted = [&[2, 0], &bytes[2..]].concat();
+        assert_eq!(arch().read_quantised(&corrupted).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        bytes.truncate(64);
+        bytes.resize(128, b'b');
+        assert_eq!(arch().read_quantised(&bytes).err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        let truncated = &quantised_bytes()[..10];
+        assert_eq!(arch().read_quantised(truncated).err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_rejects_unsupported_architectures() {
+        let inputs = || Architecture::default().input(TwoInputs);
+
+        let unsupported = [
+            layers(Architecture::default().quantisations(&[4, 2])),
+            inputs().quantisations(&[4, 2]).feature_transformer(2).activate(Activation::CReLU),
+            inputs().feature_transformer(2).activate(Activation::ReLU).activate(Activation::CReLU),
+            layers(inputs().quantisations(&[4])),
+            layers(inputs().advanced_quantisations(&[QuantTarget::I16(4), QuantTarget::I32(2)])),
+        ];
+
+        for arch in unsupported {
+            assert_eq!(arch.read_quantised(&[0; 64]).err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use bulletformat::ChessBoard;
 
+/// Chooses the output bucket of a position, matching the output buckets the network was trained with.
 pub trait OutputBuckets<T>: Send + Sync + Copy + Default + 'static {
     const BUCKETS: usize;
 
@@ -0,0 +1,578 @@
+use std::{fmt, io};
+
+use crate::checkpoint::Tensor;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuantTarget {
+    Float,
+    /// This takes an `i16` because it is common to want to use a quantisation
+    /// value of, say, 128 with weights clipped to [-0.99, 0.99]
+    I8(i16),
+    I16(i16),
+    I32(i32),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round toward zero.
+    #[default]
+    Truncate,
+    /// Round to the nearest integer, with ties away from zero.
+    Nearest,
+}
+
+/// How floats are converted to integers by `QuantTarget::quantise_with`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuantOptions {
+    pub rounding: Rounding,
+    /// Clamp values that do not fit in the target type, rather than failing.
+    pub saturate: bool,
+}
+
+/// Values that were clamped when quantising with `QuantOptions::saturate`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Clipping {
+    pub count: usize,
+    /// Largest distance of a quantised value outside the target range.
+    pub max_excess: f64,
+}
+
+impl QuantTarget {
+    pub fn quantise(self, buf: &[f32]) -> io::Result<Vec<u8>> {
+        self.quantise_with(buf, QuantOptions::default()).map(|(quantised, _)| quantised)
+    }
+
+    /// Number of bytes taken by each value.
+    pub fn size(self) -> usize {
+        match self {
+            Self::Float | Self::I32(_) => 4,
+            Self::I16(_) => 2,
+            Self::I8(_) => 1,
+        }
+    }
+
+    /// The quantisation factor, or `None` for floats.
+    pub fn factor(self) -> Option<i64> {
+        match self {
+            Self::Float => None,
+            Self::I8(q) | Self::I16(q) => Some(i64::from(q)),
+            Self::I32(q) => Some(i64::from(q)),
+        }
+    }
+
+    /// Reads back quantised values as the integers an engine would use, without dividing
+    /// by the quantisation factor. Fails for floats.
+    pub fn integers(self, bytes: &[u8]) -> io::Result<Vec<i64>> {
+        if bytes.len() % self.size() != 0 {
+            return Err(invalid("Not a whole number of values!"));
+        }
+
+        let values = bytes.chunks_exact(self.size());
+
+        Ok(match self {
+            Self::Float => return Err(invalid("Floats are not quantised to integers!")),
+            Self::I8(_) => values.map(|x| i64::from(x[0] as i8)).collect(),
+            Self::I16(_) => values.map(|x| i64::from(i16::from_le_bytes([x[0], x[1]]))).collect(),
+            Self::I32(_) => values.map(|x| i64::from(i32::from_le_bytes([x[0], x[1], x[2], x[3]]))).collect(),
+        })
+    }
+
+    /// Inverse of `quantise`, up to the precision lost in quantising.
+    pub fn dequantise(self, bytes: &[u8]) -> io::Result<Vec<f32>> {
+        if bytes.len() % self.size() != 0 {
+            return Err(invalid("Not a whole number of values!"));
+        }
+
+        let values = bytes.chunks_exact(self.size());
+
+        Ok(match self {
+            Self::Float => values.map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]])).collect(),
+            Self::I8(q) => values.map(|x| f32::from(x[0] as i8) / f32::from(q)).collect(),
+            Self::I16(q) => values.map(|x| f32::from(i16::from_le_bytes([x[0], x[1]])) / f32::from(q)).collect(),
+            Self::I32(q) => values.map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]) as f32 / q as f32).collect(),
+        })
+    }
+
+    pub fn quantise_with(self, buf: &[f32], options: QuantOptions) -> io::Result<(Vec<u8>, Clipping)> {
+        let mut quantised = Vec::with_capacity(buf.len() * self.size());
+        let mut clipping = Clipping::default();
+
+        for &float in buf {
+            let mut quant = |q: f64, min: f64, max: f64, ty: &str| {
+                quantise_value(q * f64::from(float), min, max, options, &mut clipping)
+                    .ok_or_else(|| invalid(format!("Failed quantisation from f32 to {ty}!")))
+            };
+
+            match self {
+                Self::Float => quantised.extend_from_slice(&float.to_le_bytes()),
+                Self::I8(q) => {
+                    let x = quant(q.into(), i8::MIN.into(), i8::MAX.into(), "i8")?;
+                    quantised.extend_from_slice(&(x as i8).to_le_bytes());
+                }
+                Self::I16(q) => {
+                    let x = quant(q.into(), i16::MIN.into(), i16::MAX.into(), "i16")?;
+                    quantised.extend_from_slice(&(x as i16).to_le_bytes());
+                }
+                Self::I32(q) => {
+                    let x = quant(q.into(), i32::MIN.into(), i32::MAX.into(), "i32")?;
+                    quantised.extend_from_slice(&(x as i32).to_le_bytes());
+                }
+            }
+        }
+
+        Ok((quantised, clipping))
+    }
+}
+
+/// Rounds `qf` and checks that it lies in `[min, max]`, clamping it if saturating.
+fn quantise_value(qf: f64, min: f64, max: f64, options: QuantOptions, clipping: &mut Clipping) -> Option<f64> {
+    let qf = match options.rounding {
+        Rounding::Truncate => qf.trunc(),
+        Rounding::Nearest => qf.round(),
+    };
+
+    if qf.is_nan() {
+        return None;
+    }
+
+    if (min..=max).contains(&qf) {
+        return Some(qf);
+    }
+
+    if !options.saturate {
+        return None;
+    }
+
+    clipping.count += 1;
+    clipping.max_excess = clipping.max_excess.max(if qf < min { min - qf } else { qf - max });
+
+    Some(qf.clamp(min, max))
+}
+
+/// The quantisation of the biases of `layer` in a network built by `TrainerBuilder` with the
+/// weight quantisations `quants`, which is the product of the quantisations of the layers up
+/// to it, since the last float layer. Fails with `InvalidInput` if this does not fit, or for `i32`.
+pub fn bias_quant(quants: &[QuantTarget], layer: usize) -> io::Result<QuantTarget> {
+    let unsupported = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+
+    let mut net_quant = 1i16;
+
+    for quant in &quants[..=layer] {
+        net_quant = match *quant {
+            QuantTarget::Float => 1,
+            QuantTarget::I16(q) | QuantTarget::I8(q) => {
+                net_quant.checked_mul(q).ok_or_else(|| unsupported("Bias quantisation factor overflowed!"))?
+            }
+            QuantTarget::I32(_) => return Err(unsupported("i32 quant is not implemented for TrainerBuilder!")),
+        };
+    }
+
+    Ok(match quants[layer] {
+        QuantTarget::Float => QuantTarget::Float,
+        QuantTarget::I16(_) => QuantTarget::I16(net_quant),
+        QuantTarget::I8(_) => QuantTarget::I8(net_quant),
+        QuantTarget::I32(_) => unreachable!(),
+    })
+}
+
+/// Pads a quantised network to a multiple of 64 bytes, with the repeating string `bullet`.
+pub fn pad(buf: &mut Vec<u8>) {
+    let bytes = buf.len() % 64;
+    if bytes > 0 {
+        let chs = [b'b', b'u', b'l', b'l', b'e', b't'];
+
+        for i in 0..64 - bytes {
+            buf.push(chs[i % chs.len()]);
+        }
+    }
+}
+
+/// Converts a column-major `rows x cols` matrix to row-major.
+pub fn transpose(rows: usize, cols: usize, values: &[f32]) -> Vec<f32> {
+    assert_eq!(rows * cols, values.len());
+
+    let mut transposed = vec![0.0; values.len()];
+
+    for i in 0..rows {
+        for j in 0..cols {
+            transposed[cols * i + j] = values[rows * j + i];
+        }
+    }
+
+    transposed
+}
+
+/// Ids of tensors that had values clamped when quantising.
+pub type ClippedTensors = Vec<(String, Clipping)>;
+
+/// How a tensor is saved, from a `tensor` line of a `FormatDescription`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TensorFormat {
+    pub id: String,
+    pub quant: QuantTarget,
+    pub transposed: bool,
+    /// Whether the factoriser is merged into the inputs.
+    pub merge: bool,
+}
+
+/// A description of how `Trainer::save_quantised` saves a network, as written by
+/// `Trainer::save_format_description`, with which a checkpoint can be quantised without
+/// the program that trained it. Its text form is one setting or tensor per line:
+/// ```text
+/// rounding nearest
+/// saturate false
+/// factoriser 2 0 1 -1
+/// tensor l0w i16:255 normal merge
+/// tensor l1w i8:64 transposed
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FormatDescription {
+    pub options: QuantOptions,
+    /// The number of factoriser inputs, and the factoriser input of each input, or `-1`.
+    pub factoriser: Option<(usize, Vec<i64>)>,
+    pub tensors: Vec<TensorFormat>,
+}
+
+impl FormatDescription {
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut description = Self::default();
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            match words[..] {
+                ["rounding", "truncate"] => description.options.rounding = Rounding::Truncate,
+                ["rounding", "nearest"] => description.options.rounding = Rounding::Nearest,
+                ["saturate", saturate] => description.options.saturate = parse(saturate)?,
+                ["factoriser", offset, ref map @ ..] => {
+                    let map = map.iter().map(|x| parse(x)).collect::<io::Result<_>>()?;
+                    description.factoriser = Some((parse(offset)?, map));
+                }
+                ["tensor", id, quant, layout, ref rest @ ..] => {
+                    let quant = match quant.split_once(':') {
+                        None if quant == "f32" => QuantTarget::Float,
+                        Some(("i8", q)) => QuantTarget::I8(parse(q)?),
+                        Some(("i16", q)) => QuantTarget::I16(parse(q)?),
+                        Some(("i32", q)) => QuantTarget::I32(parse(q)?),
+                        _ => return Err(invalid(format!("Invalid quantisation {quant}"))),
+                    };
+
+                    let transposed = match layout {
+                        "normal" => false,
+                        "transposed" => true,
+                        _ => return Err(invalid(format!("Invalid layout {layout}"))),
+                    };
+
+                    let merge = match rest {
+                        [] => false,
+                        ["merge"] => true,
+                        _ => return Err(invalid(format!("Unexpected {rest:?} after [{id}]"))),
+                    };
+
+                    description.tensors.push(TensorFormat { id: id.to_string(), quant, transposed, merge });
+                }
+                _ => return Err(invalid(format!("Unexpected line: {line}"))),
+            }
+        }
+
+        Ok(description)
+    }
+
+    /// Transforms and quantises the weights of a checkpoint as `Trainer::save_quantised` would,
+    /// including the padding, returning the bytes and the ids of any tensors that were clipped.
+    pub fn quantise(&self, tensors: &[Tensor]) -> io::Result<(Vec<u8>, ClippedTensors)> {
+        let mut buf = Vec::new();
+        let mut clipped = Vec::new();
+
+        for format in &self.tensors {
+            let tensor = tensors
+                .iter()
+                .find(|t| t.id == format.id)
+                .ok_or_else(|| invalid(format!("No weights [{}] in the checkpoint!", format.id)))?;
+
+            let (rows, mut cols, mut values) = (tensor.rows, tensor.cols, tensor.values.clone());
+
+            if format.merge {
+                let (offset, map) = self.factoriser.as_ref().ok_or_else(|| {
+                    invalid(format!("[{}] merges the factoriser, but there is no factoriser!", format.id))
+                })?;
+
+                values = merge_factoriser(tensor, *offset, map)?;
+                cols = map.len();
+            }
+
+            if format.transposed {
+                values = transpose(rows, cols, &values);
+            }
+
+            let (bytes, clipping) = format
+                .quant
+                .quantise_with(&values, self.options)
+                .map_err(|e| invalid(format!("[{}]: {e}", format.id)))?;
+
+            if clipping.count > 0 {
+                clipped.push((format.id.clone(), clipping));
+            }
+
+            buf.extend_from_slice(&bytes);
+        }
+
+        pad(&mut buf);
+
+        Ok((buf, clipped))
+    }
+}
+
+impl fmt::Display for FormatDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# bullet saved format")?;
+        writeln!(f, "# tensor <id> <f32 | i8:Q | i16:Q | i32:Q> <normal | transposed> [merge]")?;
+
+        let rounding = match self.options.rounding {
+            Rounding::Truncate => "truncate",
+            Rounding::Nearest => "nearest",
+        };
+
+        writeln!(f, "rounding {rounding}")?;
+        writeln!(f, "saturate {}", self.options.saturate)?;
+
+        if let Some((offset, map)) = &self.factoriser {
+            let map = map.iter().map(i64::to_string).collect::<Vec<_>>().join(" ");
+            writeln!(f, "# factoriser <factoriser inputs> <factoriser input of each input, or -1>")?;
+            writeln!(f, "factoriser {offset} {map}")?;
+        }
+
+        for format in &self.tensors {
+            let quant = match format.quant {
+                QuantTarget::Float => String::from("f32"),
+                QuantTarget::I8(q) => format!("i8:{q}"),
+                QuantTarget::I16(q) => format!("i16:{q}"),
+                QuantTarget::I32(q) => format!("i32:{q}"),
+            };
+
+            let layout = if format.transposed { "transposed" } else { "normal" };
+            let merge = if format.merge { " merge" } else { "" };
+            writeln!(f, "tensor {} {quant} {layout}{merge}", format.id)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Each merged input is its own column plus that of its factoriser input, which come first.
+fn merge_factoriser(tensor: &Tensor, offset: usize, map: &[i64]) -> io::Result<Vec<f32>> {
+    if tensor.cols != offset + map.len() {
+        return Err(invalid(format!("[{}] has {} columns, expected {}", tensor.id, tensor.cols, offset + map.len())));
+    }
+
+    let rows = tensor.rows;
+    let mut merged = Vec::with_capacity(rows * map.len());
+
+    for (col, &factoriser) in map.iter().enumerate() {
+        let own = &tensor.values[rows * (col + offset)..rows * (col + offset + 1)];
+
+        match usize::try_from(factoriser) {
+            Ok(factoriser) if factoriser < offset => {
+                let shared = &tensor.values[rows * factoriser..rows * (factoriser + 1)];
+                merged.extend(own.iter().zip(shared).map(|(x, y)| x + y));
+            }
+            Ok(factoriser) => return Err(invalid(format!("Factoriser input {factoriser} out of range"))),
+            Err(_) => merged.extend_from_slice(own),
+        }
+    }
+
+    Ok(merged)
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| invalid(format!("Invalid value {value}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEIGHTS: [f32; 4] = [0.5, -0.5, 1.3, -1.3];
+
+    const DESCRIPTION: &str = "# bullet saved format
+rounding nearest
+saturate true
+factoriser 2 0 1 -1
+tensor l0w i16:255 normal merge
+tensor l1w i8:64 transposed
+tensor l1b f32 normal
+";
+
+    fn quantise(weights: &[f32], rounding: Rounding, saturate: bool) -> io::Result<(Vec<i8>, Clipping)> {
+        let options = QuantOptions { rounding, saturate };
+        let (bytes, clipping) = QuantTarget::I8(100).quantise_with(weights, options)?;
+        Ok((bytes.into_iter().map(|x| x as i8).collect(), clipping))
+    }
+
+    fn tensors() -> Vec<Tensor> {
+        let tensor =
+            |id: &str, rows, cols, values: &[f32]| Tensor { id: id.to_string(), rows, cols, values: values.to_vec() };
+
+        vec![
+            // one row, with factoriser inputs 0 and 1, then inputs 2, 3 and 4
+            tensor("l0w", 1, 5, &[0.5, 0.25, 0.1, 0.2, 0.3]),
+            // 2x3, column-major
+            tensor("l1w", 2, 3, &[0.1, 0.2, 0.3, 0.4, 0.5, 3.0]),
+            tensor("l1b", 2, 1, &[1.5, -1.5]),
+        ]
+    }
+
+    #[test]
+    fn truncate() {
+        let (values, clipping) = quantise(&[0.019, -0.019, 0.5], Rounding::Truncate, false).unwrap();
+        assert_eq!(values, [1, -1, 50]);
+        assert_eq!(clipping.count, 0);
+    }
+
+    #[test]
+    fn nearest() {
+        let (values, clipping) = quantise(&[0.019, -0.019, 0.006, -0.006], Rounding::Nearest, false).unwrap();
+        assert_eq!(values, [2, -2, 1, -1]);
+        assert_eq!(clipping.count, 0);
+    }
+
+    #[test]
+    fn out_of_range_fails_without_saturation() {
+        for rounding in [Rounding::Truncate, Rounding::Nearest] {
+            let err = quantise(&WEIGHTS, rounding, false).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let options = QuantOptions { rounding: Rounding::Nearest, saturate: true };
+        assert!(QuantTarget::I16(1).quantise_with(&[f32::NAN], options).is_err());
+    }
+
+    #[test]
+    fn saturation_clamps() {
+        let (values, clipping) = quantise(&WEIGHTS, Rounding::Nearest, true).unwrap();
+        assert_eq!(values, [50, -50, 127, -128]);
+        assert_eq!(clipping.count, 2);
+        assert!((clipping.max_excess - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dequantise_round_trip() {
+        for quant in [QuantTarget::Float, QuantTarget::I8(64), QuantTarget::I16(256), QuantTarget::I32(256 * 64)] {
+            let values = [0.5, -0.25, 0.75, 0.0];
+            let bytes = quant.quantise(&values).unwrap();
+
+            assert_eq!(bytes.len(), values.len() * quant.size());
+            assert_eq!(quant.dequantise(&bytes).unwrap(), values);
+        }
+
+        assert!(QuantTarget::I16(255).dequantise(&[0; 3]).is_err());
+
+        let bytes = QuantTarget::I16(256).quantise(&[0.5, -0.25]).unwrap();
+        assert_eq!(QuantTarget::I16(256).integers(&bytes).unwrap(), [128, -64]);
+        assert!(QuantTarget::Float.integers(&bytes).is_err());
+    }
+
+    #[test]
+    fn bias_quantisations() {
+        use QuantTarget::*;
+
+        let quants = [I16(255), I8(64), Float, I16(32)];
+
+        assert_eq!(bias_quant(&quants, 0).unwrap(), I16(255));
+        assert_eq!(bias_quant(&quants, 1).unwrap(), I8(255 * 64));
+        assert_eq!(bias_quant(&quants, 2).unwrap(), Float);
+        assert_eq!(bias_quant(&quants, 3).unwrap(), I16(32));
+
+        for result in [bias_quant(&[I16(255), I32(64)], 1), bias_quant(&[I16(255), I16(255)], 1)] {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn pad_and_transpose() {
+        let mut buf = vec![0; 60];
+        pad(&mut buf);
+        assert_eq!(&buf[60..], b"bull");
+
+        pad(&mut buf);
+        assert_eq!(buf.len(), 64);
+
+        assert_eq!(transpose(2, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), [1.0, 3.0, 5.0, 2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn parse_description() {
+        let description = FormatDescription::parse(DESCRIPTION).unwrap();
+
+        assert_eq!(description.options, QuantOptions { rounding: Rounding::Nearest, saturate: true });
+        assert_eq!(description.factoriser, Some((2, vec![0, 1, -1])));
+        assert_eq!(
+            description.tensors,
+            [
+                TensorFormat { id: String::from("l0w"), quant: QuantTarget::I16(255), transposed: false, merge: true },
+                TensorFormat { id: String::from("l1w"), quant: QuantTarget::I8(64), transposed: true, merge: false },
+                TensorFormat { id: String::from("l1b"), quant: QuantTarget::Float, transposed: false, merge: false },
+            ]
+        );
+
+        assert_eq!(FormatDescription::parse(&description.to_string()).unwrap(), description);
+
+        for bad in
+            ["rounding up", "tensor l0w i4:2 normal", "tensor l0w f32 sideways", "tensor l0w f32 normal x", "foo"]
+        {
+            assert!(FormatDescription::parse(bad).is_err());
+        }
+    }
+
+    #[test]
+    fn quantise_checkpoint() {
+        let description = FormatDescription::parse(DESCRIPTION).unwrap();
+        let (bytes, clipped) = description.quantise(&tensors()).unwrap();
+
+        let mut expected = Vec::new();
+
+        // merged: 0.1 + 0.5, 0.2 + 0.25, 0.3
+        for x in [153i16, 115, 77] {
+            expected.extend_from_slice(&x.to_le_bytes());
+        }
+
+        // rows [0.1, 0.3, 0.5] and [0.2, 0.4, 3.0], the last clipped
+        expected.extend_from_slice(&[6, 19, 32, 13, 26, 127]);
+
+        expected.extend_from_slice(&1.5f32.to_le_bytes());
+        expected.extend_from_slice(&(-1.5f32).to_le_bytes());
+
+        assert_eq!(
+            clipped.iter().map(|(id, clipping)| (id.as_str(), clipping.count)).collect::<Vec<_>>(),
+            [("l1w", 1)]
+        );
+        assert_eq!(bytes.len(), 64);
+        assert_eq!(&bytes[..expected.len()], expected);
+        assert_eq!(&bytes[expected.len()..expected.len() + 6], b"bullet");
+    }
+
+    #[test]
+    fn quantise_checkpoint_errors() {
+        let description = FormatDescription::parse(DESCRIPTION).unwrap();
+
+        let mut missing = tensors();
+        missing.pop();
+        assert!(description.quantise(&missing).is_err());
+
+        let mut wrong_cols = tensors();
+        wrong_cols[0].cols = 4;
+        wrong_cols[0].values.pop();
+        assert!(description.quantise(&wrong_cols).is_err());
+
+        let no_factoriser = FormatDescription::parse("tensor l0w f32 normal merge").unwrap();
+        assert!(no_factoriser.quantise(&tensors()).is_err());
+
+        let unsaturated = FormatDescription::parse("tensor l1w i8:64 normal").unwrap();
+        let err = unsaturated.quantise(&tensors()).unwrap_err();
+        assert!(err.to_string().contains("[l1w]"));
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+
+use crate::IntegerRanges;
+
+/// Comparison of float and integer inference over a sample of positions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct QuantisationReport {
+    pub positions: usize,
+    pub mean_abs_diff: f64,
+    pub max_abs_diff: f64,
+    /// Positions in which a feature transformer accumulator left the `i16` range.
+    pub accumulator_overflows: usize,
+    /// Largest magnitude reached by a feature transformer accumulator.
+    pub max_accumulator: i64,
+    /// Positions in which the sum of a layer after the feature transformer left the `i32` range.
+    pub output_overflows: usize,
+}
+
+impl QuantisationReport {
+    /// Records a position, with its float and integer evals (in the same units),
+    /// and the ranges reached by the integer evaluation.
+    pub fn record(&mut self, float: f64, integer: f64, ranges: &IntegerRanges) {
+        let diff = (integer - float).abs();
+
+        self.mean_abs_diff += (diff - self.mean_abs_diff) / (self.positions + 1) as f64;
+        self.max_abs_diff = self.max_abs_diff.max(diff);
+        self.accumulator_overflows += usize::from(ranges.accumulator_overflow);
+        self.max_accumulator = self.max_accumulator.max(ranges.max_accumulator);
+        self.output_overflows += usize::from(ranges.output_overflow);
+        self.positions += 1;
+    }
+}
+
+impl fmt::Display for QuantisationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Positions              : {}", self.positions)?;
+        writeln!(f, "Mean Eval Difference   : {:.3}", self.mean_abs_diff)?;
+        writeln!(f, "Max Eval Difference    : {:.3}", self.max_abs_diff)?;
+        writeln!(f, "Max Accumulator        : {}", self.max_accumulator)?;
+        writeln!(f, "Accumulator Overflows  : {}", self.accumulator_overflows)?;
+        write!(f, "Output Overflows       : {}", self.output_overflows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut report = QuantisationReport::default();
+
+        let overflow = IntegerRanges { max_accumulator: 40_000, accumulator_overflow: true, output_overflow: false };
+        report.record(10.0, 12.0, &IntegerRanges { max_accumulator: 300, ..Default::default() });
+        report.record(-5.0, -1.0, &overflow);
+        report.record(0.0, 0.0, &IntegerRanges::default());
+
+        assert_eq!(report.positions, 3);
+        assert_eq!(report.mean_abs_diff, 2.0);
+        assert_eq!(report.max_abs_diff, 4.0);
+        assert_eq!(report.max_accumulator, 40_000);
+        assert_eq!((report.accumulator_overflows, report.output_overflows), (1, 0));
+    }
+}
@@ -0,0 +1,233 @@
+#include "util.cu"
+#ifdef __HIP_PLATFORM_AMD__
+#include <hip/hip_runtime.h>
+#endif
+
+constexpr float AdafactorEpsilon = 0.00000001F;
+constexpr float FactoredEpsilon = 1e-30F;
+
+__global__ void AdafactorRowVelocityKernel(
+    const size_t rows,
+    const size_t cols,
+    const float beta2,
+    const float adj,
+    const float* gradients,
+    float* rowVelocity)
+{
+    const size_t row = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (row >= rows)
+        return;
+
+    float sum = 0.0F;
+
+    for (size_t col = 0; col < cols; col++) {
+        const float grad = adj * gradients[rows * col + row];
+        sum += grad * grad;
+    }
+
+    rowVelocity[row] = beta2 * rowVelocity[row] + (1.0F - beta2) * (sum / cols + FactoredEpsilon);
+}
+
+__global__ void AdafactorColVelocityKernel(
+    const size_t rows,
+    const size_t cols,
+    const float beta2,
+    const float adj,
+    const float* gradients,
+    float* colVelocity)
+{
+    const size_t col = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (col >= cols)
+        return;
+
+    const float* thisGradients = gradients + rows * col;
+    float sum = 0.0F;
+
+    for (size_t row = 0; row < rows; row++) {
+        const float grad = adj * thisGradients[row];
+        sum += grad * grad;
+    }
+
+    colVelocity[col] = beta2 * colVelocity[col] + (1.0F - beta2) * (sum / rows + FactoredEpsilon);
+}
+
+__device__ float factoredUpdate(
+    const size_t i,
+    const size_t rows,
+    const float grad,
+    const float* rowVelocity,
+    const float* colVelocity,
+    const float rowSum)
+{
+    const float vel = rowVelocity[i % rows] * colVelocity[i / rows] * static_cast<float>(rows) / rowSum;
+    return grad / (sqrtf(vel) + AdafactorEpsilon);
+}
+
+// Reductions are done in two stages rather than with atomics, so that the
+// update is deterministic: each block reduces a fixed strided slice of the
+// input into `partials[blockIdx.x]`, then a single block sums the partials.
+__device__ void blockReduce(float* shared, float sum, float* out)
+{
+    shared[threadIdx.x] = sum;
+    __syncthreads();
+
+    for (size_t stride = blockDim.x / 2; stride > 0; stride /= 2) {
+        if (threadIdx.x < stride)
+            shared[threadIdx.x] += shared[threadIdx.x + stride];
+
+        __syncthreads();
+    }
+
+    if (threadIdx.x == 0)
+        *out = shared[0];
+}
+
+__global__ void AdafactorFinalSumKernel(const size_t numPartials, const float* partials, float* out)
+{
+    __shared__ float shared[threadsPerBlock];
+
+    float sum = 0.0F;
+
+    for (size_t i = threadIdx.x; i < numPartials; i += blockDim.x)
+        sum += partials[i];
+
+    blockReduce(shared, sum, out);
+}
+
+__global__ void AdafactorRowSumKernel(const size_t rows, const float* rowVelocity, float* partials)
+{
+    __shared__ float shared[threadsPerBlock];
+
+    float sum = 0.0F;
+
+    for (size_t row = blockIdx.x * blockDim.x + threadIdx.x; row < rows; row += gridDim.x * blockDim.x)
+        sum += rowVelocity[row];
+
+    blockReduce(shared, sum, partials + blockIdx.x);
+}
+
+__global__ void AdafactorUpdateNormKernel(
+    const size_t rows,
+    const size_t cols,
+    const float adj,
+    const float* rowVelocity,
+    const float* colVelocity,
+    const float* gradients,
+    const float* sums,
+    float* partials)
+{
+    __shared__ float shared[threadsPerBlock];
+
+    float sum = 0.0F;
+
+    for (size_t i = blockIdx.x * blockDim.x + threadIdx.x; i < rows * cols; i += gridDim.x * blockDim.x) {
+        const float update = factoredUpdate(i, rows, adj * gradients[i], rowVelocity, colVelocity, sums[0]);
+        sum += update * update;
+    }
+
+    blockReduce(shared, sum, partials + blockIdx.x);
+}
+
+size_t reductionBlocks(const size_t size, const size_t maxBlocks)
+{
+    const size_t blocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    return max(static_cast<size_t>(1), min(blocks, maxBlocks));
+}
+
+__global__ void AdafactorKernel(
+    const size_t rows,
+    const size_t cols,
+    const float decay,
+    const float beta1,
+    const float clipThreshold,
+    const float minWeight,
+    const float maxWeight,
+    const float adj,
+    const float rate,
+    float* network,
+    float* momentum,
+    const float* rowVelocity,
+    const float* colVelocity,
+    const float* sums,
+    const float* gradients)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= rows * cols)
+        return;
+
+    const float rms = sqrtf(sums[1] / static_cast<float>(rows * cols));
+    const float clip = max(1.0F, rms / clipThreshold);
+
+    float update = factoredUpdate(i, rows, adj * gradients[i], rowVelocity, colVelocity, sums[0]) / clip;
+
+    if (momentum != nullptr) {
+        momentum[i] = beta1 * momentum[i] + (1.0F - beta1) * update;
+        update = momentum[i];
+    }
+
+    float param = network[i];
+    param *= decay;
+    param -= rate * update;
+    param = min(max(param, minWeight), maxWeight);
+
+    network[i] = param;
+}
+
+extern "C" void Adafactor(
+    const size_t rows,
+    const size_t cols,
+    const float decay,
+    const float beta1,
+    const float beta2,
+    const float clipThreshold,
+    const float minWeight,
+    const float maxWeight,
+    const float adj,
+    const float rate,
+    float* network,
+    float* momentum,
+    float* rowVelocity,
+    float* colVelocity,
+    const size_t maxReductionBlocks,
+    float* scratch,
+    const float* gradients)
+{
+    float* sums = scratch;
+    float* partials = scratch + 2;
+
+    const size_t rowBlocks = (rows + threadsPerBlock - 1) / threadsPerBlock;
+    AdafactorRowVelocityKernel<<<rowBlocks, threadsPerBlock>>>(rows, cols, beta2, adj, gradients, rowVelocity);
+
+    const size_t colBlocks = (cols + threadsPerBlock - 1) / threadsPerBlock;
+    AdafactorColVelocityKernel<<<colBlocks, threadsPerBlock>>>(rows, cols, beta2, adj, gradients, colVelocity);
+
+    const size_t sumBlocks = reductionBlocks(rows, maxReductionBlocks);
+    AdafactorRowSumKernel<<<sumBlocks, threadsPerBlock>>>(rows, rowVelocity, partials);
+    AdafactorFinalSumKernel<<<1, threadsPerBlock>>>(sumBlocks, partials, sums);
+
+    const size_t normBlocks = reductionBlocks(rows * cols, maxReductionBlocks);
+    AdafactorUpdateNormKernel<<<normBlocks, threadsPerBlock>>>(rows, cols, adj, rowVelocity, colVelocity, gradients, sums, partials);
+    AdafactorFinalSumKernel<<<1, threadsPerBlock>>>(normBlocks, partials, sums + 1);
+
+    const size_t numBlocks = (rows * cols + threadsPerBlock - 1) / threadsPerBlock;
+    AdafactorKernel<<<numBlocks, threadsPerBlock>>>(
+        rows,
+        cols,
+        decay,
+        beta1,
+        clipThreshold,
+        minWeight,
+        maxWeight,
+        adj,
+        rate,
+        network,
+        momentum,
+        rowVelocity,
+        colVelocity,
+        sums,
+        gradients
+    );
+}
@@ -67,4 +67,110 @@ extern "C" void AdamW(
         velocity,
         gradients
     );
-}
\ No newline at end of file
+}
+
+__global__ void SparseAdamWKernel(
+    const size_t rows,
+    const float cumulativeDecay,
+    const float beta1,
+    const float beta2,
+    const float minWeight,
+    const float maxWeight,
+    const float adj,
+    const float rate,
+    const int32_t* columns,
+    const float* lastDecay,
+    float* network,
+    float* momentum,
+    float* velocity,
+    const float* gradients)
+{
+    const size_t row = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (row >= rows)
+        return;
+
+    const size_t col = static_cast<size_t>(columns[blockIdx.y]);
+    const size_t i = rows * col + row;
+
+    const float grad = adj * gradients[i];
+
+    float param = network[i];
+    param *= expf(cumulativeDecay - lastDecay[col]);
+
+    momentum[i] = beta1 * momentum[i] + (1.0F - beta1) * grad;
+    velocity[i] = beta2 * velocity[i] + (1.0F - beta2) * grad * grad;
+
+    param -= rate * momentum[i] / (sqrt(velocity[i]) + Epsilon);
+    param = min(max(param, minWeight), maxWeight);
+
+    network[i] = param;
+}
+
+__global__ void SetLastDecayKernel(const size_t numColumns, const float cumulativeDecay, const int32_t* columns, float* lastDecay)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= numColumns)
+        return;
+
+    lastDecay[columns[i]] = cumulativeDecay;
+}
+
+__global__ void ApplyLazyDecayKernel(const size_t rows, const size_t cols, const float cumulativeDecay, const float* lastDecay, float* network)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= rows * cols)
+        return;
+
+    network[i] *= expf(cumulativeDecay - lastDecay[i / rows]);
+}
+
+extern "C" void SparseAdamW(
+    const size_t rows,
+    const size_t numColumns,
+    const float cumulativeDecay,
+    const float beta1,
+    const float beta2,
+    const float minWeight,
+    const float maxWeight,
+    const float adj,
+    const float rate,
+    const int32_t* columns,
+    float* lastDecay,
+    float* network,
+    float* momentum,
+    float* velocity,
+    const float* gradients)
+{
+    const size_t threads = min(rows, threadsPerBlock);
+    const size_t chunks = (rows + threads - 1) / threads;
+    dim3 grid(chunks, numColumns);
+
+    SparseAdamWKernel<<<grid, threads>>>(
+        rows,
+        cumulativeDecay,
+        beta1,
+        beta2,
+        minWeight,
+        maxWeight,
+        adj,
+        rate,
+        columns,
+        lastDecay,
+        network,
+        momentum,
+        velocity,
+        gradients
+    );
+
+    const size_t numBlocks = (numColumns + threadsPerBlock - 1) / threadsPerBlock;
+    SetLastDecayKernel<<<numBlocks, threadsPerBlock>>>(numColumns, cumulativeDecay, columns, lastDecay);
+}
+
+extern "C" void ApplyLazyDecay(const size_t rows, const size_t cols, const float cumulativeDecay, const float* lastDecay, float* network)
+{
+    const size_t numBlocks = (rows * cols + threadsPerBlock - 1) / threadsPerBlock;
+    ApplyLazyDecayKernel<<<numBlocks, threadsPerBlock>>>(rows, cols, cumulativeDecay, lastDecay, network);
+}
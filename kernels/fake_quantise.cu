@@ -0,0 +1,37 @@
+#include "util.cu"
+#ifdef __HIP_PLATFORM_AMD__
+#include <hip/hip_runtime.h>
+#endif
+
+__global__ void FakeQuantiseKernel(
+    const size_t size,
+    const float scale,
+    const float minQ,
+    const float maxQ,
+    const bool truncate,
+    const float* inp,
+    float* out)
+{
+    const size_t i = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (i >= size)
+        return;
+
+    const float scaled = inp[i] * scale;
+    const float rounded = truncate ? truncf(scaled) : roundf(scaled);
+
+    out[i] = fminf(fmaxf(rounded, minQ), maxQ) / scale;
+}
+
+extern "C" void FakeQuantise(
+    const size_t size,
+    const float scale,
+    const float minQ,
+    const float maxQ,
+    const bool truncate,
+    const float* inp,
+    float* out)
+{
+    const size_t numBlocks = (size + threadsPerBlock - 1) / threadsPerBlock;
+    FakeQuantiseKernel<<<numBlocks, threadsPerBlock>>>(size, scale, minQ, maxQ, truncate, inp, out);
+}
@@ -1,14 +1,18 @@
 #include "stdint.h"
 #include "util.cu"
 #include "activate.cu"
+#include "adafactor.cu"
 #include "adamw.cu"
+#include "fake_quantise.cu"
 #include "gather.cu"
+#include "max_norm.cu"
 #include "pairwise.cu"
 #include "power_error.cu"
 #include "select.cu"
 #include "softmax/masked.cu"
 #include "softmax/naive.cu"
 #include "sparse/fwd.cu"
+#include "sparse/active.cu"
 #include "sparse/bwd.cu"
 #include "sparse/mask.cu"
 #include "sparse/to_dense.cu"
@@ -0,0 +1,64 @@
+#include "util.cu"
+#ifdef __HIP_PLATFORM_AMD__
+#include <hip/hip_runtime.h>
+#endif
+
+__device__ float normContribution(const float x, const bool l2) { return l2 ? x * x : fabsf(x); }
+
+__device__ float finishNorm(const float sum, const bool l2) { return l2 ? sqrtf(sum) : sum; }
+
+__global__ void MaxNormRowsKernel(const size_t rows, const size_t cols, const bool l2, const float maxNorm, float* weights)
+{
+    const size_t row = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (row >= rows)
+        return;
+
+    float sum = 0.0F;
+
+    for (size_t col = 0; col < cols; col++)
+        sum += normContribution(weights[rows * col + row], l2);
+
+    const float norm = finishNorm(sum, l2);
+
+    if (norm > maxNorm) {
+        const float scale = maxNorm / norm;
+
+        for (size_t col = 0; col < cols; col++)
+            weights[rows * col + row] *= scale;
+    }
+}
+
+__global__ void MaxNormColsKernel(const size_t rows, const size_t cols, const bool l2, const float maxNorm, float* weights)
+{
+    const size_t col = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (col >= cols)
+        return;
+
+    float* thisColumn = weights + rows * col;
+    float sum = 0.0F;
+
+    for (size_t row = 0; row < rows; row++)
+        sum += normContribution(thisColumn[row], l2);
+
+    const float norm = finishNorm(sum, l2);
+
+    if (norm > maxNorm) {
+        const float scale = maxNorm / norm;
+
+        for (size_t row = 0; row < rows; row++)
+            thisColumn[row] *= scale;
+    }
+}
+
+extern "C" void MaxNorm(const size_t rows, const size_t cols, const bool byRows, const bool l2, const float maxNorm, float* weights)
+{
+    const size_t count = byRows ? rows : cols;
+    const size_t numBlocks = (count + threadsPerBlock - 1) / threadsPerBlock;
+
+    if (byRows)
+        MaxNormRowsKernel<<<numBlocks, threadsPerBlock>>>(rows, cols, l2, maxNorm, weights);
+    else
+        MaxNormColsKernel<<<numBlocks, threadsPerBlock>>>(rows, cols, l2, maxNorm, weights);
+}
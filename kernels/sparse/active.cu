@@ -0,0 +1,29 @@
+#include "../util.cu"
+
+__global__ void sparse_mark_active_kernel(const size_t cols, const size_t max_active, const int32_t* inputs, float* marks)
+{
+    const size_t elem = blockIdx.x * blockDim.x + threadIdx.x;
+
+    if (elem >= cols)
+        return;
+
+    const int32_t* thisInput = inputs + max_active * elem;
+
+    for (size_t i = 0; i < max_active; i++) {
+        const int32_t inp = thisInput[i];
+
+        if (inp == -1)
+            break;
+
+        marks[inp] = 1.0F;
+    }
+}
+
+extern "C" void sparse_mark_active(const size_t cols, const size_t max_active, const int32_t* inputs, float* marks)
+{
+    const size_t max_threads = 1024;
+    const size_t threads = min(cols, max_threads);
+    const size_t blocks = (cols + threads - 1) / threads;
+
+    sparse_mark_active_kernel<<<blocks, threads>>>(cols, max_active, inputs, marks);
+}
@@ -1,13 +1,22 @@
+mod onnx;
+
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet},
     fmt::{Debug, Display},
+    fs::File,
+    io::{self, Write},
     ops::Index,
     time::Instant,
 };
 
+pub use onnx::{Attribute, OnnxGraph, OnnxTensor};
+
+use rand::{rngs::StdRng, SeedableRng};
+
 use crate::{
-    nn::{ExecutionContext, Shape},
+    nn::{ExecutionContext, InitSettings, Shape},
+    rng,
     tensor::{util, DenseMatrix, Tensor},
 };
 
@@ -94,9 +103,26 @@ impl GraphBuilder {
         node
     }
 
+    /// The node that `node` refers to if every operation that can be skipped is skipped,
+    /// see `Operation::is_identity`.
+    fn skipped_to(&self, mut node: Node) -> Node {
+        while let Some(operation) = &self[node].parent_operation {
+            if !operation.can_be_identity() {
+                break;
+            }
+
+            node = self[node].parent_nodes[0];
+        }
+
+        node
+    }
+
     pub fn create_result_of_operation(&mut self, operation: impl Operation, inputs: &[Node]) -> Node {
         let mut set = HashSet::new();
-        assert!(inputs.iter().all(|node| set.insert(node)), "An operation will alias nodes on backprop!");
+        assert!(
+            inputs.iter().all(|&node| set.insert(self.skipped_to(node))),
+            "An operation will alias nodes on backprop!"
+        );
 
         let input_shape = inputs.iter().map(|node| self[*node].shape).collect::<Vec<_>>();
 
@@ -145,6 +171,8 @@ pub struct Graph {
     weights: HashMap<String, Node>,
     compiled_graph: OperationQueue,
     execution_context: ExecutionContext,
+    weight_init: HashMap<String, InitSettings>,
+    weights_modified: bool,
 }
 
 impl Display for Graph {
@@ -162,7 +190,16 @@ impl Graph {
         compiled_graph: OperationQueue,
         execution_context: ExecutionContext,
     ) -> Self {
-        Self { nodes, root, inputs, weights, compiled_graph, execution_context }
+        Self {
+            nodes,
+            root,
+            inputs,
+            weights,
+            compiled_graph,
+            execution_context,
+            weight_init: HashMap::new(),
+            weights_modified: false,
+        }
     }
 
     pub fn forward(&mut self) -> f32 {
@@ -184,6 +221,7 @@ impl Graph {
     }
 
     pub fn store_weights(&mut self, weights: &str, data: &Tensor) {
+        self.weights_modified = true;
         self.store_values(self.weights[weights], data);
     }
 
@@ -193,6 +231,56 @@ impl Graph {
         }
     }
 
+    /// Zeroes the gradients of every node except the weights, so that
+    /// weight gradients accumulate over multiple backward passes.
+    pub fn zero_intermediate_grads(&mut self) {
+        let weights = self.weights.values().map(|node| node.0).collect::<HashSet<_>>();
+
+        for (i, node) in self.nodes.iter_mut().enumerate() {
+            if !weights.contains(&i) {
+                node.get_mut().zero_grad();
+            }
+        }
+    }
+
+    /// Sets how the weights `id` are initialised by `initialise_weights`.
+    pub fn set_weight_init(&mut self, id: &str, init: InitSettings) {
+        assert!(self.weights.contains_key(id), "No weights with id [{id}]!");
+        self.weight_init.insert(id.to_string(), init);
+    }
+
+    /// (Re)initialises every weight that has `InitSettings`, drawing from an rng seeded
+    /// with `seed`, or from entropy if it is `None`. Weights are initialised in order of
+    /// id, so the same seed always gives the same weights.
+    pub fn initialise_weights(&mut self, seed: Option<u64>) {
+        let mut seeded = seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+
+        let mut ids = self.weight_init.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+
+        for id in ids {
+            let init = self.weight_init[&id];
+            let weights = self.get_weights_mut(&id);
+            let size = weights.values.shape().size();
+
+            let values = match init {
+                InitSettings::Zeroed => vec![0.0; size],
+                InitSettings::Normal { mean, stdev } => rng::vec_f32_from(&mut seeded, size, mean, stdev, true),
+                InitSettings::Uniform { mean, stdev } => rng::vec_f32_from(&mut seeded, size, mean, stdev, false),
+            };
+
+            weights.load_from_slice(&values);
+        }
+
+        self.weights_modified = false;
+    }
+
+    /// Whether any weights may have been changed (e.g. by loading them from a
+    /// file, or by an optimiser step) since `initialise_weights` was last called.
+    pub fn weights_modified_since_init(&self) -> bool {
+        self.weights_modified
+    }
+
     pub fn input_ids(&self) -> Vec<String> {
         self.inputs.keys().cloned().collect()
     }
@@ -214,11 +302,12 @@ impl Graph {
     }
 
     pub fn get_weights_mut(&mut self, id: &str) -> &mut Tensor {
+        self.weights_modified = true;
         self.nodes[self.weights[id].0].get_mut()
     }
 
     pub fn get_node(&self, node: Node) -> std::cell::Ref<'_, Tensor> {
-        self.nodes[node.0].borrow()
+        self.nodes[self.compiled_graph.resolve(node).0].borrow()
     }
 
     pub fn get_num_params(&self) -> usize {
@@ -231,6 +320,76 @@ impl Graph {
         total
     }
 
+    /// Exports the part of the graph that computes `output` to an ONNX model at `path`,
+    /// with the weights embedded as initialisers. Every input, including sparse ones, becomes
+    /// a dense float input of shape `[N, size]`, so sparse inputs should be given one-hot.
+    /// Fails, naming the node, if an operation has no ONNX equivalent.
+    pub fn export_onnx(&self, output: Node, path: &str) -> io::Result<()> {
+        let producers = self.compiled_graph.queue.iter().map(|op| (op.output, op)).collect::<HashMap<_, _>>();
+
+        let mut needed = HashSet::new();
+        let mut stack = vec![output];
+
+        while let Some(node) = stack.pop() {
+            if needed.insert(node) {
+                if let Some(op) = producers.get(&node) {
+                    stack.extend(&op.inputs);
+                }
+            }
+        }
+
+        let mut onnx = OnnxGraph::default();
+        let mut tensors = HashMap::new();
+
+        let mut inputs = self.inputs.iter().filter(|(_, node)| needed.contains(node)).collect::<Vec<_>>();
+        inputs.sort_by_key(|(id, _)| *id);
+
+        for (id, &node) in inputs {
+            let shape = Shape::new(self.get_node(node).values.shape().rows(), 1);
+            let tensor = OnnxTensor { name: id.clone(), shape, batched: true };
+            onnx.input(id, &tensor.dims());
+            tensors.insert(node, tensor);
+        }
+
+        let mut weights = self.weights.iter().filter(|(_, node)| needed.contains(node)).collect::<Vec<_>>();
+        weights.sort_by_key(|(id, _)| *id);
+
+        for (id, &node) in weights {
+            let values = self.get_node(node);
+            let values = values.values.dense();
+
+            let mut buf = vec![0.0; values.shape().size()];
+            values.write_to_slice(&mut buf);
+
+            let tensor = OnnxTensor { name: id.clone(), shape: values.shape(), batched: false };
+            onnx.initialiser(id, &tensor.dims(), &buf);
+            tensors.insert(node, tensor);
+        }
+
+        for op in self.compiled_graph.queue.iter().filter(|op| needed.contains(&op.output)) {
+            let inputs = op.inputs.iter().map(|node| tensors[node].clone()).collect::<Vec<_>>();
+            let shapes = inputs.iter().map(|tensor| tensor.shape).collect::<Vec<_>>();
+
+            let unsupported = |e: String| {
+                let msg = format!("Cannot export node {} ({}) to ONNX: {e}", op.output.0, op.operation.name());
+                io::Error::new(io::ErrorKind::Unsupported, msg)
+            };
+
+            let shape = op.operation.output_tensor(&shapes).map_err(unsupported)?;
+            let batched = inputs.iter().any(|tensor| tensor.batched) && !op.operation.reduces_batch();
+            let tensor = OnnxTensor { name: format!("node{}", op.output.0), shape, batched };
+
+            op.operation.to_onnx(&mut onnx, &inputs, &tensor).map_err(unsupported)?;
+            tensors.insert(op.output, tensor);
+        }
+
+        let output = &tensors[&output];
+        onnx.node("Identity", &[&output.name], &["output"], Vec::new());
+        onnx.output("output", &output.dims());
+
+        File::create(path)?.write_all(&onnx.to_model_bytes())
+    }
+
     pub fn profile_all_operations(&mut self) {
         self.compiled_graph.profile_all_operations();
     }
@@ -276,6 +435,31 @@ pub trait Operation: Debug + 'static {
     fn name(&self) -> String {
         format!("{:?}", self)
     }
+
+    /// Whether the operation currently passes its single input through unchanged (e.g. a
+    /// disabled `FakeQuantise`), in which case it is skipped on both passes and anything
+    /// that uses its output reads (and backpropagates into) its input directly.
+    fn is_identity(&self) -> bool {
+        false
+    }
+
+    /// Whether `is_identity` can ever be true. This is checked when building the graph,
+    /// so that skipping the operation cannot alias the inputs of another operation.
+    fn can_be_identity(&self) -> bool {
+        false
+    }
+
+    /// Whether the output is summed over the batch (e.g. a loss), so there is a single
+    /// output rather than one per position.
+    fn reduces_batch(&self) -> bool {
+        false
+    }
+
+    /// Adds the equivalent ONNX nodes to `onnx`, computing `output` from `inputs`.
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        let _ = (onnx, inputs, output);
+        Err(String::from("no ONNX equivalent"))
+    }
 }
 
 pub struct OperationPayload {
@@ -283,16 +467,21 @@ pub struct OperationPayload {
     inputs: Vec<Node>,
     output: Node,
     time_spent: Option<(u128, u128, u64, u64)>,
+    skipped: bool,
 }
 
 #[derive(Default)]
 pub struct OperationQueue {
     queue: Vec<OperationPayload>,
+    /// The node whose values each node actually refers to, after skipping identity operations
+    /// in the last forward pass.
+    aliases: Vec<usize>,
 }
 
 impl OperationQueue {
     pub fn push(&mut self, operation: Box<dyn Operation>, inputs: &[Node], output: Node) {
-        self.queue.push(OperationPayload { operation, inputs: inputs.to_vec(), output, time_spent: None });
+        let payload = OperationPayload { operation, inputs: inputs.to_vec(), output, time_spent: None, skipped: false };
+        self.queue.push(payload);
     }
 
     pub fn profile_all_operations(&mut self) {
@@ -315,14 +504,31 @@ impl OperationQueue {
         }
     }
 
+    /// The node whose values `node` refers to, see `Operation::is_identity`.
+    pub fn resolve(&self, node: Node) -> Node {
+        Node(self.aliases.get(node.0).copied().unwrap_or(node.0))
+    }
+
     pub fn execute_fwd(&mut self, ctx: &mut ExecutionContext, graph: &mut [RefCell<Tensor>]) {
-        for OperationPayload { operation, inputs, output, time_spent } in &mut self.queue {
+        let Self { queue, aliases } = self;
+
+        aliases.clear();
+        aliases.extend(0..graph.len());
+
+        for OperationPayload { operation, inputs, output, time_spent, skipped } in queue {
+            *skipped = operation.is_identity();
+
+            if *skipped {
+                aliases[output.0] = aliases[inputs[0].0];
+                continue;
+            }
+
             if time_spent.is_some() {
                 util::device_synchronise();
             }
             let t = Instant::now();
 
-            let inputs = inputs.iter().map(|node| graph[node.0].borrow()).collect::<Vec<_>>();
+            let inputs = inputs.iter().map(|node| graph[aliases[node.0]].borrow()).collect::<Vec<_>>();
 
             let inputs = inputs.iter().map(|ref_cell| &**ref_cell).collect::<Vec<_>>();
 
@@ -339,13 +545,19 @@ impl OperationQueue {
     }
 
     pub fn execute_bwd(&mut self, ctx: &mut ExecutionContext, graph: &mut [RefCell<Tensor>]) {
-        for OperationPayload { operation, inputs, output, time_spent } in self.queue.iter_mut().rev() {
+        let Self { queue, aliases } = self;
+
+        for OperationPayload { operation, inputs, output, time_spent, skipped } in queue.iter_mut().rev() {
+            if *skipped {
+                continue;
+            }
+
             if time_spent.is_some() {
                 util::device_synchronise();
             }
             let t = Instant::now();
 
-            let mut inputs = inputs.iter().map(|node| graph[node.0].borrow_mut()).collect::<Vec<_>>();
+            let mut inputs = inputs.iter().map(|node| graph[aliases[node.0]].borrow_mut()).collect::<Vec<_>>();
 
             let mut inputs = inputs.iter_mut().map(|ref_cell| &mut **ref_cell).collect::<Vec<_>>();
 
@@ -0,0 +1,434 @@
+use crate::{Activation, Shape};
+
+const IR_VERSION: i64 = 8;
+const OPSET_VERSION: i64 = 17;
+
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+
+/// A value in the exported graph.
+///
+/// Values computed from the inputs are batched, with dims `[N, rows]`. Values computed only
+/// from weights are not, and have dims `[cols, rows]`, as bullet stores matrices column-major.
+#[derive(Clone, Debug)]
+pub struct OnnxTensor {
+    pub name: String,
+    pub shape: Shape,
+    pub batched: bool,
+}
+
+impl OnnxTensor {
+    pub fn dims(&self) -> [i64; 2] {
+        if self.batched {
+            [-1, self.shape.size() as i64]
+        } else {
+            [self.shape.cols() as i64, self.shape.rows() as i64]
+        }
+    }
+}
+
+pub enum Attribute {
+    Int(&'static str, i64),
+    Ints(&'static str, Vec<i64>),
+    Float(&'static str, f32),
+}
+
+/// The nodes, initialisers, inputs and outputs of an ONNX graph being built, encoded
+/// directly as protobuf, with only the fields needed by `Graph::export_onnx`.
+#[derive(Default)]
+pub struct OnnxGraph {
+    nodes: Vec<Vec<u8>>,
+    initialisers: Vec<Vec<u8>>,
+    inputs: Vec<Vec<u8>>,
+    outputs: Vec<Vec<u8>>,
+    temps: usize,
+}
+
+impl OnnxGraph {
+    /// A new name for an intermediate value.
+    pub fn temp(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    pub fn node(&mut self, op_type: &str, inputs: &[&str], outputs: &[&str], attributes: Vec<Attribute>) {
+        let mut node = Vec::new();
+
+        for input in inputs {
+            write_string(&mut node, 1, input);
+        }
+
+        for output in outputs {
+            write_string(&mut node, 2, output);
+        }
+
+        write_string(&mut node, 3, &format!("{op_type}_{}", self.nodes.len()));
+        write_string(&mut node, 4, op_type);
+
+        for attribute in attributes {
+            let mut attr = Vec::new();
+
+            match attribute {
+                Attribute::Int(name, x) => {
+                    write_string(&mut attr, 1, name);
+                    write_varint_field(&mut attr, 3, x);
+                    write_varint_field(&mut attr, 20, 2);
+                }
+                Attribute::Ints(name, xs) => {
+                    write_string(&mut attr, 1, name);
+                    xs.iter().for_each(|&x| write_varint_field(&mut attr, 8, x));
+                    write_varint_field(&mut attr, 20, 7);
+                }
+                Attribute::Float(name, x) => {
+                    write_string(&mut attr, 1, name);
+                    write_key(&mut attr, 2, 5);
+                    attr.extend_from_slice(&x.to_le_bytes());
+                    write_varint_field(&mut attr, 20, 1);
+                }
+            }
+
+            write_bytes(&mut node, 5, &attr);
+        }
+
+        self.nodes.push(node);
+    }
+
+    /// Adds a node with a single output, named by `temp`, and returns its name.
+    pub fn op(&mut self, op_type: &str, inputs: &[&str], attributes: Vec<Attribute>) -> String {
+        let output = self.temp();
+        self.node(op_type, inputs, &[&output], attributes);
+        output
+    }
+
+    pub fn initialiser(&mut self, name: &str, dims: &[i64], values: &[f32]) {
+        let raw = values.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+        self.push_initialiser(name, dims, FLOAT, &raw);
+    }
+
+    /// A constant `f32` scalar.
+    pub fn scalar(&mut self, value: f32) -> String {
+        let name = self.temp();
+        self.initialiser(&name, &[], &[value]);
+        name
+    }
+
+    /// A constant `i64` vector, e.g. for the axes of `Slice`.
+    pub fn ints(&mut self, values: &[i64]) -> String {
+        let name = self.temp();
+        let raw = values.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+        self.push_initialiser(&name, &[values.len() as i64], INT64, &raw);
+        name
+    }
+
+    /// Reshapes to `dims`, where `-1` is inferred, as the batch size usually is.
+    pub fn reshape(&mut self, input: &str, dims: &[i64]) -> String {
+        let shape = self.ints(dims);
+        self.op("Reshape", &[input, &shape], Vec::new())
+    }
+
+    pub fn activate(&mut self, input: &str, activation: Activation, output: &str) {
+        let clip = |onnx: &mut Self| {
+            let (zero, one) = (onnx.scalar(0.0), onnx.scalar(1.0));
+            onnx.op("Clip", &[input, &zero, &one], Vec::new())
+        };
+
+        match activation {
+            Activation::Identity => self.node("Identity", &[input], &[output], Vec::new()),
+            Activation::ReLU => self.node("Relu", &[input], &[output], Vec::new()),
+            Activation::CReLU => {
+                let clipped = clip(self);
+                self.node("Identity", &[&clipped], &[output], Vec::new());
+            }
+            Activation::SCReLU => {
+                let clipped = clip(self);
+                self.node("Mul", &[&clipped, &clipped], &[output], Vec::new());
+            }
+            Activation::SqrReLU => {
+                let relu = self.op("Relu", &[input], Vec::new());
+                self.node("Mul", &[&relu, &relu], &[output], Vec::new());
+            }
+            Activation::Sigmoid => self.node("Sigmoid", &[input], &[output], Vec::new()),
+        }
+    }
+
+    pub fn input(&mut self, name: &str, dims: &[i64]) {
+        let info = value_info(name, dims);
+        self.inputs.push(info);
+    }
+
+    pub fn output(&mut self, name: &str, dims: &[i64]) {
+        let info = value_info(name, dims);
+        self.outputs.push(info);
+    }
+
+    fn push_initialiser(&mut self, name: &str, dims: &[i64], data_type: i64, raw: &[u8]) {
+        let mut tensor = Vec::new();
+        dims.iter().for_each(|&dim| write_varint_field(&mut tensor, 1, dim));
+        write_varint_field(&mut tensor, 2, data_type);
+        write_string(&mut tensor, 8, name);
+        write_bytes(&mut tensor, 9, raw);
+        self.initialisers.push(tensor);
+    }
+
+    /// Encodes the model containing this graph.
+    pub fn to_model_bytes(&self) -> Vec<u8> {
+        let mut graph = Vec::new();
+        self.nodes.iter().for_each(|node| write_bytes(&mut graph, 1, node));
+        write_string(&mut graph, 2, "bullet");
+        self.initialisers.iter().for_each(|tensor| write_bytes(&mut graph, 5, tensor));
+        self.inputs.iter().for_each(|info| write_bytes(&mut graph, 11, info));
+        self.outputs.iter().for_each(|info| write_bytes(&mut graph, 12, info));
+
+        let mut opset = Vec::new();
+        write_string(&mut opset, 1, "");
+        write_varint_field(&mut opset, 2, OPSET_VERSION);
+
+        let mut model = Vec::new();
+        write_varint_field(&mut model, 1, IR_VERSION);
+        write_string(&mut model, 2, "bullet");
+        write_bytes(&mut model, 7, &graph);
+        write_bytes(&mut model, 8, &opset);
+
+        model
+    }
+}
+
+/// A float tensor with the given dims, where `-1` is the (symbolic) batch size.
+fn value_info(name: &str, dims: &[i64]) -> Vec<u8> {
+    let mut shape = Vec::new();
+
+    for &dim in dims {
+        let mut dimension = Vec::new();
+
+        if dim < 0 {
+            write_string(&mut dimension, 2, "N");
+        } else {
+            write_varint_field(&mut dimension, 1, dim);
+        }
+
+        write_bytes(&mut shape, 1, &dimension);
+    }
+
+    let mut tensor_type = Vec::new();
+    write_varint_field(&mut tensor_type, 1, FLOAT);
+    write_bytes(&mut tensor_type, 2, &shape);
+
+    let mut type_proto = Vec::new();
+    write_bytes(&mut type_proto, 1, &tensor_type);
+
+    let mut info = Vec::new();
+    write_string(&mut info, 1, name);
+    write_bytes(&mut info, 2, &type_proto);
+
+    info
+}
+
+fn write_varint(buf: &mut Vec<u8>, value: u64) {
+    let mut value = value;
+
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+
+    buf.push(value as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, field: u64, wire_type: u64) {
+    write_varint(buf, (field << 3) | wire_type);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, field: u64, value: i64) {
+    write_key(buf, field, 0);
+    write_varint(buf, value as u64);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_key(buf, field, 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_string(buf: &mut Vec<u8>, field: u64, string: &str) {
+    write_bytes(buf, field, string.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{nn::NetworkBuilder, ExecutionContext};
+
+    #[test]
+    fn one_node_model_bytes() {
+        let mut onnx = OnnxGraph::default();
+        onnx.input("x", &[-1, 2]);
+        onnx.node("Relu", &["x"], &["y"], Vec::new());
+        onnx.output("y", &[-1, 2]);
+
+        let value_info = |name: u8| {
+            let mut info = vec![0x0A, 0x01, name];
+            info.extend_from_slice(&[0x12, 0x0F, 0x0A, 0x0D, 0x08, 0x01, 0x12, 0x09]);
+            info.extend_from_slice(&[0x0A, 0x03, 0x12, 0x01, b'N', 0x0A, 0x02, 0x08, 0x02]);
+            info
+        };
+
+        let mut graph = vec![0x0A, 0x14, 0x0A, 0x01, b'x', 0x12, 0x01, b'y', 0x1A, 0x06];
+        graph.extend_from_slice(b"Relu_0");
+        graph.extend_from_slice(&[0x22, 0x04]);
+        graph.extend_from_slice(b"Relu");
+        graph.extend_from_slice(&[0x12, 0x06]);
+        graph.extend_from_slice(b"bullet");
+        graph.extend_from_slice(&[0x5A, 0x14]);
+        graph.extend_from_slice(&value_info(b'x'));
+        graph.extend_from_slice(&[0x62, 0x14]);
+        graph.extend_from_slice(&value_info(b'y'));
+
+        let mut expected = vec![0x08, 0x08, 0x12, 0x06];
+        expected.extend_from_slice(b"bullet");
+        expected.extend_from_slice(&[0x3A, graph.len() as u8]);
+        expected.extend_from_slice(&graph);
+        expected.extend_from_slice(&[0x42, 0x04, 0x0A, 0x00, 0x10, 0x11]);
+
+        assert_eq!(onnx.to_model_bytes(), expected);
+    }
+
+    #[test]
+    fn varints() {
+        let encode = |x: i64| {
+            let mut buf = Vec::new();
+            write_varint_field(&mut buf, 1, x);
+            buf
+        };
+
+        assert_eq!(encode(0), [0x08, 0x00]);
+        assert_eq!(encode(300), [0x08, 0xAC, 0x02]);
+        assert_eq!(encode(-1), [0x08, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+    }
+
+    enum Field {
+        Varint(i64),
+        Bytes(Vec<u8>),
+    }
+
+    fn read_varint(buf: &mut &[u8]) -> u64 {
+        let mut value = 0;
+
+        for shift in (0..).step_by(7) {
+            let byte = buf[0];
+            *buf = &buf[1..];
+            value |= u64::from(byte & 0x7F) << shift;
+
+            if byte < 0x80 {
+                break;
+            }
+        }
+
+        value
+    }
+
+    /// Decodes the top level fields of a protobuf message.
+    fn fields(mut buf: &[u8]) -> Vec<(u64, Field)> {
+        let mut fields = Vec::new();
+
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf);
+
+            let field = match key & 7 {
+                0 => Field::Varint(read_varint(&mut buf) as i64),
+                2 => {
+                    let len = read_varint(&mut buf) as usize;
+                    let (bytes, rest) = buf.split_at(len);
+                    buf = rest;
+                    Field::Bytes(bytes.to_vec())
+                }
+                5 => {
+                    let (bytes, rest) = buf.split_at(4);
+                    buf = rest;
+                    Field::Bytes(bytes.to_vec())
+                }
+                wire_type => panic!("Unexpected wire type {wire_type}!"),
+            };
+
+            fields.push((key >> 3, field));
+        }
+
+        fields
+    }
+
+    fn bytes(buf: &[u8], field: u64) -> Vec<Vec<u8>> {
+        let matching = fields(buf).into_iter().filter(|(number, _)| *number == field);
+        matching.map(|(_, value)| if let Field::Bytes(bytes) = value { bytes } else { panic!("Not bytes!") }).collect()
+    }
+
+    fn varint_fields(buf: &[u8], field: u64) -> Vec<i64> {
+        let matching = fields(buf).into_iter().filter(|(number, _)| *number == field);
+        matching.map(|(_, value)| if let Field::Varint(x) = value { x } else { panic!("Not a varint!") }).collect()
+    }
+
+    fn string(buf: &[u8], field: u64) -> String {
+        String::from_utf8(bytes(buf, field).remove(0)).unwrap()
+    }
+
+    /// The names and dims (with `-1` for the batch size) of the inputs or outputs of `graph`.
+    fn value_infos(graph: &[u8], field: u64) -> Vec<(String, Vec<i64>)> {
+        let info = |info: Vec<u8>| {
+            let tensor_type = &bytes(&bytes(&info, 2)[0], 1)[0];
+            let dims = bytes(&bytes(tensor_type, 2)[0], 1);
+            let dims = dims.iter().map(|dim| varint_fields(dim, 1).first().copied().unwrap_or(-1)).collect();
+            (string(&info, 1), dims)
+        };
+
+        bytes(graph, field).into_iter().map(info).collect()
+    }
+
+    #[test]
+    fn export_graph() {
+        let builder = NetworkBuilder::default();
+
+        let x = builder.new_input("x", Shape::new(3, 1));
+        let targets = builder.new_input("targets", Shape::new(2, 1));
+        let l0 = builder.new_affine("l0", 3, 2);
+
+        let out = l0.forward(x).activate(Activation::ReLU);
+        let output_node = out.node();
+        let loss_node = out.softmax_crossentropy_loss(targets).node();
+
+        let mut graph = builder.build(ExecutionContext::default());
+
+        let l0w = [0.1, -0.2, 0.3, 0.4, -0.5, 0.6];
+        graph.get_weights_mut("l0w").load_from_slice(&l0w);
+        graph.get_weights_mut("l0b").load_from_slice(&[0.7, -0.8]);
+
+        let export = |node| {
+            let path = std::env::temp_dir().join(format!("bullet-onnx-{}.onnx", std::process::id()));
+            let path = path.to_str().unwrap();
+            graph.export_onnx(node, path).unwrap();
+
+            let model = std::fs::read(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            bytes(&model, 7).remove(0)
+        };
+
+        let graph_bytes = export(output_node);
+        assert_eq!(value_infos(&graph_bytes, 11), [(String::from("x"), vec![-1, 3])]);
+        assert_eq!(value_infos(&graph_bytes, 12), [(String::from("output"), vec![-1, 2])]);
+
+        // constants used by the nodes are named like intermediate values
+        let mut initialisers = bytes(&graph_bytes, 5)
+            .into_iter()
+            .map(|tensor| (string(&tensor, 8), varint_fields(&tensor, 1), bytes(&tensor, 9).remove(0)))
+            .filter(|(name, _, _)| !name.starts_with('t'))
+            .collect::<Vec<_>>();
+        initialisers.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let raw = |values: &[f32]| values.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+        assert_eq!(initialisers.len(), 2);
+        assert_eq!(initialisers[0], (String::from("l0b"), vec![1, 2], raw(&[0.7, -0.8])));
+        assert_eq!(initialisers[1], (String::from("l0w"), vec![3, 2], raw(&l0w)));
+
+        let graph_bytes = export(loss_node);
+        let inputs = value_infos(&graph_bytes, 11);
+        assert_eq!(inputs, [(String::from("targets"), vec![-1, 2]), (String::from("x"), vec![-1, 3])]);
+        assert_eq!(value_infos(&graph_bytes, 12), [(String::from("output"), vec![1, 1])]);
+    }
+}
@@ -31,6 +31,10 @@ impl NetworkBuilder {
         self.init_data.try_lock().unwrap()
     }
 
+    pub(crate) fn node(&self, node: Node) -> NetworkBuilderNode<'_> {
+        NetworkBuilderNode { node, builder: self }
+    }
+
     pub fn new_input<'a>(&'a self, id: &str, shape: Shape) -> NetworkBuilderNode<'a> {
         let node = self.builder().create_input(id, shape);
         NetworkBuilderNode { node, builder: self }
@@ -60,14 +64,12 @@ impl NetworkBuilder {
         let builder = self.graph_builder.into_inner().unwrap();
         let mut graph = builder.build(execution_context);
 
-        for (id, init_data) in self.init_data.lock().unwrap().iter() {
-            match *init_data {
-                InitSettings::Zeroed => {}
-                InitSettings::Normal { mean, stdev } => graph.get_weights_mut(id).seed_random(mean, stdev, true),
-                InitSettings::Uniform { mean, stdev } => graph.get_weights_mut(id).seed_random(mean, stdev, false),
-            };
+        for (id, &init_data) in self.init_data.lock().unwrap().iter() {
+            graph.set_weight_init(id, init_data);
         }
 
+        graph.initialise_weights(None);
+
         graph
     }
 }
@@ -155,6 +157,13 @@ impl NetworkBuilderNode<'_> {
         self.builder.apply(operations::SparseSoftmaxCrossEntropyLoss, &[mask.node, self.node, targets.node])
     }
 
+    /// Rounds to the nearest multiple of `1 / scale` representable as a signed `bits`-bit
+    /// integer, with a straight-through gradient. See `TrainerBuilder::quantisation_aware_from`.
+    pub fn fake_quantise(self, scale: f32, bits: u32) -> Self {
+        let op = operations::FakeQuantise { scale, bits, truncate: false, switch: None };
+        self.builder.apply(op, &[self.node])
+    }
+
     pub fn slice_rows(self, start: usize, end: usize) -> Self {
         self.builder.apply(operations::SliceRows(start, end), &[self.node])
     }
@@ -166,8 +175,8 @@ impl NetworkBuilderNode<'_> {
 
 #[derive(Clone, Copy)]
 pub struct Affine {
-    weights: Node,
-    bias: Node,
+    pub(crate) weights: Node,
+    pub(crate) bias: Node,
 }
 
 impl Affine {
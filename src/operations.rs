@@ -3,6 +3,7 @@ mod affine;
 mod affine_dual;
 mod concat;
 mod conv;
+mod fake_quantise;
 mod gather;
 mod linear;
 mod linear_comb;
@@ -18,6 +19,7 @@ mod submatrix_product;
 pub use affine::*;
 pub use affine_dual::*;
 pub use concat::*;
+pub use fake_quantise::*;
 pub use gather::*;
 pub use linear::*;
 pub use linear_comb::*;
@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{OnnxGraph, OnnxTensor, Operation},
     tensor::{Activation, DenseMatrix, ExecutionContext, Shape, Tensor},
 };
 
@@ -40,4 +40,9 @@ impl Operation for Activation {
             Activation::Sigmoid => DenseMatrix::sigmoid_backward(input.dense(), input_grad, output_grad),
         }
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        onnx.activate(&inputs[0].name, *self, &output.name);
+        Ok(())
+    }
 }
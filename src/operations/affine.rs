@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{OnnxGraph, OnnxTensor, Operation},
     tensor::{DenseMatrix, ExecutionContext, Matrix, Shape, SparseMatrix, Tensor},
 };
 
@@ -58,4 +58,13 @@ impl Operation for Affine {
             }
         }
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        if inputs[0].batched {
+            return Err(String::from("the weights of an affine transform cannot depend on the inputs"));
+        }
+
+        onnx.node("Gemm", &[&inputs[1].name, &inputs[0].name, &inputs[2].name], &[&output.name], Vec::new());
+        Ok(())
+    }
 }
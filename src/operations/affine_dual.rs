@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{Attribute, OnnxGraph, OnnxTensor, Operation},
     tensor::{Activation, ExecutionContext, Matrix, Shape, SparseMatrix, Tensor},
 };
 
@@ -49,4 +49,23 @@ impl Operation for AffineDualActivate {
             );
         }
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        if inputs[0].batched {
+            return Err(String::from("the weights of an affine transform cannot depend on the inputs"));
+        }
+
+        let mut accumulator = |input: &OnnxTensor| {
+            let affine = onnx.op("Gemm", &[&input.name, &inputs[0].name, &inputs[3].name], Vec::new());
+            let activated = onnx.temp();
+            onnx.activate(&affine, self.0, &activated);
+            activated
+        };
+
+        let stm = accumulator(&inputs[1]);
+        let nstm = accumulator(&inputs[2]);
+
+        onnx.node("Concat", &[&stm, &nstm], &[&output.name], vec![Attribute::Int("axis", 1)]);
+        Ok(())
+    }
 }
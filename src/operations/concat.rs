@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{Attribute, OnnxGraph, OnnxTensor, Operation},
     tensor::{DenseMatrix, ExecutionContext, Shape, Tensor},
 };
 
@@ -31,4 +31,13 @@ impl Operation for Concat {
             output.gradients.as_ref().unwrap(),
         );
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        if inputs[0].batched != inputs[1].batched {
+            return Err(String::from("cannot concatenate weights with values that depend on the inputs"));
+        }
+
+        onnx.node("Concat", &[&inputs[0].name, &inputs[1].name], &[&output.name], vec![Attribute::Int("axis", 1)]);
+        Ok(())
+    }
 }
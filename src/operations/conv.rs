@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{Attribute, OnnxGraph, OnnxTensor, Operation},
     tensor::{ConvolutionDescription, DenseMatrix, ExecutionContext, Shape, Tensor},
 };
 
@@ -57,4 +57,32 @@ impl Operation for ConvolutionDescription {
             output.gradients.as_ref().unwrap(),
         );
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        if inputs[0].batched {
+            return Err(String::from("the filters of a convolution cannot depend on the inputs"));
+        }
+
+        // both are stored NCHW, as for cuDNN
+        let dims = |shape: Shape| [shape.rows() as i64, shape.cols() as i64];
+        let [h, w] = dims(self.input_shape);
+        let [r, s] = dims(self.filter_shape);
+        let (c, k) = (self.input_channels as i64, self.output_channels as i64);
+
+        let input = onnx.reshape(&inputs[1].name, &[-1, c, h, w]);
+        let filters = onnx.reshape(&inputs[0].name, &[k, c, r, s]);
+
+        let (pad_h, pad_w) = (self.padding_shape.0 as i64, self.padding_shape.1 as i64);
+        let attributes = vec![
+            Attribute::Ints("kernel_shape", vec![r, s]),
+            Attribute::Ints("pads", vec![pad_h, pad_w, pad_h, pad_w]),
+            Attribute::Ints("strides", dims(self.stride_shape).to_vec()),
+        ];
+
+        let conv = onnx.op("Conv", &[&input, &filters], attributes);
+
+        let shape = onnx.ints(&[-1, output.shape.rows() as i64]);
+        onnx.node("Reshape", &[&conv, &shape], &[&output.name], Vec::new());
+        Ok(())
+    }
 }
@@ -0,0 +1,180 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    autograd::{OnnxGraph, OnnxTensor, Operation},
+    tensor::{DenseMatrix, ExecutionContext, Shape, Tensor},
+};
+
+/// Shared switch for `FakeQuantise` operations, so that quantisation-aware
+/// training can be turned on partway through a run.
+#[derive(Clone, Debug, Default)]
+pub struct FakeQuantiseSwitch(Arc<AtomicBool>);
+
+impl FakeQuantiseSwitch {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Quantises the input to a signed `bits`-bit integer with the given `scale` and
+/// dequantises it again, so the network sees the rounding of the quantised network.
+/// The backward pass is a straight-through estimator, i.e. the identity.
+/// If it has a `switch` that is disabled, this is the identity, and is skipped entirely.
+#[derive(Debug)]
+pub struct FakeQuantise {
+    pub scale: f32,
+    pub bits: u32,
+    pub truncate: bool,
+    pub switch: Option<FakeQuantiseSwitch>,
+}
+
+impl Operation for FakeQuantise {
+    fn output_tensor(&self, inputs: &[Shape]) -> Result<Shape, String> {
+        if inputs.len() == 1 {
+            Ok(inputs[0])
+        } else {
+            Err(format!("Invalid number of inputs in fake quantise! Expected 1, got {}", inputs.len()))
+        }
+    }
+
+    fn forward(&self, _: &mut ExecutionContext, inputs: &[&Tensor], output: &mut Tensor) {
+        let input = inputs[0].values.dense();
+        let output = output.values.dense_mut();
+
+        if self.is_identity() {
+            input.copy_into(output);
+        } else {
+            DenseMatrix::fake_quantise(input, output, self.scale, self.bits, self.truncate);
+        }
+    }
+
+    fn is_identity(&self) -> bool {
+        !self.switch.as_ref().is_none_or(FakeQuantiseSwitch::is_enabled)
+    }
+
+    fn can_be_identity(&self) -> bool {
+        self.switch.is_some()
+    }
+
+    fn backward(&self, ctx: &mut ExecutionContext, output: &Tensor, inputs: &mut [&mut Tensor]) {
+        let output_grad = output.gradients.as_ref().unwrap();
+
+        if let Some(input_grad) = inputs[0].gradients.as_mut() {
+            input_grad.reshape_if_needed(output_grad.shape());
+            DenseMatrix::add_assign_scaled(ctx, 1.0, output_grad, input_grad);
+        }
+    }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        let input = &inputs[0].name;
+
+        if !self.switch.as_ref().is_none_or(FakeQuantiseSwitch::is_enabled) {
+            onnx.node("Identity", &[input], &[&output.name], Vec::new());
+            return Ok(());
+        }
+
+        let scale = onnx.scalar(self.scale);
+        let scaled = onnx.op("Mul", &[input, &scale], Vec::new());
+
+        // `Round` rounds ties to even, so round the magnitude with ties away from zero instead
+        let sign = onnx.op("Sign", &[&scaled], Vec::new());
+        let mut magnitude = onnx.op("Abs", &[&scaled], Vec::new());
+
+        if !self.truncate {
+            let half = onnx.scalar(0.5);
+            magnitude = onnx.op("Add", &[&magnitude, &half], Vec::new());
+        }
+
+        let magnitude = onnx.op("Floor", &[&magnitude], Vec::new());
+        let rounded = onnx.op("Mul", &[&sign, &magnitude], Vec::new());
+
+        let max = ((1u64 << (self.bits - 1)) - 1) as f32;
+        let min = -((1u64 << (self.bits - 1)) as f32);
+        let (min, max) = (onnx.scalar(min), onnx.scalar(max));
+        let clipped = onnx.op("Clip", &[&rounded, &min, &max], Vec::new());
+
+        onnx.node("Div", &[&clipped, &scale], &[&output.name], Vec::new());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        nn::{Graph, InitSettings, NetworkBuilder},
+        trainer::default::{QuantOptions, QuantTarget, Rounding},
+    };
+
+    const WEIGHTS: [f32; 6] = [0.3, -0.11, 0.5, 0.02, -0.7, 1.01];
+    const INPUT: [f32; 3] = [1.0, 2.0, -1.0];
+
+    /// `w * x`, with `w` a column-major 2x3 matrix.
+    fn matmul(w: &[f32], x: &[f32]) -> [f32; 2] {
+        [0, 1].map(|row| (0..3).map(|col| w[2 * col + row] * x[col]).sum())
+    }
+
+    fn assert_close(a: [f32; 2], b: [f32; 2]) {
+        assert!(a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-5), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn forward_matches_saved_weights() {
+        let switch = FakeQuantiseSwitch::default();
+        let builder = NetworkBuilder::default();
+
+        let w = builder.new_weights("w", Shape::new(2, 3), InitSettings::Zeroed);
+        let x = builder.new_input("x", Shape::new(3, 1));
+        let targets = builder.new_input("targets", Shape::new(2, 1));
+
+        let op = FakeQuantise { scale: 64.0, bits: 16, truncate: false, switch: Some(switch.clone()) };
+        let out = builder.apply(op, &[w.node()]).matmul(x);
+        let output_node = out.node();
+        out.mse(targets);
+
+        let mut graph = builder.build(ExecutionContext::default());
+        graph.get_weights_mut("w").load_from_slice(&WEIGHTS);
+        graph.get_input_mut("x").load_from_slice(&INPUT);
+        graph.get_input_mut("targets").load_from_slice(&[0.0; 2]);
+
+        let output = |graph: &mut Graph| {
+            graph.forward();
+            graph.backward();
+
+            let mut buf = [0.0; 2];
+            graph.get_node(output_node).values.dense().write_to_slice(&mut buf);
+            buf
+        };
+
+        // as saved by `save_quantised`, with the same rounding
+        let options = QuantOptions { rounding: Rounding::Nearest, saturate: false };
+        let (bytes, _) = QuantTarget::I16(64).quantise_with(&WEIGHTS, options).unwrap();
+        let saved = bytes.chunks_exact(2).map(|x| f32::from(i16::from_le_bytes([x[0], x[1]])) / 64.0);
+
+        switch.set_enabled(true);
+        assert_close(output(&mut graph), matmul(&saved.collect::<Vec<_>>(), &INPUT));
+
+        switch.set_enabled(false);
+        assert_close(output(&mut graph), matmul(&WEIGHTS, &INPUT));
+    }
+
+    #[test]
+    #[should_panic(expected = "An operation will alias nodes on backprop!")]
+    fn switched_operation_cannot_alias_inputs() {
+        let builder = NetworkBuilder::default();
+        let w = builder.new_weights("w", Shape::new(2, 1), InitSettings::Zeroed);
+
+        let op = FakeQuantise { scale: 64.0, bits: 16, truncate: false, switch: Some(FakeQuantiseSwitch::default()) };
+        let quantised = builder.apply(op, &[w.node()]);
+
+        // when disabled, `quantised` is `w`, so this would borrow `w` twice on backprop
+        let _ = w.concat(quantised);
+    }
+}
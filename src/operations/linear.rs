@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{OnnxGraph, OnnxTensor, Operation},
     tensor::{DenseMatrix, ExecutionContext, Matrix, Shape, SparseMatrix, Tensor},
 };
 
@@ -57,4 +57,14 @@ impl Operation for Linear {
             }
         }
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        if inputs[0].batched {
+            return Err(String::from("the weights of a matmul cannot depend on the inputs"));
+        }
+
+        // the weights are stored transposed, so this is `(W * x)^T`
+        onnx.node("MatMul", &[&inputs[1].name, &inputs[0].name], &[&output.name], Vec::new());
+        Ok(())
+    }
 }
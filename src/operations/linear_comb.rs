@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{OnnxGraph, OnnxTensor, Operation},
     tensor::{DenseMatrix, ExecutionContext, Shape, Tensor},
 };
 
@@ -40,4 +40,17 @@ impl Operation for LinearCombination {
             output.gradients.as_ref().unwrap(),
         );
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        if inputs[0].batched != inputs[1].batched {
+            return Err(String::from("cannot add weights to values that depend on the inputs"));
+        }
+
+        let (alpha, beta) = (onnx.scalar(self.0), onnx.scalar(self.1));
+        let first = onnx.op("Mul", &[&inputs[0].name, &alpha], Vec::new());
+        let second = onnx.op("Mul", &[&inputs[1].name, &beta], Vec::new());
+
+        onnx.node("Add", &[&first, &second], &[&output.name], Vec::new());
+        Ok(())
+    }
 }
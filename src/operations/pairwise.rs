@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{OnnxGraph, OnnxTensor, Operation},
     tensor::{DenseMatrix, ExecutionContext, Shape, Tensor},
 };
 
@@ -31,4 +31,22 @@ impl Operation for PairwiseMul {
             DenseMatrix::backprop_pairwise(input, output_grad, input_grad, self.0);
         }
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        // after concatenating, each half is multiplied pairwise separately
+        let groups = if self.0 { 2 } else { 1 };
+        let half = (inputs[0].shape.rows() / (2 * groups)) as i64;
+
+        let grouped = onnx.reshape(&inputs[0].name, &[-1, groups as i64, 2, half]);
+        let axes = onnx.ints(&[2]);
+        let (zero, one, two) = (onnx.ints(&[0]), onnx.ints(&[1]), onnx.ints(&[2]));
+
+        let first = onnx.op("Slice", &[&grouped, &zero, &one, &axes], Vec::new());
+        let second = onnx.op("Slice", &[&grouped, &one, &two, &axes], Vec::new());
+        let product = onnx.op("Mul", &[&first, &second], Vec::new());
+
+        let shape = onnx.ints(&[-1, groups as i64 * half]);
+        onnx.node("Reshape", &[&product, &shape], &[&output.name], Vec::new());
+        Ok(())
+    }
 }
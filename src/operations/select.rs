@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{Attribute, OnnxGraph, OnnxTensor, Operation},
     tensor::{ExecutionContext, Matrix, Shape, SparseMatrix, Tensor},
 };
 
@@ -39,4 +39,18 @@ impl Operation for Select {
             }
         }
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        // the buckets are one-hot, so this sums the selected bucket with zeros
+        let buckets = inputs[1].shape.rows() as i64;
+        let size = output.shape.rows() as i64;
+
+        let values = onnx.reshape(&inputs[0].name, &[-1, buckets, size]);
+        let one_hot = onnx.reshape(&inputs[1].name, &[-1, buckets, 1]);
+        let selected = onnx.op("Mul", &[&values, &one_hot], Vec::new());
+
+        let axes = onnx.ints(&[1]);
+        onnx.node("ReduceSum", &[&selected, &axes], &[&output.name], vec![Attribute::Int("keepdims", 0)]);
+        Ok(())
+    }
 }
@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{OnnxGraph, OnnxTensor, Operation},
     tensor::{DenseMatrix, ExecutionContext, Shape, Tensor},
 };
 
@@ -37,4 +37,13 @@ impl Operation for SliceRows {
             output.gradients.as_ref().unwrap(),
         );
     }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        let starts = onnx.ints(&[self.0 as i64]);
+        let ends = onnx.ints(&[self.1 as i64]);
+        let axes = onnx.ints(&[1]);
+
+        onnx.node("Slice", &[&inputs[0].name, &starts, &ends, &axes], &[&output.name], Vec::new());
+        Ok(())
+    }
 }
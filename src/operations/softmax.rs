@@ -1,5 +1,5 @@
 use crate::{
-    autograd::Operation,
+    autograd::{Attribute, OnnxGraph, OnnxTensor, Operation},
     tensor::{DenseMatrix, Shape, Tensor},
     ExecutionContext,
 };
@@ -61,4 +61,19 @@ impl Operation for SoftmaxCrossEntropyLoss {
             );
         }
     }
+
+    fn reduces_batch(&self) -> bool {
+        true
+    }
+
+    fn to_onnx(&self, onnx: &mut OnnxGraph, inputs: &[OnnxTensor], output: &OnnxTensor) -> Result<(), String> {
+        let log_softmax = onnx.op("LogSoftmax", &[&inputs[0].name], vec![Attribute::Int("axis", 1)]);
+        let weighted = onnx.op("Mul", &[&log_softmax, &inputs[1].name], Vec::new());
+
+        let axes = onnx.ints(&[0, 1]);
+        let loss = onnx.op("ReduceSum", &[&weighted, &axes], vec![Attribute::Int("keepdims", 1)]);
+
+        onnx.node("Neg", &[&loss], &[&output.name], Vec::new());
+        Ok(())
+    }
 }
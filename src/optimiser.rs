@@ -1,7 +1,13 @@
+mod adafactor;
 mod adamw;
+mod constraint;
+mod transfer;
 pub mod utils;
 
+pub use adafactor::{Adafactor, AdafactorOptimiser, AdafactorParams};
 pub use adamw::{AdamW, AdamWOptimiser, AdamWParams};
+pub use constraint::{ColumnMaxNorm, Norm, RowMaxNorm, WeightConstraint};
+pub use transfer::{load_weights_partially, PadFill, TransferReport, WeightTransfer};
 
 use crate::nn::Graph;
 
@@ -12,6 +18,23 @@ pub trait Optimiser {
 
     fn update(&mut self, gradient_factor: f32, learning_rate: f32);
 
+    /// Called after each backward pass whose gradients will be used by the next
+    /// `update`, so that anything depending on the currently loaded inputs (e.g.
+    /// the features active in a sparse update) can be recorded before the next
+    /// micro-batch replaces them.
+    fn record_gradients(&mut self) {}
+
+    /// Optimisers may defer part of an update (e.g. weight decay of untouched
+    /// weights in a sparse update), this applies anything outstanding so that
+    /// the weights are up to date. Called before the weights are saved.
+    fn apply_deferred_updates(&mut self) {}
+
+    /// Number of `f32`s of state kept by the optimiser, in addition to the
+    /// weights and their gradients, if known.
+    fn state_size(&self) -> Option<usize> {
+        None
+    }
+
     fn graph(&self) -> &Graph;
 
     fn graph_mut(&mut self) -> &mut Graph;
@@ -22,6 +45,41 @@ pub trait Optimiser {
 
     fn set_params_for_weight(&mut self, id: &str, params: Self::Params);
 
+    /// Optimisers that don't support per-weight options can leave this
+    /// and `set_options_for_weight` unimplemented, in which case trying
+    /// to change the options of a weight panics.
+    fn options_for_weight(&self, id: &str) -> WeightOptions {
+        panic!("This optimiser does not support per-weight options (tried to get those of [{id}])!");
+    }
+
+    fn set_options_for_weight(&mut self, id: &str, _options: WeightOptions) {
+        panic!("This optimiser does not support per-weight options (tried to set those of [{id}])!");
+    }
+
+    /// Adds a constraint that is applied to the weights `id` after each update,
+    /// in the order in which constraints were added.
+    fn add_constraint(&mut self, id: &str, _constraint: Box<dyn WeightConstraint>) {
+        panic!("This optimiser does not support weight constraints (tried to add one to [{id}])!");
+    }
+
+    /// Scales the learning rate used to update every weight matching `pattern`.
+    /// See `matching_weight_ids` for the accepted patterns.
+    fn set_lr_multiplier(&mut self, pattern: &str, multiplier: f32) {
+        for id in matching_weight_ids(self.graph(), pattern) {
+            let options = WeightOptions { lr_multiplier: multiplier, ..self.options_for_weight(&id) };
+            self.set_options_for_weight(&id, options);
+        }
+    }
+
+    /// Enables or disables weight decay for every weight matching `pattern`.
+    /// See `matching_weight_ids` for the accepted patterns.
+    fn set_weight_decay_enabled(&mut self, pattern: &str, enabled: bool) {
+        for id in matching_weight_ids(self.graph(), pattern) {
+            let options = WeightOptions { apply_decay: enabled, ..self.options_for_weight(&id) };
+            self.set_options_for_weight(&id, options);
+        }
+    }
+
     fn set_params(&mut self, params: Self::Params) {
         for id in self.graph().weight_ids() {
             self.set_params_for_weight(&id, params.clone());
@@ -29,6 +87,35 @@ pub trait Optimiser {
     }
 }
 
+/// Per-weight adjustments that are applied on top of the optimiser params.
+#[derive(Clone, Copy, Debug)]
+pub struct WeightOptions {
+    pub lr_multiplier: f32,
+    pub apply_decay: bool,
+}
+
+impl Default for WeightOptions {
+    fn default() -> Self {
+        Self { lr_multiplier: 1.0, apply_decay: true }
+    }
+}
+
+/// Returns the ids of the weights matched by `pattern`, which is either an
+/// exact weight id (e.g. `"l0w"`), or a prefix followed by `*` (e.g. `"l0*"`).
+pub fn matching_weight_ids(graph: &Graph, pattern: &str) -> Vec<String> {
+    let ids = graph.weight_ids();
+
+    let matching = if let Some(prefix) = pattern.strip_suffix('*') {
+        ids.into_iter().filter(|id| id.starts_with(prefix)).collect::<Vec<_>>()
+    } else {
+        ids.into_iter().filter(|id| id == pattern).collect()
+    };
+
+    assert!(!matching.is_empty(), "No weights match [{pattern}]!");
+
+    matching
+}
+
 /// This is for use in `TrainerBuilder`
 pub trait OptimiserType: Default {
     type Optimiser: Optimiser;
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+use crate::{nn::Graph, tensor::DenseMatrix, Shape};
+
+use super::{utils, Optimiser, OptimiserType, WeightConstraint, WeightOptions};
+
+/// Momentum is disabled (and not stored) for a weight if `beta1 == 0.0`.
+///
+/// Each step the update of a weight is divided by `max(1, RMS(update) / clip_threshold)`,
+/// as the second moment estimate is tiny for the first few steps and would otherwise
+/// move every weight by about `lr / sqrt(1 - beta2)`.
+#[derive(Clone, Copy, Debug)]
+pub struct AdafactorParams {
+    pub decay: f32,
+    pub beta1: f32,
+    pub beta2: f32,
+    pub clip_threshold: f32,
+    pub min_weight: f32,
+    pub max_weight: f32,
+}
+
+impl Default for AdafactorParams {
+    fn default() -> Self {
+        Self { decay: 0.01, beta1: 0.0, beta2: 0.999, clip_threshold: 1.0, min_weight: -1.98, max_weight: 1.98 }
+    }
+}
+
+#[derive(Default)]
+pub struct Adafactor;
+impl OptimiserType for Adafactor {
+    type Optimiser = AdafactorOptimiser;
+}
+
+/// Stores running means of the squared gradients of each row and
+/// each column of a weight, rather than one value per weight as in
+/// AdamW, so a `rows x cols` weight only needs `rows + cols` floats
+/// of second moment state.
+pub struct AdafactorOptimiser {
+    graph: Graph,
+    momentum: HashMap<String, DenseMatrix>,
+    row_velocity: HashMap<String, DenseMatrix>,
+    col_velocity: HashMap<String, DenseMatrix>,
+    options: HashMap<String, WeightOptions>,
+    constraints: HashMap<String, Vec<Box<dyn WeightConstraint>>>,
+    params: HashMap<String, AdafactorParams>,
+    scratch: DenseMatrix,
+}
+
+impl Optimiser for AdafactorOptimiser {
+    type Params = AdafactorParams;
+
+    fn new(graph: Graph, default_params: Self::Params) -> Self {
+        let weight_ids = graph.weight_ids();
+
+        let mut row_velocity = HashMap::new();
+        let mut col_velocity = HashMap::new();
+        let mut options = HashMap::new();
+        let mut params = HashMap::new();
+
+        for id in weight_ids {
+            let shape = graph.get_weights(&id).values.shape();
+
+            let old = row_velocity.insert(id.clone(), DenseMatrix::zeroed(Shape::new(shape.rows(), 1)));
+            assert!(old.is_none());
+
+            let old = col_velocity.insert(id.clone(), DenseMatrix::zeroed(Shape::new(shape.cols(), 1)));
+            assert!(old.is_none());
+
+            let old = options.insert(id.clone(), WeightOptions::default());
+            assert!(old.is_none());
+
+            let old = params.insert(id, default_params);
+            assert!(old.is_none());
+        }
+
+        let mut optimiser = Self {
+            graph,
+            momentum: HashMap::new(),
+            row_velocity,
+            col_velocity,
+            options,
+            constraints: HashMap::new(),
+            params,
+            scratch: DenseMatrix::default(),
+        };
+
+        for id in optimiser.graph.weight_ids() {
+            optimiser.set_params_for_weight(&id, default_params);
+        }
+
+        optimiser
+    }
+
+    fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    fn graph_mut(&mut self) -> &mut Graph {
+        &mut self.graph
+    }
+
+    fn update(&mut self, gradient_factor: f32, learning_rate: f32) {
+        for id in &self.graph.weight_ids() {
+            let options = self.options.get(id).unwrap();
+
+            let mut params = *self.params.get(id).unwrap();
+            if !options.apply_decay {
+                params.decay = 0.0;
+            }
+
+            let weights = self.graph.get_weights_mut(id);
+
+            weights.values.dense_mut().adafactor(
+                weights.gradients.as_ref().unwrap(),
+                self.momentum.get_mut(id),
+                self.row_velocity.get_mut(id).unwrap(),
+                self.col_velocity.get_mut(id).unwrap(),
+                &mut self.scratch,
+                &params,
+                gradient_factor,
+                learning_rate * options.lr_multiplier,
+            );
+
+            if let Some(constraints) = self.constraints.get(id) {
+                for constraint in constraints {
+                    constraint.apply(weights.values.dense_mut());
+                }
+            }
+        }
+    }
+
+    fn state_size(&self) -> Option<usize> {
+        let size = |map: &HashMap<String, DenseMatrix>| map.values().map(|x| x.shape().size()).sum::<usize>();
+        Some(size(&self.momentum) + size(&self.row_velocity) + size(&self.col_velocity))
+    }
+
+    fn write_to_checkpoint(&self, path: &str) {
+        utils::write_graph_weights_to_file(&self.graph, &format!("{path}/weights.bin"));
+        utils::write_weight_hashmap_to_file(&self.momentum, &format!("{path}/momentum.bin"));
+        utils::write_weight_hashmap_to_file(&self.row_velocity, &format!("{path}/row_velocity.bin"));
+        utils::write_weight_hashmap_to_file(&self.col_velocity, &format!("{path}/col_velocity.bin"));
+    }
+
+    /// Momentum is only loaded for weights that currently have it enabled,
+    /// so the params should be set before loading a checkpoint.
+    fn load_from_checkpoint(&mut self, path: &str) {
+        utils::load_graph_weights_from_file(&mut self.graph, &format!("{path}/weights.bin"));
+        utils::load_weight_hashmap_from_file(&mut self.momentum, &format!("{path}/momentum.bin"));
+        utils::load_weight_hashmap_from_file(&mut self.row_velocity, &format!("{path}/row_velocity.bin"));
+        utils::load_weight_hashmap_from_file(&mut self.col_velocity, &format!("{path}/col_velocity.bin"));
+    }
+
+    fn options_for_weight(&self, id: &str) -> WeightOptions {
+        *self.options.get(id).unwrap()
+    }
+
+    fn set_options_for_weight(&mut self, id: &str, options: WeightOptions) {
+        *self.options.get_mut(id).unwrap() = options;
+    }
+
+    fn add_constraint(&mut self, id: &str, constraint: Box<dyn WeightConstraint>) {
+        assert!(self.options.contains_key(id), "No weights with id [{id}]!");
+        self.constraints.entry(id.to_string()).or_default().push(constraint);
+    }
+
+    fn set_params_for_weight(&mut self, id: &str, params: Self::Params) {
+        *self.params.get_mut(id).unwrap() = params;
+
+        if params.beta1 == 0.0 {
+            self.momentum.remove(id);
+        } else if !self.momentum.contains_key(id) {
+            let shape = self.graph.get_weights(id).values.shape();
+            self.momentum.insert(id.to_string(), DenseMatrix::zeroed(shape));
+        }
+    }
+}
+
+impl AdafactorOptimiser {
+    pub fn load_weights_from_file(&mut self, path: &str) {
+        utils::load_graph_weights_from_file(&mut self.graph, path);
+    }
+}
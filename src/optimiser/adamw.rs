@@ -1,8 +1,12 @@
 use std::collections::HashMap;
 
-use crate::{nn::Graph, tensor::DenseMatrix};
+use crate::{
+    nn::Graph,
+    tensor::{DenseMatrix, Matrix, SparseMatrix},
+    Shape,
+};
 
-use super::{utils, Optimiser, OptimiserType};
+use super::{utils, Optimiser, OptimiserType, WeightConstraint, WeightOptions};
 
 #[derive(Clone, Copy, Debug)]
 pub struct AdamWParams {
@@ -29,7 +33,42 @@ pub struct AdamWOptimiser {
     graph: Graph,
     momentum: HashMap<String, DenseMatrix>,
     velocity: HashMap<String, DenseMatrix>,
+    options: HashMap<String, WeightOptions>,
+    constraints: HashMap<String, Vec<Box<dyn WeightConstraint>>>,
     params: HashMap<String, AdamWParams>,
+    sparse: HashMap<String, LazySparseState>,
+}
+
+/// Bookkeeping for weights that are updated with `SparseMatrix::lazy_adamw`.
+struct LazySparseState {
+    inputs: Vec<String>,
+    columns: SparseMatrix,
+    marks: DenseMatrix,
+    last_decay: DenseMatrix,
+    cumulative_decay: f32,
+}
+
+impl LazySparseState {
+    fn reset(&mut self) {
+        self.marks.set_zero();
+        self.last_decay.set_zero();
+        self.cumulative_decay = 0.0;
+    }
+
+    /// Marks the columns of the weights `id` that are active in the inputs currently
+    /// loaded into `graph`, in addition to those marked since the last update.
+    fn mark_active(&mut self, id: &str, graph: &Graph) {
+        let inputs = self.inputs.iter().map(|input| graph.get_input(input)).collect::<Vec<_>>();
+        let inputs = inputs
+            .iter()
+            .map(|input| match &input.values {
+                Matrix::Sparse(matrix) => matrix,
+                Matrix::Dense(_) => panic!("Sparse updates of [{id}] require sparse inputs!"),
+            })
+            .collect::<Vec<_>>();
+
+        SparseMatrix::mark_active_rows(&inputs, &mut self.marks);
+    }
 }
 
 impl Optimiser for AdamWOptimiser {
@@ -40,6 +79,7 @@ impl Optimiser for AdamWOptimiser {
 
         let mut momentum = HashMap::new();
         let mut velocity = HashMap::new();
+        let mut options = HashMap::new();
         let mut params = HashMap::new();
 
         for id in weight_ids {
@@ -51,11 +91,14 @@ impl Optimiser for AdamWOptimiser {
             let old = velocity.insert(id.clone(), DenseMatrix::zeroed(shape));
             assert!(old.is_none());
 
+            let old = options.insert(id.clone(), WeightOptions::default());
+            assert!(old.is_none());
+
             let old = params.insert(id, default_params);
             assert!(old.is_none());
         }
 
-        Self { graph, momentum, velocity, params }
+        Self { graph, momentum, velocity, options, constraints: HashMap::new(), params, sparse: HashMap::new() }
     }
 
     fn graph(&self) -> &Graph {
@@ -68,19 +111,76 @@ impl Optimiser for AdamWOptimiser {
 
     fn update(&mut self, gradient_factor: f32, learning_rate: f32) {
         for id in &self.graph.weight_ids() {
-            let weights = self.graph.get_weights_mut(id);
+            let options = self.options.get(id).unwrap();
+            let learning_rate = learning_rate * options.lr_multiplier;
+
+            let mut params = *self.params.get(id).unwrap();
+            if !options.apply_decay {
+                params.decay = 0.0;
+            }
+
+            if let Some(sparse) = self.sparse.get_mut(id) {
+                sparse.mark_active(id, &self.graph);
+                SparseMatrix::take_marked_rows(&mut sparse.marks, &mut sparse.columns);
+
+                sparse.cumulative_decay += (1.0 - learning_rate * params.decay).ln();
+
+                let weights = self.graph.get_weights_mut(id);
+
+                SparseMatrix::lazy_adamw(
+                    &sparse.columns,
+                    weights.values.dense_mut(),
+                    weights.gradients.as_ref().unwrap(),
+                    self.momentum.get_mut(id).unwrap(),
+                    self.velocity.get_mut(id).unwrap(),
+                    &mut sparse.last_decay,
+                    sparse.cumulative_decay,
+                    &params,
+                    gradient_factor,
+                    learning_rate,
+                );
+            } else {
+                let weights = self.graph.get_weights_mut(id);
 
-            weights.values.dense_mut().adamw(
-                weights.gradients.as_ref().unwrap(),
-                self.momentum.get_mut(id).unwrap(),
-                self.velocity.get_mut(id).unwrap(),
-                self.params.get(id).unwrap(),
-                gradient_factor,
-                learning_rate,
-            );
+                weights.values.dense_mut().adamw(
+                    weights.gradients.as_ref().unwrap(),
+                    self.momentum.get_mut(id).unwrap(),
+                    self.velocity.get_mut(id).unwrap(),
+                    &params,
+                    gradient_factor,
+                    learning_rate,
+                );
+            }
+
+            if let Some(constraints) = self.constraints.get(id) {
+                let weights = self.graph.get_weights_mut(id);
+
+                for constraint in constraints {
+                    constraint.apply(weights.values.dense_mut());
+                }
+            }
+        }
+    }
+
+    fn record_gradients(&mut self) {
+        for (id, sparse) in &mut self.sparse {
+            sparse.mark_active(id, &self.graph);
+        }
+    }
+
+    fn apply_deferred_updates(&mut self) {
+        for (id, sparse) in &mut self.sparse {
+            let weights = self.graph.get_weights_mut(id);
+            weights.values.dense_mut().apply_lazy_decay(&sparse.last_decay, sparse.cumulative_decay);
+            sparse.reset();
         }
     }
 
+    fn state_size(&self) -> Option<usize> {
+        let size = |map: &HashMap<String, DenseMatrix>| map.values().map(|x| x.shape().size()).sum::<usize>();
+        Some(size(&self.momentum) + size(&self.velocity))
+    }
+
     fn write_to_checkpoint(&self, path: &str) {
         utils::write_graph_weights_to_file(&self.graph, &format!("{path}/weights.bin"));
         utils::write_weight_hashmap_to_file(&self.momentum, &format!("{path}/momentum.bin"));
@@ -91,6 +191,23 @@ impl Optimiser for AdamWOptimiser {
         utils::load_graph_weights_from_file(&mut self.graph, &format!("{path}/weights.bin"));
         utils::load_weight_hashmap_from_file(&mut self.momentum, &format!("{path}/momentum.bin"));
         utils::load_weight_hashmap_from_file(&mut self.velocity, &format!("{path}/velocity.bin"));
+
+        for sparse in self.sparse.values_mut() {
+            sparse.reset();
+        }
+    }
+
+    fn options_for_weight(&self, id: &str) -> WeightOptions {
+        *self.options.get(id).unwrap()
+    }
+
+    fn set_options_for_weight(&mut self, id: &str, options: WeightOptions) {
+        *self.options.get_mut(id).unwrap() = options;
+    }
+
+    fn add_constraint(&mut self, id: &str, constraint: Box<dyn WeightConstraint>) {
+        assert!(self.options.contains_key(id), "No weights with id [{id}]!");
+        self.constraints.entry(id.to_string()).or_default().push(constraint);
     }
 
     fn set_params_for_weight(&mut self, id: &str, params: Self::Params) {
@@ -102,4 +219,272 @@ impl AdamWOptimiser {
     pub fn load_weights_from_file(&mut self, path: &str) {
         utils::load_graph_weights_from_file(&mut self.graph, path);
     }
+
+    /// Only update the columns of the weights `id` that correspond to features active
+    /// in the sparse `inputs` (e.g. `["stm", "nstm"]` for `l0w`), rather than doing a
+    /// dense pass over the whole matrix. Weight decay skipped by untouched columns is
+    /// applied when they are next touched, or when `apply_deferred_updates` is called.
+    /// When gradients are accumulated over several micro-batches, every column active
+    /// in any of them is updated, provided `record_gradients` is called after each one.
+    ///
+    /// Note that momentum is not applied to untouched columns, nor are their moments
+    /// decayed, so this is not exactly equivalent to the dense update. A column skipped
+    /// after a step with momentum `m` and velocity `v` misses a total movement of at most
+    /// `lr * |m| / sqrt(v) * r / (1 - r)`, where `r = beta1 / sqrt(beta2)`. With `beta1 = 0`
+    /// the two agree exactly once `apply_deferred_updates` has been called.
+    pub fn set_sparse_update_for_weight(&mut self, id: &str, inputs: &[&str]) {
+        assert!(self.graph.weight_ids().iter().any(|w| w == id), "No weights with id [{id}]!");
+
+        let cols = self.graph.get_weights(id).values.shape().cols();
+
+        for input in inputs {
+            assert!(self.graph.input_ids().iter().any(|i| i == input), "No input with id [{input}]!");
+
+            let rows = self.graph.get_input(input).values.shape().rows();
+            assert_eq!(rows, cols, "Input [{input}] does not index the columns of [{id}]!");
+        }
+
+        let state = LazySparseState {
+            inputs: inputs.iter().map(|input| input.to_string()).collect(),
+            columns: SparseMatrix::default(),
+            marks: DenseMatrix::zeroed(Shape::new(cols, 1)),
+            last_decay: DenseMatrix::zeroed(Shape::new(cols, 1)),
+            cumulative_decay: 0.0,
+        };
+
+        if let Some(old) = self.sparse.insert(id.to_string(), state) {
+            let weights = self.graph.get_weights_mut(id);
+            weights.values.dense_mut().apply_lazy_decay(&old.last_decay, old.cumulative_decay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::optimiser::{Norm, RowMaxNorm};
+    use crate::{nn::NetworkBuilder, ExecutionContext, NetworkTrainer};
+
+    fn build_graph() -> Graph {
+        let builder = NetworkBuilder::default();
+        let stm = builder.new_input("stm", Shape::new(4, 1));
+        let targets = builder.new_input("targets", Shape::new(2, 1));
+        let l0 = builder.new_affine("l0", 4, 2);
+        l0.forward(stm).mse(targets);
+        builder.build(ExecutionContext::default())
+    }
+
+    fn weights(optimiser: &AdamWOptimiser, id: &str) -> Vec<f32> {
+        let weights = optimiser.graph.get_weights(id);
+        let dense = weights.values.dense();
+        let mut buf = vec![0.0; dense.shape().size()];
+        dense.write_to_slice(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn lazy_update_matches_dense_with_decay() {
+        let params = AdamWParams { decay: 0.1, beta1: 0.0, beta2: 0.0, ..Default::default() };
+        let lr = 0.01;
+
+        let mut dense = AdamWOptimiser::new(build_graph(), params);
+        let mut lazy = AdamWOptimiser::new(build_graph(), params);
+
+        for id in dense.graph.weight_ids() {
+            let weights = dense.graph.get_weights(&id);
+            lazy.graph.store_weights(&id, &weights);
+        }
+
+        lazy.set_sparse_update_for_weight("l0w", &["stm"]);
+
+        // targets are far from the outputs, so the error always has the same
+        // sign and the stale weights seen by the lazy forward pass don't matter
+        let batches: [&[i32]; 4] = [&[0, -1], &[1, 2], &[0, -1], &[3, -1]];
+
+        for features in batches {
+            for optimiser in [&mut dense, &mut lazy] {
+                let graph = optimiser.graph_mut();
+
+                unsafe {
+                    graph.get_input_mut("stm").load_sparse_from_slice(Shape::new(4, 1), 2, features);
+                }
+
+                graph.get_input_mut("targets").load_dense_from_slice(Shape::new(2, 1), &[5.0, 5.0]);
+
+                graph.zero_grads();
+                graph.forward();
+                graph.backward();
+
+                optimiser.update(1.0, lr);
+            }
+        }
+
+        lazy.apply_deferred_updates();
+
+        for id in ["l0w", "l0b"] {
+            let (d, l) = (weights(&dense, id), weights(&lazy, id));
+
+            for (d, l) in d.iter().zip(l.iter()) {
+                assert!((d - l).abs() < 1e-5, "[{id}] {d:?} != {l:?}");
+            }
+        }
+    }
+
+    fn step(optimiser: &mut AdamWOptimiser, lr: f32) {
+        let graph = optimiser.graph_mut();
+
+        unsafe {
+            graph.get_input_mut("stm").load_sparse_from_slice(Shape::new(4, 1), 2, &[0, 1]);
+        }
+
+        graph.get_input_mut("targets").load_dense_from_slice(Shape::new(2, 1), &[5.0, 5.0]);
+
+        graph.zero_grads();
+        graph.forward();
+        graph.backward();
+
+        optimiser.update(1.0, lr);
+    }
+
+    #[test]
+    fn zero_lr_multiplier_freezes_weights() {
+        let mut optimiser = AdamWOptimiser::new(build_graph(), AdamWParams::default());
+        optimiser.set_lr_multiplier("l0w", 0.0);
+
+        let (w, b) = (weights(&optimiser, "l0w"), weights(&optimiser, "l0b"));
+        step(&mut optimiser, 0.01);
+
+        assert_eq!(weights(&optimiser, "l0w"), w);
+        assert_ne!(weights(&optimiser, "l0b"), b);
+    }
+
+    #[test]
+    fn disabling_decay_matches_zero_decay() {
+        let params = AdamWParams { decay: 0.5, ..Default::default() };
+        let lr = 0.1;
+
+        let mut decayed = AdamWOptimiser::new(build_graph(), params);
+        let mut toggled = AdamWOptimiser::new(build_graph(), params);
+        let mut undecayed = AdamWOptimiser::new(build_graph(), AdamWParams { decay: 0.0, ..params });
+
+        for id in decayed.graph.weight_ids() {
+            let weights = decayed.graph.get_weights(&id);
+            toggled.graph.store_weights(&id, &weights);
+            undecayed.graph.store_weights(&id, &weights);
+        }
+
+        toggled.set_weight_decay_enabled("l0*", false);
+
+        for optimiser in [&mut decayed, &mut toggled, &mut undecayed] {
+            step(optimiser, lr);
+        }
+
+        for id in ["l0w", "l0b"] {
+            assert_eq!(weights(&toggled, id), weights(&undecayed, id), "[{id}]");
+        }
+
+        // biases start at zero, so only the weights are changed by decay after one step
+        assert_ne!(weights(&toggled, "l0w"), weights(&decayed, "l0w"));
+    }
+
+    /// Overwrites every weight with `value`, so it is obvious whether it ran.
+    struct Fill(f32);
+
+    impl WeightConstraint for Fill {
+        fn apply(&self, weights: &mut DenseMatrix) {
+            let shape = weights.shape();
+            weights.load_from_slice(shape, &vec![self.0; shape.size()]);
+        }
+    }
+
+    #[test]
+    fn constraints_are_applied_after_update() {
+        let mut optimiser = AdamWOptimiser::new(build_graph(), AdamWParams::default());
+        optimiser.add_constraint("l0w", Box::new(RowMaxNorm { norm: Norm::L2, max: 0.1 }));
+        optimiser.add_constraint("l0b", Box::new(Fill(0.25)));
+
+        step(&mut optimiser, 1.0);
+
+        let w = weights(&optimiser, "l0w");
+        for row in 0..2 {
+            let norm = (0..4).map(|col| w[2 * col + row].powi(2)).sum::<f32>().sqrt();
+            assert!(norm <= 0.1 + 1e-5, "row {row} has norm {norm}");
+        }
+
+        assert_eq!(weights(&optimiser, "l0b"), [0.25; 2]);
+    }
+
+    struct Trainer(AdamWOptimiser);
+
+    impl NetworkTrainer for Trainer {
+        type PreparedData = Vec<i32>;
+        type Optimiser = AdamWOptimiser;
+
+        fn load_batch(&mut self, features: &Self::PreparedData) -> usize {
+            let batch_size = features.len() / 2;
+            let graph = self.0.graph_mut();
+
+            unsafe {
+                graph.get_input_mut("stm").load_sparse_from_slice(Shape::new(4, batch_size), 2, features);
+            }
+
+            graph.get_input_mut("targets").load_dense_from_slice(Shape::new(2, batch_size), &vec![5.0; 2 * batch_size]);
+
+            batch_size
+        }
+
+        fn optimiser(&self) -> &Self::Optimiser {
+            &self.0
+        }
+
+        fn optimiser_mut(&mut self) -> &mut Self::Optimiser {
+            &mut self.0
+        }
+    }
+
+    #[test]
+    fn lazy_update_over_micro_batches_matches_full_batch() {
+        let params = AdamWParams::default();
+        let lr = 0.01;
+
+        let mut full = Trainer(AdamWOptimiser::new(build_graph(), params));
+        let mut micro = Trainer(AdamWOptimiser::new(build_graph(), params));
+
+        for id in full.0.graph.weight_ids() {
+            let weights = full.0.graph.get_weights(&id);
+            micro.0.graph.store_weights(&id, &weights);
+        }
+
+        full.0.set_sparse_update_for_weight("l0w", &["stm"]);
+        micro.0.set_sparse_update_for_weight("l0w", &["stm"]);
+
+        // the two micro-batches have no features in common
+        let batches = [vec![0, -1, 1, -1], vec![2, 3, 3, -1]];
+
+        for _ in 0..3 {
+            full.0.graph.zero_grads();
+            let batch_size = full.load_batch(&batches.concat());
+            full.accumulate_gradients();
+            full.apply_update(1.0 / batch_size as f32, lr);
+
+            micro.0.graph.zero_grads();
+            let mut batch_size = 0;
+            for batch in &batches {
+                batch_size += micro.load_batch(batch);
+                micro.accumulate_gradients();
+            }
+            micro.apply_update(1.0 / batch_size as f32, lr);
+        }
+
+        full.0.apply_deferred_updates();
+        micro.0.apply_deferred_updates();
+
+        for id in ["l0w", "l0b"] {
+            let (f, m) = (weights(&full.0, id), weights(&micro.0, id));
+
+            for (f, m) in f.iter().zip(m.iter()) {
+                assert!((f - m).abs() < 1e-5, "[{id}] {f:?} != {m:?}");
+            }
+        }
+    }
 }
@@ -0,0 +1,41 @@
+use crate::tensor::DenseMatrix;
+
+/// A constraint that is applied to a weight after every optimiser step.
+pub trait WeightConstraint {
+    fn apply(&self, weights: &mut DenseMatrix);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Norm {
+    L1,
+    L2,
+}
+
+/// Rescales each row of a weight so that its norm is at most `max`.
+/// Row `i` holds all the weights feeding into output neuron `i`, so
+/// this can be used to bound the accumulators in quantised inference.
+#[derive(Clone, Copy, Debug)]
+pub struct RowMaxNorm {
+    pub norm: Norm,
+    pub max: f32,
+}
+
+impl WeightConstraint for RowMaxNorm {
+    fn apply(&self, weights: &mut DenseMatrix) {
+        weights.max_norm(true, self.norm == Norm::L2, self.max);
+    }
+}
+
+/// Rescales each column of a weight so that its norm is at most `max`.
+/// Column `j` holds all the weights coming from input `j`.
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnMaxNorm {
+    pub norm: Norm,
+    pub max: f32,
+}
+
+impl WeightConstraint for ColumnMaxNorm {
+    fn apply(&self, weights: &mut DenseMatrix) {
+        weights.max_norm(false, self.norm == Norm::L2, self.max);
+    }
+}
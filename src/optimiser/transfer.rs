@@ -0,0 +1,275 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{nn::Graph, rng, Shape};
+
+use super::utils::{self, CheckpointTensor};
+
+/// How to fill the entries of a weight that are not covered by
+/// the smaller tensor it is being initialised from.
+#[derive(Clone, Copy, Debug)]
+pub enum PadFill {
+    Zero,
+    /// Gaussian noise with mean zero and the given standard deviation.
+    Noise(f32),
+}
+
+/// A rule for initialising a weight from a tensor of a different shape,
+/// used with `load_weights_partially`.
+#[derive(Clone, Copy, Debug)]
+pub enum WeightTransfer {
+    /// The tensor is split into `row_blocks x col_blocks` equally sized blocks, each of
+    /// which is copied to the start of the corresponding block of the (larger) weight,
+    /// with the rest filled by `fill`.
+    ///
+    /// For net2net-style widening of a feature transformer from `N` to `M` neurons,
+    /// use one row block for `l0w` and `l0b`, filled with noise so that the new neurons
+    /// can learn, and for the next layer's weights use as many column blocks as there
+    /// are concatenated accumulators (e.g. 2 for stm/nstm), filled with zeros, so that
+    /// the new neurons initially have no effect on the output.
+    ///
+    /// A pairwise feature transformer multiplies neuron `i` by neuron `i + N / 2`, so
+    /// `l0w` and `l0b` must instead use `row_blocks: 2`, to widen each half separately.
+    Pad { row_blocks: usize, col_blocks: usize, fill: PadFill },
+    /// The tensor is repeated down the rows of the weight, e.g. to copy an output
+    /// layer with a single bucket into every bucket of a layer with output buckets.
+    RepeatRows,
+}
+
+impl WeightTransfer {
+    /// Widens a weight whose rows are made up of a single block, e.g. `l0w` or `l0b`
+    /// of a feature transformer that is not pairwise.
+    pub fn widen(fill: PadFill) -> Self {
+        Self::Pad { row_blocks: 1, col_blocks: 1, fill }
+    }
+
+    fn apply(&self, from: &CheckpointTensor, to: Shape) -> Result<Vec<f32>, String> {
+        match *self {
+            Self::Pad { row_blocks, col_blocks, fill } => pad(from, to, row_blocks, col_blocks, fill),
+            Self::RepeatRows => repeat_rows(from, to),
+        }
+    }
+}
+
+/// What happened to each tensor and weight in `load_weights_partially`.
+#[derive(Debug, Default)]
+pub struct TransferReport {
+    /// Weights loaded from a tensor of the same shape.
+    pub loaded: Vec<String>,
+    /// Weights initialised from a tensor of a different shape, with the shapes of each.
+    pub transferred: Vec<(String, Shape, Shape)>,
+    /// Tensors in the file that were not used, and why.
+    pub skipped: Vec<(String, String)>,
+    /// Weights that were not in the file, and so keep their existing values.
+    pub not_loaded: Vec<String>,
+}
+
+impl fmt::Display for TransferReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for id in &self.loaded {
+            writeln!(f, "  = loaded      [{id}]")?;
+        }
+
+        for (id, from, to) in &self.transferred {
+            writeln!(f, "  > transferred [{id}] {from} -> {to}")?;
+        }
+
+        for (id, reason) in &self.skipped {
+            writeln!(f, "  - skipped     [{id}] {reason}")?;
+        }
+
+        for id in &self.not_loaded {
+            writeln!(f, "  + not loaded  [{id}]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads every weight of the graph that is in the file at `path` with the same shape,
+/// and initialises the weights in `transfers` from tensors of a different shape.
+/// Everything else is left alone and listed in the returned report, rather than
+/// panicking like `utils::load_graph_weights_from_file`.
+pub fn load_weights_partially(graph: &mut Graph, path: &str, transfers: &[(&str, WeightTransfer)]) -> TransferReport {
+    let file =
+        utils::read_tensors_from_file(path).unwrap_or_else(|e| panic!("Failed to read checkpoint file [{path}]: {e}"));
+
+    let transfers = transfers.iter().copied().collect::<HashMap<_, _>>();
+    let weight_ids = graph.weight_ids();
+
+    for id in transfers.keys() {
+        assert!(weight_ids.iter().any(|w| w == id), "No weights with id [{id}]!");
+    }
+
+    let mut report = TransferReport::default();
+
+    for tensor in &file.tensors {
+        if !weight_ids.contains(&tensor.id) {
+            report.skipped.push((tensor.id.clone(), "not in the network".to_string()));
+            continue;
+        }
+
+        let weights = graph.get_weights_mut(&tensor.id).values.dense_mut();
+        let shape = weights.shape();
+
+        if shape == tensor.shape {
+            weights.load_from_slice(shape, &tensor.values);
+            report.loaded.push(tensor.id.clone());
+            continue;
+        }
+
+        let Some(transfer) = transfers.get(tensor.id.as_str()) else {
+            report.skipped.push((tensor.id.clone(), format!("expected {shape}, found {}", tensor.shape)));
+            continue;
+        };
+
+        match transfer.apply(tensor, shape) {
+            Ok(values) => {
+                weights.load_from_slice(shape, &values);
+                report.transferred.push((tensor.id.clone(), tensor.shape, shape));
+            }
+            Err(reason) => report.skipped.push((tensor.id.clone(), reason)),
+        }
+    }
+
+    let used = |id: &String| report.loaded.contains(id) || report.transferred.iter().any(|(t, _, _)| t == id);
+    report.not_loaded = weight_ids.iter().filter(|id| !used(id)).cloned().collect();
+    report.not_loaded.sort();
+
+    report
+}
+
+fn pad(
+    from: &CheckpointTensor,
+    to: Shape,
+    row_blocks: usize,
+    col_blocks: usize,
+    fill: PadFill,
+) -> Result<Vec<f32>, String> {
+    let split = |old: usize, new: usize, blocks: usize, dim: &str| {
+        if blocks == 0 || old % blocks != 0 || new % blocks != 0 || new < old {
+            Err(format!("cannot pad {dim} {old} to {new} in {blocks} blocks"))
+        } else {
+            Ok((old / blocks, new / blocks))
+        }
+    };
+
+    let (old_row_block, new_row_block) = split(from.shape.rows(), to.rows(), row_blocks, "rows")?;
+    let (old_col_block, new_col_block) = split(from.shape.cols(), to.cols(), col_blocks, "cols")?;
+
+    let mut values = match fill {
+        PadFill::Zero => vec![0.0; to.size()],
+        PadFill::Noise(stdev) => rng::vec_f32(to.size(), 0.0, stdev, true),
+    };
+
+    for col in 0..from.shape.cols() {
+        let new_col = col / old_col_block * new_col_block + col % old_col_block;
+
+        for row in 0..from.shape.rows() {
+            let new_row = row / old_row_block * new_row_block + row % old_row_block;
+            values[to.rows() * new_col + new_row] = from.values[from.shape.rows() * col + row];
+        }
+    }
+
+    Ok(values)
+}
+
+fn repeat_rows(from: &CheckpointTensor, to: Shape) -> Result<Vec<f32>, String> {
+    let rows = from.shape.rows();
+
+    if rows == 0 || from.shape.cols() != to.cols() || to.rows() % rows != 0 {
+        return Err(format!("cannot repeat {} to fill {to}", from.shape));
+    }
+
+    let mut values = vec![0.0; to.size()];
+
+    for col in 0..to.cols() {
+        for row in 0..to.rows() {
+            values[to.rows() * col + row] = from.values[rows * col + row % rows];
+        }
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tensor(rows: usize, cols: usize, values: &[f32]) -> CheckpointTensor {
+        CheckpointTensor { id: "w".to_string(), shape: Shape::new(rows, cols), values: values.to_vec() }
+    }
+
+    #[test]
+    fn pad_rows() {
+        // two row blocks of 2, widened to blocks of 3
+        let from = tensor(4, 1, &[1.0, 2.0, 3.0, 4.0]);
+        let transfer = WeightTransfer::Pad { row_blocks: 2, col_blocks: 1, fill: PadFill::Zero };
+
+        let values = transfer.apply(&from, Shape::new(6, 1)).unwrap();
+        assert_eq!(values, [1.0, 2.0, 0.0, 3.0, 4.0, 0.0]);
+    }
+
+    #[test]
+    fn pad_pairwise_feature_transformer() {
+        // 4 neurons, with neuron `i` multiplied by neuron `i + 2`, widened to 6 neurons
+        let l0w = tensor(4, 2, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        let l0b = tensor(4, 1, &[1.0, 2.0, 3.0, 4.0]);
+        let transfer = WeightTransfer::Pad { row_blocks: 2, col_blocks: 1, fill: PadFill::Zero };
+
+        // neuron `i` is now multiplied by neuron `i + 3`, so the old pairs must stay 3 apart
+        let values = transfer.apply(&l0w, Shape::new(6, 2)).unwrap();
+        assert_eq!(values, [1.0, 2.0, 0.0, 3.0, 4.0, 0.0, 5.0, 6.0, 0.0, 7.0, 8.0, 0.0]);
+
+        let values = transfer.apply(&l0b, Shape::new(6, 1)).unwrap();
+        assert_eq!(values, [1.0, 2.0, 0.0, 3.0, 4.0, 0.0]);
+
+        // a single block would pair the old neurons up incorrectly
+        let values = WeightTransfer::widen(PadFill::Zero).apply(&l0b, Shape::new(6, 1)).unwrap();
+        assert_ne!((values[0], values[3]), (1.0, 3.0));
+    }
+
+    #[test]
+    fn pad_cols() {
+        // 2x4 in two column blocks (e.g. stm/nstm accumulators), widened to 2x6
+        let from = tensor(2, 4, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        let transfer = WeightTransfer::Pad { row_blocks: 1, col_blocks: 2, fill: PadFill::Zero };
+
+        let values = transfer.apply(&from, Shape::new(2, 6)).unwrap();
+        assert_eq!(values, [1.0, 2.0, 3.0, 4.0, 0.0, 0.0, 5.0, 6.0, 7.0, 8.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn pad_rejects_bad_blocks() {
+        let from = tensor(4, 1, &[1.0, 2.0, 3.0, 4.0]);
+
+        for (row_blocks, to) in [(3, 6), (2, 5), (1, 2), (0, 4)] {
+            let transfer = WeightTransfer::Pad { row_blocks, col_blocks: 1, fill: PadFill::Zero };
+            assert!(transfer.apply(&from, Shape::new(to, 1)).is_err());
+        }
+    }
+
+    #[test]
+    fn pad_fill_noise() {
+        let from = tensor(2, 2, &[1.0, 2.0, 3.0, 4.0]);
+        let transfer = WeightTransfer::widen(PadFill::Noise(0.1));
+
+        let values = transfer.apply(&from, Shape::new(3, 3)).unwrap();
+
+        assert_eq!([values[0], values[1], values[3], values[4]], [1.0, 2.0, 3.0, 4.0]);
+
+        let padded = [values[2], values[5], values[6], values[7], values[8]];
+        assert!(padded.iter().all(|x| x.abs() < 1.0), "{padded:?}");
+        assert!(padded.iter().any(|&x| x != 0.0), "{padded:?}");
+    }
+
+    #[test]
+    fn repeat_rows() {
+        let from = tensor(2, 2, &[1.0, 2.0, 3.0, 4.0]);
+
+        let values = WeightTransfer::RepeatRows.apply(&from, Shape::new(6, 2)).unwrap();
+        assert_eq!(values, [1.0, 2.0, 1.0, 2.0, 1.0, 2.0, 3.0, 4.0, 3.0, 4.0, 3.0, 4.0]);
+
+        assert!(WeightTransfer::RepeatRows.apply(&from, Shape::new(5, 2)).is_err());
+        assert!(WeightTransfer::RepeatRows.apply(&from, Shape::new(4, 3)).is_err());
+    }
+}
@@ -1,80 +1,276 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, fs, io};
 
-use crate::{nn::Graph, tensor::DenseMatrix};
+use bullet_inference::checkpoint::{self, Tensor};
 
-/// Writes the weights of a graph to a file. If `gradients` is true,
-/// it will instead write the gradients of those weights.
-pub fn write_graph_weights_to_file(graph: &Graph, path: &str) {
-    use std::{fs::File, io::Write};
+use crate::{nn::Graph, tensor::DenseMatrix, Shape};
 
-    let weight_ids = graph.weight_ids();
+/// A single labelled tensor, as stored in a checkpoint file.
+pub struct CheckpointTensor {
+    pub id: String,
+    pub shape: Shape,
+    pub values: Vec<f32>,
+}
 
-    let mut buf = Vec::new();
+impl CheckpointTensor {
+    pub fn from_matrix(id: &str, matrix: &DenseMatrix) -> Self {
+        let shape = matrix.shape();
+        let mut values = vec![0.0; shape.size()];
+        matrix.write_to_slice(&mut values);
 
-    for id in &weight_ids {
-        let weights = graph.get_weights(id);
-        let this_buf = weights.values.dense().write_to_byte_buffer(id).unwrap();
+        Self { id: id.to_string(), shape, values }
+    }
+}
 
-        buf.extend_from_slice(&this_buf);
+impl From<Tensor> for CheckpointTensor {
+    fn from(tensor: Tensor) -> Self {
+        Self { id: tensor.id, shape: Shape::new(tensor.rows, tensor.cols), values: tensor.values }
     }
+}
 
-    let mut file = File::create(path).unwrap();
-    file.write_all(&buf).unwrap();
+/// The contents of a checkpoint file, see `bullet_inference::checkpoint` for the layout.
+/// Legacy files, from before the layout had a header, are read with `arch_hash: None`.
+pub struct CheckpointFile {
+    pub arch_hash: Option<u64>,
+    pub tensors: Vec<CheckpointTensor>,
 }
 
-/// Loads the weights of a graph from a file. If `gradients` is true,
-/// it will instead load the gradients of those weights.
-pub fn load_graph_weights_from_file(graph: &mut Graph, path: &str) {
-    use std::{fs::File, io::Read};
+/// Hash of the ids and shapes of a set of tensors, independent of their order.
+pub fn architecture_hash<'a>(tensors: impl Iterator<Item = (&'a str, Shape)>) -> u64 {
+    checkpoint::architecture_hash(tensors.map(|(id, shape)| (id, shape.rows(), shape.cols())))
+}
+
+/// Architecture hash of the weights of a graph.
+pub fn graph_architecture_hash(graph: &Graph) -> u64 {
+    let ids = graph.weight_ids();
+    let shapes = ids.iter().map(|id| graph.get_weights(id).values.shape()).collect::<Vec<_>>();
+    architecture_hash(ids.iter().map(String::as_str).zip(shapes))
+}
+
+/// Differences between the tensors expected when loading a checkpoint,
+/// and those actually found in it.
+#[derive(Debug, Default)]
+pub struct CheckpointDiff {
+    pub missing: Vec<(String, Shape)>,
+    pub extra: Vec<(String, Shape)>,
+    pub reshaped: Vec<(String, Shape, Shape)>,
+}
+
+impl CheckpointDiff {
+    pub fn new(expected: &HashMap<String, Shape>, found: &[CheckpointTensor]) -> Self {
+        let mut diff = Self::default();
 
-    let mut buf = Vec::new();
-    let mut file = File::open(path).unwrap();
-    file.read_to_end(&mut buf).unwrap();
+        for tensor in found {
+            match expected.get(&tensor.id) {
+                None => diff.extra.push((tensor.id.clone(), tensor.shape)),
+                Some(&shape) if shape != tensor.shape => diff.reshaped.push((tensor.id.clone(), shape, tensor.shape)),
+                _ => {}
+            }
+        }
 
-    let mut offset = 0;
-    let mut matrix_buffer = DenseMatrix::default();
+        for (id, &shape) in expected {
+            if !found.iter().any(|tensor| &tensor.id == id) {
+                diff.missing.push((id.clone(), shape));
+            }
+        }
 
-    while offset < buf.len() {
-        let (id, bytes_read) = matrix_buffer.read_from_byte_buffer(&buf[offset..]);
+        diff.missing.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.extra.sort_by(|a, b| a.0.cmp(&b.0));
+        diff.reshaped.sort_by(|a, b| a.0.cmp(&b.0));
 
-        let weights = graph.get_weights_mut(&id);
-        matrix_buffer.copy_into(weights.values.dense_mut());
+        diff
+    }
 
-        offset += bytes_read;
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.reshaped.is_empty()
+    }
+}
+
+impl fmt::Display for CheckpointDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, shape) in &self.missing {
+            writeln!(f, "  - missing  [{id}] {shape}")?;
+        }
+
+        for (id, shape) in &self.extra {
+            writeln!(f, "  + extra    [{id}] {shape}")?;
+        }
+
+        for (id, expected, found) in &self.reshaped {
+            writeln!(f, "  ~ reshaped [{id}] expected {expected}, found {found}")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn write_tensors_to_file(tensors: &[CheckpointTensor], path: &str) -> io::Result<()> {
+    let tensors = tensors
+        .iter()
+        .map(|tensor| Tensor {
+            id: tensor.id.clone(),
+            rows: tensor.shape.rows(),
+            cols: tensor.shape.cols(),
+            values: tensor.values.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    checkpoint::write(path, &tensors)
+}
+
+pub fn read_tensors_from_file(path: &str) -> io::Result<CheckpointFile> {
+    let buf = fs::read(path)?;
+
+    let file = if buf.starts_with(&checkpoint::MAGIC) {
+        let tensors = checkpoint::decode(&buf)?.into_iter().map(CheckpointTensor::from).collect::<Vec<_>>();
+        CheckpointFile { arch_hash: Some(architecture_hash(tensors.iter().map(|t| (t.id.as_str(), t.shape)))), tensors }
+    } else {
+        let tensors = checkpoint::decode_legacy(&buf)?.into_iter().map(CheckpointTensor::from).collect();
+        CheckpointFile { arch_hash: None, tensors }
+    };
+
+    Ok(file)
+}
+
+/// Writes the weights of a graph to a file.
+pub fn write_graph_weights_to_file(graph: &Graph, path: &str) {
+    let mut ids = graph.weight_ids();
+    ids.sort();
+
+    let tensors = ids
+        .iter()
+        .map(|id| CheckpointTensor::from_matrix(id, graph.get_weights(id).values.dense()))
+        .collect::<Vec<_>>();
+
+    write_tensors_to_file(&tensors, path).unwrap();
+}
+
+/// Loads the weights of a graph from a file, panicking unless the file
+/// contains exactly the weights of the graph, with the same shapes.
+/// See `load_weights_partially` for loading only some of the weights.
+pub fn load_graph_weights_from_file(graph: &mut Graph, path: &str) {
+    let expected = graph.weight_ids().into_iter().map(|id| {
+        let shape = graph.get_weights(&id).values.shape();
+        (id, shape)
+    });
+
+    let tensors = read_and_check(path, expected.collect());
+
+    for tensor in tensors {
+        graph.get_weights_mut(&tensor.id).values.dense_mut().load_from_slice(tensor.shape, &tensor.values);
     }
 }
 
 /// Write a set of labelled weights from a `HashMap` into a file.
 pub fn write_weight_hashmap_to_file(map: &HashMap<String, DenseMatrix>, path: &str) {
-    use std::{fs::File, io::Write};
+    let mut ids = map.keys().collect::<Vec<_>>();
+    ids.sort();
 
-    let mut buf = Vec::new();
+    let tensors = ids.into_iter().map(|id| CheckpointTensor::from_matrix(id, &map[id])).collect::<Vec<_>>();
+
+    write_tensors_to_file(&tensors, path).unwrap();
+}
 
-    for (id, weights) in map {
-        let this_buf = weights.write_to_byte_buffer(id).unwrap();
-        buf.extend_from_slice(&this_buf);
+/// Loads a set of labelled weights from a file into a `HashMap`, with the same
+/// rules as `load_graph_weights_from_file`.
+pub fn load_weight_hashmap_from_file(map: &mut HashMap<String, DenseMatrix>, path: &str) {
+    let expected = map.iter().map(|(id, matrix)| (id.clone(), matrix.shape())).collect();
+
+    for tensor in read_and_check(path, expected) {
+        map.get_mut(&tensor.id).unwrap().load_from_slice(tensor.shape, &tensor.values);
     }
+}
+
+fn read_and_check(path: &str, expected: HashMap<String, Shape>) -> Vec<CheckpointTensor> {
+    let file = read_tensors_from_file(path).unwrap_or_else(|e| panic!("Failed to read checkpoint file [{path}]: {e}"));
 
-    let mut file = File::create(path).unwrap();
-    file.write_all(&buf).unwrap();
+    let diff = CheckpointDiff::new(&expected, &file.tensors);
+
+    assert!(diff.is_empty(), "Checkpoint file [{path}] does not match the network:\n{diff}");
+
+    file.tensors
 }
 
-/// Loads a set of labelled weights from a file into a `HashMap`.
-pub fn load_weight_hashmap_from_file(map: &mut HashMap<String, DenseMatrix>, path: &str) {
-    use std::{fs::File, io::Read};
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut buf = Vec::new();
-    let mut file = File::open(path).unwrap();
-    file.read_to_end(&mut buf).unwrap();
+    /// Writes tensors as `DenseMatrix::write_to_byte_buffer` did before checkpoints had a header.
+    fn legacy_bytes(tensors: &[(&str, usize, usize, &[f32])]) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        for (id, rows, cols, values) in tensors {
+            buf.extend_from_slice(id.as_bytes());
+            buf.push(b'\n');
+            buf.extend_from_slice(&(*rows as u64).to_le_bytes());
+            buf.extend_from_slice(&(*cols as u64).to_le_bytes());
+
+            for val in *values {
+                buf.extend_from_slice(&val.to_le_bytes());
+            }
+        }
+
+        buf
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("bullet-{name}-{}.bin", std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn read_legacy_checkpoint_file() {
+        let path = temp_path("legacy-checkpoint");
+        let bytes = legacy_bytes(&[("l0w", 2, 3, &[1.0, -2.0, 3.0, 0.5, 0.25, -0.125]), ("l0b", 2, 1, &[0.0, 1.5])]);
+        std::fs::write(&path, bytes).unwrap();
+
+        let file = read_tensors_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let file = file.unwrap();
+
+        assert_eq!(file.arch_hash, None);
+        assert_eq!(file.tensors.len(), 2);
+        assert_eq!(file.tensors[0].id, "l0w");
+        assert_eq!(file.tensors[0].shape, Shape::new(2, 3));
+        assert_eq!(file.tensors[0].values, [1.0, -2.0, 3.0, 0.5, 0.25, -0.125]);
+        assert_eq!(file.tensors[1].id, "l0b");
+        assert_eq!(file.tensors[1].shape, Shape::new(2, 1));
+        assert_eq!(file.tensors[1].values, [0.0, 1.5]);
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let path = temp_path("checkpoint");
+        let tensors = [
+            CheckpointTensor { id: "l0w".to_string(), shape: Shape::new(2, 2), values: vec![1.0, 2.0, 3.0, 4.0] },
+            CheckpointTensor { id: "l0b".to_string(), shape: Shape::new(2, 1), values: vec![-1.0, 0.5] },
+        ];
+
+        write_tensors_to_file(&tensors, &path).unwrap();
+        let file = read_tensors_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let file = file.unwrap();
+
+        assert_eq!(file.arch_hash, Some(architecture_hash(tensors.iter().map(|t| (t.id.as_str(), t.shape)))));
+
+        for (a, b) in file.tensors.iter().zip(tensors.iter()) {
+            assert_eq!((&a.id, a.shape, &a.values), (&b.id, b.shape, &b.values));
+        }
+    }
 
-    let mut offset = 0;
-    let mut matrix_buffer = DenseMatrix::default();
+    #[test]
+    fn missing_tensors_are_rejected() {
+        let path = temp_path("partial-checkpoint");
+        let tensors = [CheckpointTensor { id: "l0b".to_string(), shape: Shape::new(2, 1), values: vec![-1.0, 0.5] }];
+        write_tensors_to_file(&tensors, &path).unwrap();
 
-    while offset < buf.len() {
-        let (id, bytes_read) = matrix_buffer.read_from_byte_buffer(&buf[offset..]);
+        let l0b = ("l0b".to_string(), Shape::new(2, 1));
+        let expected = || [("l0w".to_string(), Shape::new(2, 2)), l0b.clone()].into();
 
-        matrix_buffer.copy_into(map.get_mut(&id).unwrap());
+        let exact = std::panic::catch_unwind(|| read_and_check(&path, [l0b.clone()].into()));
+        let missing = std::panic::catch_unwind(|| read_and_check(&path, expected()));
+        std::fs::remove_file(&path).unwrap();
 
-        offset += bytes_read;
+        assert_eq!(exact.unwrap().len(), 1);
+        assert!(missing.is_err());
     }
 }
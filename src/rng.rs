@@ -1,6 +1,6 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use rand::{rngs::ThreadRng, thread_rng};
+use rand::{thread_rng, Rng};
 use rand_distr::{Distribution, Normal, Uniform};
 
 enum Dist {
@@ -17,7 +17,7 @@ impl Dist {
         }
     }
 
-    fn sample(&self, rng: &mut ThreadRng) -> f32 {
+    fn sample<R: Rng>(&self, rng: &mut R) -> f32 {
         match self {
             Dist::Normal(x) => x.sample(rng),
             Dist::Uniform(x) => x.sample(rng),
@@ -26,16 +26,13 @@ impl Dist {
 }
 
 pub fn vec_f32(length: usize, mean: f32, stdev: f32, use_gaussian: bool) -> Vec<f32> {
-    let mut res = Vec::with_capacity(length);
+    vec_f32_from(&mut thread_rng(), length, mean, stdev, use_gaussian)
+}
 
-    let mut rng = thread_rng();
+/// As `vec_f32`, but drawn from `rng`, e.g. a seeded one for reproducible values.
+pub fn vec_f32_from<R: Rng>(rng: &mut R, length: usize, mean: f32, stdev: f32, use_gaussian: bool) -> Vec<f32> {
     let dist = Dist::new(mean, stdev, use_gaussian);
-
-    for _ in 0..length {
-        res.push(dist.sample(&mut rng));
-    }
-
-    res
+    (0..length).map(|_| dist.sample(rng)).collect()
 }
 
 pub struct SimpleRand(u64);
@@ -48,6 +45,16 @@ impl SimpleRand {
         Self(seed)
     }
 
+    pub fn from_seed(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Self(seed.max(1))
+    }
+
+    /// Current state, from which `from_seed` continues the same sequence.
+    pub fn state(&self) -> u64 {
+        self.0
+    }
+
     pub fn rng(&mut self) -> u64 {
         self.0 ^= self.0 << 13;
         self.0 ^= self.0 >> 7;
@@ -15,7 +15,12 @@ extern "C" {
     pub fn backpropSigmoid(size: usize, output: *const f32, output_grad: *const f32, input_grad: *mut f32);
     pub fn powerError(bufferSize: usize, inputs: *const f32, results: *const f32, output: *mut f32, power: f32);
     pub fn backpropPowerError(bufferSize: usize, inputs: *const f32, results: *const f32, output_grad: *const f32, input_grads: *mut f32, power: f32);
+    pub fn Adafactor(rows: usize, cols: usize, decay: f32, beta1: f32, beta2: f32, clipThreshold: f32, minWeight: f32, maxWeight: f32, adj: f32, rate: f32, network: *mut f32, momentum: *mut f32, rowVelocity: *mut f32, colVelocity: *mut f32, maxReductionBlocks: usize, scratch: *mut f32, gradients: *const f32);
     pub fn AdamW(size: usize, decay: f32, beta1: f32, beta2: f32, minWeight: f32, maxWeight: f32, adj: f32, rate: f32, network: *mut f32, momentum: *mut f32, velocity: *mut f32, gradients: *const f32);
+    pub fn SparseAdamW(rows: usize, numColumns: usize, cumulativeDecay: f32, beta1: f32, beta2: f32, minWeight: f32, maxWeight: f32, adj: f32, rate: f32, columns: *const i32, lastDecay: *mut f32, network: *mut f32, momentum: *mut f32, velocity: *mut f32, gradients: *const f32);
+    pub fn ApplyLazyDecay(rows: usize, cols: usize, cumulativeDecay: f32, lastDecay: *const f32, network: *mut f32);
+    pub fn FakeQuantise(size: usize, scale: f32, minQ: f32, maxQ: f32, truncate: bool, inp: *const f32, out: *mut f32);
+    pub fn MaxNorm(rows: usize, cols: usize, byRows: bool, l2: bool, maxNorm: f32, weights: *mut f32);
     pub fn sparseAffineForward(batchSize: usize, maxInputSize: usize, outputSize: usize, weights: *const f32, biases: *const f32, inputs: *const i32, outputs: *mut f32);
     pub fn sparseAffineBackward(batchSize: usize, maxInputSize: usize, outputSize: usize, weightsGrad: *mut f32, biasesGrad: *mut f32, inputs: *const i32, outputs: *const f32, errors: *const f32);
     pub fn sparseAffineDualForward(batchSize: usize, maxInputSize: usize, outputSize: usize, weights: *const f32, biases: *const f32, stm: *const i32, ntm: *const i32, outputs: *mut f32, activation: i32);
@@ -30,6 +35,7 @@ extern "C" {
     pub fn softmax_across_columns_masked(max_active: usize, rows: usize, cols: usize, mask: *const i32, inp: *const f32, out: *mut f32);
     pub fn crossentropy_masked(max_active: usize, cols: usize, mask: *const i32, pred: *const f32, target: *const f32, out: *mut f32, err: *mut f32);
     pub fn backprop_softmax_cross_entropy_masked(max_active: usize, rows: usize, cols: usize, mask: *const i32, softmaxed: *const f32, target: *const f32, out_grad: *const f32, input_grad: *mut f32);
+    pub fn sparse_mark_active(cols: usize, max_active: usize, inputs: *const i32, marks: *mut f32);
     pub fn sparse_to_dense(rows: usize, cols: usize, max_active: usize, inputs: *const i32, outputs: *mut f32);
     pub fn sparse_mask(rows: usize, cols: usize, max_active: usize, inputs: *const f32, masks: *const i32, outputs: *mut f32);
     pub fn sparse_mask_backprop(rows: usize, cols: usize, max_active: usize, output_grads: *const f32, masks: *const i32, input_grads: *mut f32);
@@ -1,9 +1,12 @@
 mod activate;
+mod adafactor;
 mod adamw;
 mod concat;
 mod conv;
+mod fake_quantise;
 mod linear_comb;
 mod matmul;
+mod max_norm;
 mod pairwise;
 mod power_error;
 mod slice;
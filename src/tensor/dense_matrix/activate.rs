@@ -2,17 +2,7 @@ use crate::tensor::backend::ops;
 
 use super::DenseMatrix;
 
-/// List of supported activation functions.
-#[repr(i32)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum Activation {
-    Identity = 0,
-    ReLU = 1,
-    CReLU = 2,
-    SCReLU = 3,
-    SqrReLU = 4,
-    Sigmoid = 5,
-}
+pub use bullet_inference::Activation;
 
 macro_rules! define_activation {
     (
@@ -0,0 +1,203 @@
+use crate::{
+    optimiser::AdafactorParams,
+    tensor::{backend::ops, Shape},
+};
+
+use super::DenseMatrix;
+
+/// Upper bound on the number of blocks used for each of the
+/// two-stage reductions in the Adafactor kernel.
+const MAX_REDUCTION_BLOCKS: usize = 256;
+
+impl DenseMatrix {
+    /// Adafactor-style update, where the second moment estimate of each weight is
+    /// reconstructed from the running row and column means of the squared gradients.
+    /// The update is scaled down if its RMS exceeds `params.clip_threshold`.
+    /// `scratch` is used to hold the row velocity sum, the update norm and the
+    /// per-block partial sums they are reduced from.
+    #[allow(clippy::too_many_arguments)]
+    pub fn adafactor(
+        &mut self,
+        gradient: &Self,
+        momentum: Option<&mut Self>,
+        row_velocity: &mut Self,
+        col_velocity: &mut Self,
+        scratch: &mut Self,
+        params: &AdafactorParams,
+        gradient_factor: f32,
+        learning_rate: f32,
+    ) {
+        assert_eq!(self.shape, gradient.shape);
+        assert_eq!(row_velocity.shape, Shape::new(self.shape.rows(), 1));
+        assert_eq!(col_velocity.shape, Shape::new(self.shape.cols(), 1));
+
+        let momentum = if let Some(momentum) = momentum {
+            assert_eq!(self.shape, momentum.shape);
+            momentum.buf.mut_ptr()
+        } else {
+            std::ptr::null_mut()
+        };
+
+        scratch.reshape_if_needed(Shape::new(2 + MAX_REDUCTION_BLOCKS, 1));
+
+        let decay = 1.0 - learning_rate * params.decay;
+
+        unsafe {
+            ops::Adafactor(
+                self.shape.rows(),
+                self.shape.cols(),
+                decay,
+                params.beta1,
+                params.beta2,
+                params.clip_threshold,
+                params.min_weight,
+                params.max_weight,
+                gradient_factor,
+                learning_rate,
+                self.buf.mut_ptr(),
+                momentum,
+                row_velocity.buf.mut_ptr(),
+                col_velocity.buf.mut_ptr(),
+                MAX_REDUCTION_BLOCKS,
+                scratch.buf.mut_ptr(),
+                gradient.buf.ptr(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tensor::backend::util;
+
+    const ROWS: usize = 2;
+    const COLS: usize = 3;
+
+    fn run(grads: &[[f32; ROWS * COLS]], params: &AdafactorParams, lr: f32) -> [f32; ROWS * COLS] {
+        let shape = Shape::new(ROWS, COLS);
+
+        let mut weights = DenseMatrix::zeroed(shape);
+        let mut gradient = DenseMatrix::default();
+        let mut row_velocity = DenseMatrix::zeroed(Shape::new(ROWS, 1));
+        let mut col_velocity = DenseMatrix::zeroed(Shape::new(COLS, 1));
+        let mut scratch = DenseMatrix::default();
+
+        util::panic_if_device_error("Failed to initialise matrices!");
+
+        for grad in grads {
+            gradient.load_from_slice(shape, grad);
+            weights.adafactor(&gradient, None, &mut row_velocity, &mut col_velocity, &mut scratch, params, 1.0, lr);
+        }
+
+        util::panic_if_device_error("Failed to run adafactor!");
+
+        let mut buf = [0.0; ROWS * COLS];
+        weights.write_to_slice(&mut buf);
+        buf
+    }
+
+    /// Reconstructs the second moment from row and column means on the CPU.
+    fn factored_reference(grads: &[[f32; ROWS * COLS]], beta2: f32, lr: f32) -> [f32; ROWS * COLS] {
+        let mut weights = [0.0; ROWS * COLS];
+        let mut row_vel = [0.0; ROWS];
+        let mut col_vel = [0.0; COLS];
+
+        for grad in grads {
+            for (row, vel) in row_vel.iter_mut().enumerate() {
+                let mean = (0..COLS).map(|col| grad[ROWS * col + row].powi(2)).sum::<f32>() / COLS as f32;
+                *vel = beta2 * *vel + (1.0 - beta2) * mean;
+            }
+
+            for (col, vel) in col_vel.iter_mut().enumerate() {
+                let mean = (0..ROWS).map(|row| grad[ROWS * col + row].powi(2)).sum::<f32>() / ROWS as f32;
+                *vel = beta2 * *vel + (1.0 - beta2) * mean;
+            }
+
+            let row_sum = row_vel.iter().sum::<f32>();
+
+            for (i, weight) in weights.iter_mut().enumerate() {
+                let vel = row_vel[i % ROWS] * col_vel[i / ROWS] * ROWS as f32 / row_sum;
+                *weight -= lr * grad[i] / (vel.sqrt() + 1e-8);
+            }
+        }
+
+        weights
+    }
+
+    #[test]
+    fn adafactor_matches_factored_reference() {
+        let params = AdafactorParams { decay: 0.0, beta2: 0.9, clip_threshold: f32::MAX, ..Default::default() };
+        let lr = 0.01;
+        let grads = [[1.0, -2.0, 0.5, 3.0, -1.5, 0.25], [0.5, 1.0, -1.0, 2.0, 0.75, -0.5]];
+
+        let buf = run(&grads, &params, lr);
+        let expected = factored_reference(&grads, params.beta2, lr);
+
+        for (a, b) in buf.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-5, "{buf:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn adafactor_matches_dense_for_rank_one_gradients() {
+        let params = AdafactorParams { decay: 0.0, beta2: 0.9, clip_threshold: f32::MAX, ..Default::default() };
+        let lr = 0.01;
+
+        // squared gradients of the form `a_r * b_c` are exactly recovered by the factorisation
+        let (a, b) = ([1.0, -2.0], [0.5, 3.0, -1.5]);
+        let grad = std::array::from_fn(|i| a[i % ROWS] * b[i / ROWS]);
+
+        let buf = run(&[grad], &params, lr);
+
+        for (w, g) in buf.iter().zip(grad.iter()) {
+            let dense = -lr * g / ((1.0 - params.beta2) * g * g).sqrt();
+            assert!((w - dense).abs() < 1e-5, "{buf:?}");
+        }
+    }
+
+    #[test]
+    fn adafactor_clips_update() {
+        let params = AdafactorParams { decay: 0.0, ..Default::default() };
+        let lr = 0.01;
+        let grads = [[1.0, -2.0, 0.5, 3.0, -1.5, 0.25]];
+
+        let buf = run(&grads, &params, lr);
+
+        // unclipped, the first step moves each weight by about `lr / sqrt(1 - beta2) ~ 31.6 * lr`
+        let rms = (buf.iter().map(|w| (w / lr).powi(2)).sum::<f32>() / buf.len() as f32).sqrt();
+        assert!((rms - params.clip_threshold).abs() < 1e-4, "RMS of update is {rms}");
+    }
+
+    #[test]
+    fn adafactor_reduces_over_many_blocks_deterministically() {
+        let params = AdafactorParams { decay: 0.0, ..Default::default() };
+        let lr = 0.01;
+
+        // more elements than `MAX_REDUCTION_BLOCKS` full blocks, so every block strides
+        let shape = Shape::new(2048, 200);
+        let grad = (0..shape.size()).map(|i| ((i * 7919) % 201) as f32 / 100.0 - 1.0).collect::<Vec<_>>();
+
+        let step = || {
+            let mut weights = DenseMatrix::zeroed(shape);
+            let mut gradient = DenseMatrix::default();
+            let mut row_velocity = DenseMatrix::zeroed(Shape::new(shape.rows(), 1));
+            let mut col_velocity = DenseMatrix::zeroed(Shape::new(shape.cols(), 1));
+            let mut scratch = DenseMatrix::default();
+
+            gradient.load_from_slice(shape, &grad);
+            weights.adafactor(&gradient, None, &mut row_velocity, &mut col_velocity, &mut scratch, &params, 1.0, lr);
+            util::panic_if_device_error("Failed to run adafactor!");
+
+            let mut buf = vec![0.0; shape.size()];
+            weights.write_to_slice(&mut buf);
+            buf
+        };
+
+        let first = step();
+        assert_eq!(first, step());
+
+        let rms = (first.iter().map(|w| f64::from(w / lr).powi(2)).sum::<f64>() / first.len() as f64).sqrt();
+        assert!((rms as f32 - params.clip_threshold).abs() < 1e-3, "RMS of update is {rms}");
+    }
+}
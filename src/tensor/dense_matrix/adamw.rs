@@ -1,4 +1,7 @@
-use crate::{optimiser::AdamWParams, tensor::backend::ops};
+use crate::{
+    optimiser::AdamWParams,
+    tensor::{backend::ops, Shape},
+};
 
 use super::DenseMatrix;
 
@@ -35,4 +38,20 @@ impl DenseMatrix {
             );
         }
     }
+
+    /// Applies any weight decay that has been deferred by `SparseMatrix::lazy_adamw`,
+    /// after which every column is up to date with `cumulative_decay`.
+    pub fn apply_lazy_decay(&mut self, last_decay: &Self, cumulative_decay: f32) {
+        assert_eq!(last_decay.shape, Shape::new(self.shape.cols(), 1));
+
+        unsafe {
+            ops::ApplyLazyDecay(
+                self.shape.rows(),
+                self.shape.cols(),
+                cumulative_decay,
+                last_decay.buf.ptr(),
+                self.buf.mut_ptr(),
+            );
+        }
+    }
 }
@@ -0,0 +1,55 @@
+use crate::tensor::backend::ops;
+
+use super::DenseMatrix;
+
+impl DenseMatrix {
+    /// Rounds (or truncates) `input * scale` to a signed `bits`-bit integer, saturating,
+    /// and writes the result divided by `scale` to `output`.
+    pub fn fake_quantise(input: &Self, output: &mut Self, scale: f32, bits: u32, truncate: bool) {
+        assert!((2..=32).contains(&bits), "Can only fake quantise to between 2 and 32 bits!");
+        assert!(scale > 0.0, "Quantisation scale must be positive!");
+
+        let max = ((1u64 << (bits - 1)) - 1) as f32;
+        let min = -((1u64 << (bits - 1)) as f32);
+
+        output.reshape_if_needed(input.shape);
+
+        unsafe {
+            ops::FakeQuantise(input.shape.size(), scale, min, max, truncate, input.buf.ptr(), output.buf.mut_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tensor::{backend::util, Shape};
+
+    #[test]
+    fn fake_quantise() {
+        let shape = Shape::new(2, 3);
+        let values = [0.26, -0.26, 0.74, -0.74, 2.0, -2.0];
+
+        let mut input = DenseMatrix::default();
+        let mut output = DenseMatrix::default();
+
+        util::panic_if_device_error("Failed to initialise matrices!");
+
+        input.load_from_slice(shape, &values);
+
+        DenseMatrix::fake_quantise(&input, &mut output, 4.0, 3, false);
+        util::panic_if_device_error("Failed to fake quantise!");
+
+        let mut buf = [0.0; 6];
+        output.write_to_slice(&mut buf);
+        assert_eq!(buf, [0.25, -0.25, 0.75, -0.75, 0.75, -1.0]);
+
+        DenseMatrix::fake_quantise(&input, &mut output, 4.0, 3, true);
+        util::panic_if_device_error("Failed to fake quantise!");
+
+        output.write_to_slice(&mut buf);
+        assert_eq!(buf, [0.25, -0.25, 0.5, -0.5, 0.75, -1.0]);
+
+        util::panic_if_device_error("Failed to write data to CPU!");
+    }
+}
@@ -0,0 +1,61 @@
+use crate::tensor::backend::ops;
+
+use super::DenseMatrix;
+
+impl DenseMatrix {
+    /// Rescales every row (or column, if `by_rows` is false) whose L1 (or L2,
+    /// if `l2` is true) norm exceeds `max_norm` so that its norm is `max_norm`.
+    pub fn max_norm(&mut self, by_rows: bool, l2: bool, max_norm: f32) {
+        assert!(max_norm > 0.0, "Max norm must be positive!");
+
+        unsafe {
+            ops::MaxNorm(self.shape.rows(), self.shape.cols(), by_rows, l2, max_norm, self.buf.mut_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tensor::{backend::util, Shape};
+
+    #[test]
+    fn max_norm() {
+        let shape = Shape::new(2, 3);
+        let values = [1.0, 0.5, -2.0, 0.5, 3.0, 0.5];
+
+        let mut matrix = DenseMatrix::default();
+
+        util::panic_if_device_error("Failed to initialise matrices!");
+
+        matrix.load_from_slice(shape, &values);
+        matrix.max_norm(true, false, 3.0);
+
+        util::panic_if_device_error("Failed to apply row max norm!");
+
+        let mut buf = [0.0; 6];
+        matrix.write_to_slice(&mut buf);
+        assert_eq!(buf, [0.5, 0.5, -1.0, 0.5, 1.5, 0.5]);
+
+        matrix.load_from_slice(shape, &values);
+        matrix.max_norm(false, true, 1.0);
+
+        util::panic_if_device_error("Failed to apply column max norm!");
+
+        matrix.write_to_slice(&mut buf);
+        let expected = [
+            1.0 / 1.25f32.sqrt(),
+            0.5 / 1.25f32.sqrt(),
+            -2.0 / 4.25f32.sqrt(),
+            0.5 / 4.25f32.sqrt(),
+            3.0 / 9.25f32.sqrt(),
+            0.5 / 9.25f32.sqrt(),
+        ];
+
+        for (a, b) in buf.iter().zip(expected.iter()) {
+            assert!((a - b).abs() < 1e-6);
+        }
+
+        util::panic_if_device_error("Failed to write data to CPU!");
+    }
+}
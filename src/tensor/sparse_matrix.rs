@@ -1,3 +1,4 @@
+mod adamw;
 mod affine;
 mod affine_dual;
 mod gather;
@@ -0,0 +1,270 @@
+use crate::{
+    optimiser::AdamWParams,
+    tensor::{backend::ops, DenseMatrix, Shape},
+};
+
+use super::SparseMatrix;
+
+impl SparseMatrix {
+    /// Marks every row that is active in at least one of `inputs`, keeping
+    /// any rows already marked, so that the union of the rows active over
+    /// several batches can be gathered with `take_marked_rows`.
+    pub fn mark_active_rows(inputs: &[&Self], marks: &mut DenseMatrix) {
+        let rows = marks.shape().rows();
+        assert_eq!(marks.shape().cols(), 1);
+
+        for input in inputs {
+            assert_eq!(input.shape.rows(), rows);
+
+            unsafe {
+                ops::sparse_mark_active(input.shape.cols(), input.max_active, input.buf.ptr(), marks.buf.mut_ptr());
+            }
+        }
+    }
+
+    /// Gathers the rows marked by `mark_active_rows` into the single
+    /// sparse column `output`, and clears the marks.
+    pub fn take_marked_rows(marks: &mut DenseMatrix, output: &mut Self) {
+        let rows = marks.shape().rows();
+
+        let mut buf = vec![0.0; rows];
+        marks.write_to_slice(&mut buf);
+        marks.set_zero();
+
+        let active = buf.iter().enumerate().filter(|(_, &mark)| mark > 0.0).map(|(i, _)| i as i32).collect::<Vec<_>>();
+
+        unsafe {
+            output.load_from_slice(Shape::new(rows, 1), active.len(), &active);
+        }
+    }
+
+    /// AdamW update that only touches the given `columns` of the weights.
+    /// Weight decay for the steps a column was skipped is applied lazily, using
+    /// the running sum of log-decay factors `cumulative_decay` and the value of
+    /// it when each column was last updated, stored in `last_decay`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lazy_adamw(
+        columns: &Self,
+        weights: &mut DenseMatrix,
+        gradient: &DenseMatrix,
+        momentum: &mut DenseMatrix,
+        velocity: &mut DenseMatrix,
+        last_decay: &mut DenseMatrix,
+        cumulative_decay: f32,
+        params: &AdamWParams,
+        gradient_factor: f32,
+        learning_rate: f32,
+    ) {
+        let shape = weights.shape();
+        assert_eq!(shape, gradient.shape);
+        assert_eq!(shape, momentum.shape);
+        assert_eq!(shape, velocity.shape);
+        assert_eq!(columns.shape, Shape::new(shape.cols(), 1));
+        assert_eq!(last_decay.shape, Shape::new(shape.cols(), 1));
+
+        if columns.max_active == 0 {
+            return;
+        }
+
+        unsafe {
+            ops::SparseAdamW(
+                shape.rows(),
+                columns.max_active,
+                cumulative_decay,
+                params.beta1,
+                params.beta2,
+                params.min_weight,
+                params.max_weight,
+                gradient_factor,
+                learning_rate,
+                columns.buf.ptr(),
+                last_decay.buf.mut_ptr(),
+                weights.buf.mut_ptr(),
+                momentum.buf.mut_ptr(),
+                velocity.buf.mut_ptr(),
+                gradient.buf.ptr(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tensor::backend::util;
+
+    #[test]
+    fn lazy_adamw_matches_dense() {
+        let shape = Shape::new(2, 4);
+        let params = AdamWParams::default();
+        let lr = 0.1;
+
+        let weights = [0.5, -0.5, 0.25, -0.25, 1.0, -1.0, 0.75, -0.75];
+        let grads = [1.0, -2.0, 0.5, 3.0, 0.0, 0.0, 0.0, 0.0];
+
+        let mut dense = DenseMatrix::default();
+        let mut lazy = DenseMatrix::default();
+        let mut gradient = DenseMatrix::default();
+        let mut dense_state = [DenseMatrix::zeroed(shape), DenseMatrix::zeroed(shape)];
+        let mut lazy_state = [DenseMatrix::zeroed(shape), DenseMatrix::zeroed(shape)];
+        let mut last_decay = DenseMatrix::zeroed(Shape::new(4, 1));
+
+        let mut inputs = SparseMatrix::default();
+        let mut marks = DenseMatrix::zeroed(Shape::new(4, 1));
+        let mut columns = SparseMatrix::default();
+
+        dense.load_from_slice(shape, &weights);
+        lazy.load_from_slice(shape, &weights);
+        gradient.load_from_slice(shape, &grads);
+
+        unsafe {
+            inputs.load_from_slice(Shape::new(4, 2), 2, &[0, -1, 1, 0]);
+        }
+
+        util::panic_if_device_error("Failed to initialise matrices!");
+
+        SparseMatrix::mark_active_rows(&[&inputs], &mut marks);
+        SparseMatrix::take_marked_rows(&mut marks, &mut columns);
+
+        util::panic_if_device_error("Failed to find active rows!");
+
+        assert_eq!(columns.shape, Shape::new(4, 1));
+        assert_eq!(columns.max_active, 2);
+
+        let mut cumulative_decay = 0.0;
+
+        for _ in 0..2 {
+            let [momentum, velocity] = &mut dense_state;
+            dense.adamw(&gradient, momentum, velocity, &params, 1.0, lr);
+
+            cumulative_decay += (1.0 - lr * params.decay).ln();
+            let [momentum, velocity] = &mut lazy_state;
+            SparseMatrix::lazy_adamw(
+                &columns,
+                &mut lazy,
+                &gradient,
+                momentum,
+                velocity,
+                &mut last_decay,
+                cumulative_decay,
+                &params,
+                1.0,
+                lr,
+            );
+        }
+
+        lazy.apply_lazy_decay(&last_decay, cumulative_decay);
+
+        util::panic_if_device_error("Failed to run adamw!");
+
+        let mut dense_buf = [0.0; 8];
+        let mut lazy_buf = [0.0; 8];
+        dense.write_to_slice(&mut dense_buf);
+        lazy.write_to_slice(&mut lazy_buf);
+
+        for (d, l) in dense_buf.iter().zip(lazy_buf.iter()) {
+            assert!((d - l).abs() < 1e-5, "{dense_buf:?} != {lazy_buf:?}");
+        }
+    }
+
+    #[test]
+    fn lazy_adamw_divergence_is_bounded() {
+        let shape = Shape::new(2, 4);
+        let params = AdamWParams::default();
+        let lr = 0.01;
+        let steps = 6;
+
+        let weights = [0.5, -0.5, 0.25, -0.25, 1.0, -1.0, 0.75, -0.75];
+        let first_grads = [1.0, -2.0, 0.5, 3.0, -1.5, 0.5, 2.0, -1.0];
+        let later_grads = [1.0, -2.0, 0.5, 3.0, 0.0, 0.0, 0.0, 0.0];
+
+        let mut dense = DenseMatrix::default();
+        let mut lazy = DenseMatrix::default();
+        let mut gradient = DenseMatrix::default();
+        let mut dense_state = [DenseMatrix::zeroed(shape), DenseMatrix::zeroed(shape)];
+        let mut lazy_state = [DenseMatrix::zeroed(shape), DenseMatrix::zeroed(shape)];
+        let mut last_decay = DenseMatrix::zeroed(Shape::new(4, 1));
+
+        let mut all_inputs = SparseMatrix::default();
+        let mut later_inputs = SparseMatrix::default();
+        let mut marks = DenseMatrix::zeroed(Shape::new(4, 1));
+        let mut all_columns = SparseMatrix::default();
+        let mut later_columns = SparseMatrix::default();
+
+        dense.load_from_slice(shape, &weights);
+        lazy.load_from_slice(shape, &weights);
+
+        unsafe {
+            all_inputs.load_from_slice(Shape::new(4, 2), 2, &[0, 2, 1, 3]);
+            later_inputs.load_from_slice(Shape::new(4, 2), 2, &[0, -1, 1, 0]);
+        }
+
+        SparseMatrix::mark_active_rows(&[&all_inputs], &mut marks);
+        SparseMatrix::take_marked_rows(&mut marks, &mut all_columns);
+        SparseMatrix::mark_active_rows(&[&later_inputs], &mut marks);
+        SparseMatrix::take_marked_rows(&mut marks, &mut later_columns);
+
+        util::panic_if_device_error("Failed to initialise matrices!");
+
+        assert_eq!(all_columns.max_active, 4);
+        assert_eq!(later_columns.max_active, 2);
+
+        let mut cumulative_decay = 0.0;
+        let mut first_moments = ([0.0; 8], [0.0; 8]);
+
+        for step in 0..steps {
+            let (grads, columns) =
+                if step == 0 { (&first_grads, &all_columns) } else { (&later_grads, &later_columns) };
+            gradient.load_from_slice(shape, grads);
+
+            let [momentum, velocity] = &mut dense_state;
+            dense.adamw(&gradient, momentum, velocity, &params, 1.0, lr);
+
+            cumulative_decay += (1.0 - lr * params.decay).ln();
+            let [momentum, velocity] = &mut lazy_state;
+            SparseMatrix::lazy_adamw(
+                columns,
+                &mut lazy,
+                &gradient,
+                momentum,
+                velocity,
+                &mut last_decay,
+                cumulative_decay,
+                &params,
+                1.0,
+                lr,
+            );
+
+            if step == 0 {
+                momentum.write_to_slice(&mut first_moments.0);
+                velocity.write_to_slice(&mut first_moments.1);
+            }
+        }
+
+        lazy.apply_lazy_decay(&last_decay, cumulative_decay);
+
+        util::panic_if_device_error("Failed to run adamw!");
+
+        let mut dense_buf = [0.0; 8];
+        let mut lazy_buf = [0.0; 8];
+        dense.write_to_slice(&mut dense_buf);
+        lazy.write_to_slice(&mut lazy_buf);
+
+        // columns touched every step match exactly
+        for i in 0..4 {
+            assert!((dense_buf[i] - lazy_buf[i]).abs() < 1e-5, "{dense_buf:?} != {lazy_buf:?}");
+        }
+
+        // skipped columns miss the movement due to their leftover momentum,
+        // which is at most `lr * |m| / sqrt(v) * r / (1 - r)` with `r = beta1 / sqrt(beta2)`
+        let r = params.beta1 / params.beta2.sqrt();
+        for i in 4..8 {
+            let (m, v) = (first_moments.0[i], first_moments.1[i]);
+            let bound = lr * m.abs() / v.sqrt() * r / (1.0 - r);
+            let diff = (dense_buf[i] - lazy_buf[i]).abs();
+
+            assert!(diff > 1e-4, "Expected skipped column to diverge: {dense_buf:?} vs {lazy_buf:?}");
+            assert!(diff <= bound + 1e-5, "Divergence {diff} exceeds bound {bound}");
+        }
+    }
+}
@@ -4,16 +4,20 @@ mod preparer;
 pub mod save;
 pub mod schedule;
 pub mod settings;
+pub mod state;
+mod summary;
 
-pub use preparer::DataPreparer;
+pub use preparer::{DataPreparer, LoaderPosition};
 use save::SavedFormat;
 use schedule::{lr::LrScheduler, wdl::WdlScheduler, TrainingSchedule};
 use settings::LocalSettings;
+use state::RunState;
+use summary::CheckpointSummary;
 
 use std::{
     fs::File,
     io::{self, Write},
-    sync::mpsc::{self, Receiver},
+    sync::mpsc,
     time::Instant,
 };
 
@@ -32,22 +36,51 @@ pub trait NetworkTrainer {
         util::device_synchronise();
         self.optimiser_mut().graph_mut().zero_grads();
 
+        let error = self.accumulate_gradients();
+
+        self.apply_update(gf, lr);
+
+        error
+    }
+
+    /// Runs the forward and backward pass on a batch that has been previously
+    /// loaded using `load_batch`, adding to the existing weight gradients.
+    fn accumulate_gradients(&mut self) -> f32 {
+        util::device_synchronise();
+        self.optimiser_mut().graph_mut().zero_intermediate_grads();
+
         let error = self.optimiser_mut().graph_mut().forward();
 
         self.optimiser_mut().graph_mut().backward();
+        self.optimiser_mut().record_gradients();
 
+        error
+    }
+
+    /// Updates the weights using the gradients accumulated since they were last zeroed.
+    fn apply_update(&mut self, gf: f32, lr: f32) {
         self.optimiser_mut().update(gf, lr);
 
         util::device_synchronise();
         util::panic_if_device_error("Something went wrong!");
-
-        error
     }
 
     fn optimiser(&self) -> &Self::Optimiser;
 
     fn optimiser_mut(&mut self) -> &mut Self::Optimiser;
 
+    /// Run state of a checkpoint that should be continued from the next time
+    /// `train_custom` is called, if any.
+    fn take_resume_state(&mut self) -> Option<RunState> {
+        None
+    }
+
+    /// Called before training on the first batch of each superbatch.
+    fn on_superbatch_start(&mut self, _superbatch: usize) {}
+
+    /// Called before saving each checkpoint.
+    fn on_checkpoint(&mut self, _net_id: &str, _superbatch: usize) {}
+
     fn load_from_checkpoint(&mut self, path: &str) {
         self.optimiser_mut().load_from_checkpoint(&format!("{path}/optimiser_state"));
     }
@@ -87,13 +120,15 @@ pub trait NetworkTrainer {
 
         util::device_synchronise();
 
-        let steps = schedule.steps;
+        let mut steps = schedule.steps;
         let pos_per_sb = steps.batch_size * steps.batches_per_superbatch;
 
-        let (sender, receiver) = mpsc::sync_channel::<D::PreparedData>(settings.batch_queue_size);
+        let micro_batches = settings.micro_batches;
+        assert!(micro_batches > 0, "Must have at least one micro-batch per batch!");
+        assert_eq!(steps.batch_size % micro_batches, 0, "Batch size must be divisible by the number of micro-batches!");
 
-        let dataloader =
-            preparer::create_dataloader(preparer.clone(), sender, steps, schedule.wdl_scheduler.clone(), threads);
+        let mut preparer = preparer.clone();
+        let mut test_preparer = test_preparer.clone();
 
         let mut validation_freq = settings.test_set.map_or(32, |test| test.freq);
 
@@ -102,15 +137,72 @@ pub trait NetworkTrainer {
             validation_freq = 32;
         }
 
+        let mut test_steps = schedule.steps_for_validation(validation_freq);
+
+        // a fresh run starting partway through the schedule skips the data it would have used
+        let mut start = LoaderPosition {
+            batches: steps.batches_per_superbatch * micro_batches * (steps.start_superbatch - 1),
+            ..Default::default()
+        };
+        let mut test_start = LoaderPosition {
+            batches: test_steps.batches_per_superbatch * micro_batches * (steps.start_superbatch - 1),
+            ..Default::default()
+        };
+
+        let resume_state = self.take_resume_state();
+        let resume_from = resume_state.as_ref().map(|state| state.superbatch + 1);
+        let mut summary = CheckpointSummary::new(out_dir, &schedule.net_id(), resume_from);
+
+        if let Some(state) = resume_state {
+            state.check_compatible(steps, micro_batches);
+
+            if state.net_id != schedule.net_id {
+                println!("Warning: Resuming run [{}] as [{}]", state.net_id, schedule.net_id);
+            }
+
+            if let Some(seed) = state.seed {
+                preparer.set_shuffle_seed(seed);
+            }
+
+            if let (Some(seed), Some(test_preparer)) = (state.test_seed, &mut test_preparer) {
+                test_preparer.set_shuffle_seed(seed);
+            }
+
+            steps.start_superbatch = state.superbatch + 1;
+            start = state.loader_position;
+            test_start = state.test_position.unwrap_or(test_start);
+            error_record = state.error_record;
+            validation_record = state.validation_record;
+
+            println!("Resuming from superbatch {}", logger::ansi(steps.start_superbatch, logger::num_cs()));
+        }
+
+        let seed = preparer.shuffle_seed();
+        let test_seed = test_preparer.as_ref().and_then(|preparer| preparer.shuffle_seed());
+
+        let (sender, receiver) = mpsc::sync_channel(settings.batch_queue_size);
+
+        let dataloader = preparer::create_dataloader(
+            preparer,
+            sender,
+            steps,
+            start,
+            micro_batches,
+            schedule.wdl_scheduler.clone(),
+            threads,
+        );
+
         let (test_dataloader, test_receiver) = settings
             .test_set
             .map(|_| {
-                let (sender, receiver) = mpsc::sync_channel::<D::PreparedData>(2);
-                let steps = schedule.steps_for_validation(validation_freq);
+                let (sender, receiver) = mpsc::sync_channel(2);
+                test_steps.start_superbatch = steps.start_superbatch;
                 let dataloader = preparer::create_dataloader(
-                    test_preparer.clone().unwrap(),
+                    test_preparer.unwrap(),
                     sender,
-                    steps,
+                    test_steps,
+                    test_start,
+                    micro_batches,
                     schedule.wdl_scheduler.clone(),
                     threads,
                 );
@@ -118,7 +210,7 @@ pub trait NetworkTrainer {
             })
             .unzip();
 
-        let mut prev_lr = schedule.lr(0, 1);
+        let mut prev_lr = schedule.lr(0, steps.start_superbatch);
         let mut superbatch = steps.start_superbatch;
         let mut curr_batch = 0;
         let mut superbatch_timer = Instant::now();
@@ -126,7 +218,11 @@ pub trait NetworkTrainer {
 
         let mut prev32_loss = 0.0;
 
-        while let Ok(prepared_data) = receiver.recv() {
+        let mut test_position = settings.test_set.map(|_| test_start);
+
+        self.on_superbatch_start(superbatch);
+
+        'training: while let Ok((prepared_data, this_position)) = receiver.recv() {
             let lrate = schedule.lr(curr_batch, superbatch);
 
             if lrate != prev_lr {
@@ -135,23 +231,48 @@ pub trait NetworkTrainer {
 
             prev_lr = lrate;
 
-            let this_batch_size = self.load_batch(&prepared_data);
+            util::device_synchronise();
+            self.optimiser_mut().graph_mut().zero_grads();
+
+            let mut this_batch_size = self.load_batch(&prepared_data);
+            let mut error = self.accumulate_gradients();
+            let mut position = this_position;
+
+            for _ in 1..micro_batches {
+                let Ok((prepared_data, this_position)) = receiver.recv() else {
+                    println!("Warning: Dataloader stopped partway through a batch, dropping it");
+                    break 'training;
+                };
+
+                this_batch_size += self.load_batch(&prepared_data);
+                error += self.accumulate_gradients();
+                position = this_position;
+            }
+
             let gf = 1.0 / this_batch_size as f32;
+            self.apply_update(gf, lrate);
 
-            let error = self.train_on_batch(gf, lrate) / this_batch_size as f32;
+            let error = error / this_batch_size as f32;
 
             running_loss += error;
             prev32_loss += error;
 
             // Track test loss every freq batches.
             if curr_batch % validation_freq == 0 {
-                if let Some(Ok(test_batch)) = test_receiver.as_ref().map(Receiver::recv) {
-                    let this_batch_size = self.load_batch(&test_batch);
-                    util::device_synchronise();
-                    let error = self.optimiser_mut().graph_mut().forward();
-                    let error = error / this_batch_size as f32;
+                if let Some(test_receiver) = &test_receiver {
+                    let mut this_batch_size = 0;
+                    let mut error = 0.0;
+
+                    for (test_batch, this_position) in test_receiver.iter().take(micro_batches) {
+                        test_position = Some(this_position);
+                        this_batch_size += self.load_batch(&test_batch);
+                        util::device_synchronise();
+                        error += self.optimiser_mut().graph_mut().forward();
+                    }
 
-                    validation_record.push((superbatch, curr_batch, error));
+                    if this_batch_size > 0 {
+                        validation_record.push((superbatch, curr_batch, error / this_batch_size as f32));
+                    }
                 }
             }
 
@@ -185,19 +306,64 @@ pub trait NetworkTrainer {
                 logger::report_superbatch_finished(superbatch, error, sb_time, total_time, pos_per_sb);
                 logger::report_time_left(steps, superbatch, total_time);
 
+                self.optimiser_mut().apply_deferred_updates();
+
                 if schedule.should_save(superbatch) {
-                    let name = format!("{}-{superbatch}", schedule.net_id());
-                    let out_dir = settings.output_directory;
-                    let path = format!("{out_dir}/{name}");
-                    self.save_to_checkpoint(path.as_str());
+                    let net_id = schedule.net_id();
+                    let name = format!("{net_id}-{superbatch}");
 
-                    write_losses(&format!("{path}/log.txt"), &error_record);
+                    let validation_losses =
+                        validation_record.iter().filter(|(sb, _, _)| *sb == superbatch).map(|(_, _, loss)| *loss);
+                    let (count, sum) = validation_losses.fold((0, 0.0), |(count, sum), loss| (count + 1, sum + loss));
+                    let validation_loss = (count > 0).then(|| sum / count as f32);
 
-                    if settings.test_set.is_some() {
-                        write_losses(&format!("{path}/validation-log.txt"), &validation_record);
+                    let is_best = summary.record(superbatch, error, validation_loss);
+
+                    let mut paths = vec![format!("{out_dir}/{name}")];
+
+                    if is_best {
+                        paths.push(format!("{out_dir}/{net_id}-best"));
+                    }
+
+                    self.on_checkpoint(&net_id, superbatch);
+
+                    for path in &paths {
+                        self.save_to_checkpoint(path.as_str());
+
+                        write_losses(&format!("{path}/log.txt"), &error_record);
+
+                        let state = RunState {
+                            net_id: net_id.clone(),
+                            superbatch,
+                            batch_size: steps.batch_size,
+                            batches_per_superbatch: steps.batches_per_superbatch,
+                            micro_batches,
+                            seed,
+                            test_seed,
+                            loader_position: position,
+                            test_position,
+                            error_record: error_record.clone(),
+                            validation_record: validation_record.clone(),
+                        };
+
+                        state.write_to_file(&format!("{path}/run_state.txt")).expect("Writing run state failed!");
+
+                        if settings.test_set.is_some() {
+                            write_losses(&format!("{path}/validation-log.txt"), &validation_record);
+                        }
+                    }
+
+                    summary.prune(&settings.checkpoint_retention, |sb| format!("{out_dir}/{net_id}-{sb}"));
+
+                    if let Err(e) = summary.write() {
+                        println!("Warning: Failed to write checkpoint summary: {e}");
                     }
 
                     println!("Saved [{}]", logger::ansi(name, 31));
+
+                    if is_best {
+                        println!("Saved [{}] (best validation loss)", logger::ansi(format!("{net_id}-best"), 31));
+                    }
                 }
 
                 callback(superbatch, self, schedule, settings);
@@ -206,6 +372,8 @@ pub trait NetworkTrainer {
                 curr_batch = 0;
                 prev32_loss = 0.0;
                 superbatch_timer = Instant::now();
+
+                self.on_superbatch_start(superbatch);
             }
         }
 
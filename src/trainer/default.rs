@@ -4,9 +4,10 @@ pub mod gamerunner;
 /// as well as several premade input formats that are commonly used.
 pub mod inputs;
 pub mod loader;
-/// Contains the `OutputBuckets` trait for implementing custom output bucket types,
-/// as well as several premade output buckets that are commonly used.
-pub mod outputs;
+/// Contains the reader and writer for Stockfish `.nnue` files.
+pub mod nnue;
+mod prune;
+mod source;
 pub mod testing;
 
 /// Re-exports crates for certain file formats (e.g. Bulletformat)
@@ -16,33 +17,54 @@ pub mod formats {
     pub use sfbinpack;
 }
 
-pub use super::save::{Layout, QuantTarget, SavedFormat};
+pub use super::save::{
+    Clipping, CustomTransform, Layout, QuantOptions, QuantTarget, Rounding, SavedFormat, Transform, TransformContext,
+};
 pub use builder::{Loss, TrainerBuilder};
-
+/// Contains the metadata trailer that can be written after quantised networks.
+pub use bullet_inference::metadata;
+/// Contains the `OutputBuckets` trait for implementing custom output bucket types,
+/// as well as several premade output buckets that are commonly used.
+pub use bullet_inference::outputs;
+pub use bullet_inference::QuantisationReport;
+pub use prune::NeuronStats;
+
+use bullet_inference::{
+    inputs::Features,
+    quantise::{FormatDescription, TensorFormat},
+    Architecture,
+};
 use inputs::SparseInputType;
 use loader::{
     CanBeDirectlySequentiallyLoaded, DataLoader, DefaultDataLoader, DefaultDataPreparer, DirectSequentialDataLoader,
 };
+use metadata::Metadata;
+use nnue::{NnueArch, NnueFile, NnueLayer};
 use outputs::OutputBuckets;
+use prune::{FeatureTransformerOutput, NeuronCounts};
+use source::{NetworkConstant, SourceTensor};
 use testing::{EngineType, TestSettings};
 
 use std::{
     collections::HashSet,
     fs::File,
-    io::{self, Write},
+    io::{self, Read, Write},
 };
 
 use super::{
     logger,
     schedule::{lr::LrScheduler, wdl::WdlScheduler, TrainingSteps},
-    LocalSettings, NetworkTrainer, TrainingSchedule,
+    state::RunState,
+    DataPreparer, LocalSettings, NetworkTrainer, TrainingSchedule,
 };
 
 use crate::{
     autograd::{Graph, Node},
-    optimiser::Optimiser,
+    operations::FakeQuantiseSwitch,
+    optimiser::{self, utils, Optimiser, TransferReport, WeightConstraint, WeightTransfer},
     save,
     tensor::SparseMatrix,
+    Shape,
 };
 
 unsafe impl CanBeDirectlySequentiallyLoaded for bulletformat::ChessBoard {}
@@ -50,6 +72,9 @@ unsafe impl CanBeDirectlySequentiallyLoaded for bulletformat::AtaxxBoard {}
 unsafe impl CanBeDirectlySequentiallyLoaded for bulletformat::chess::CudADFormat {}
 unsafe impl CanBeDirectlySequentiallyLoaded for bulletformat::chess::MarlinFormat {}
 
+/// Maximum number of positions evaluated at once by `Trainer::eval_raw_outputs` and `Trainer::quantisation_report`.
+pub const EVAL_BATCH_SIZE: usize = 16_384;
+
 #[derive(Clone, Copy)]
 pub struct AdditionalTrainerInputs {
     nstm: bool,
@@ -65,8 +90,15 @@ pub struct Trainer<Opt, Inp, Out = outputs::Single> {
     output_node: Node,
     additional_inputs: AdditionalTrainerInputs,
     saved_format: Vec<SavedFormat>,
-    factorised_weights: Option<Vec<String>>,
     sparse_scratch_space: SparseMatrix,
+    resume_state: Option<RunState>,
+    quant_options: QuantOptions,
+    architecture: Option<Architecture<Inp, Out>>,
+    nnue_support: Result<(), String>,
+    fake_quantise: Option<(usize, FakeQuantiseSwitch)>,
+    constants: Vec<NetworkConstant>,
+    metadata: Option<Metadata>,
+    ft_output: Option<FeatureTransformerOutput>,
 }
 
 impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataType>> NetworkTrainer
@@ -116,6 +148,29 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
         &self.optimiser
     }
 
+    fn on_superbatch_start(&mut self, superbatch: usize) {
+        if let Some((start, switch)) = &self.fake_quantise {
+            let enabled = superbatch >= *start;
+
+            if enabled && !switch.is_enabled() {
+                println!("Quantisation-aware training enabled from superbatch {}", logger::ansi(superbatch, 31));
+            }
+
+            switch.set_enabled(enabled);
+        }
+    }
+
+    fn on_checkpoint(&mut self, net_id: &str, superbatch: usize) {
+        if let Some(metadata) = &mut self.metadata {
+            metadata.net_id = net_id.to_string();
+            metadata.superbatch = superbatch;
+        }
+    }
+
+    fn take_resume_state(&mut self) -> Option<RunState> {
+        self.resume_state.take()
+    }
+
     fn optimiser_mut(&mut self) -> &mut Self::Optimiser {
         &mut self.optimiser
     }
@@ -172,8 +227,15 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
             output_node,
             additional_inputs: AdditionalTrainerInputs { nstm, output_buckets, wdl, dense_inputs },
             saved_format,
-            factorised_weights: None,
             sparse_scratch_space: SparseMatrix::default(),
+            resume_state: None,
+            quant_options: QuantOptions::default(),
+            architecture: None,
+            nnue_support: Err(String::from("only networks built by TrainerBuilder are supported")),
+            fake_quantise: None,
+            constants: Vec::new(),
+            metadata: None,
+            ft_output: None,
         }
     }
 
@@ -185,50 +247,76 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
         <Self as NetworkTrainer>::save_to_checkpoint(self, path);
     }
 
+    /// Loads the weights in `path` (e.g. `<checkpoint>/optimiser_state/weights.bin`)
+    /// that match the network, and initialises the weights in `transfers` from ones of
+    /// a different shape, so that a network can be grown from a previous one. Any other
+    /// weights keep their initial values, and what was loaded is printed.
+    pub fn load_weights_partially(&mut self, path: &str, transfers: &[(&str, WeightTransfer)]) -> TransferReport {
+        let report = optimiser::load_weights_partially(self.optimiser.graph_mut(), path, transfers);
+        println!("Loaded weights from [{}]:\n{report}", logger::ansi(path, 31));
+        report
+    }
+
+    /// Loads a checkpoint saved during training, and sets up the next call to
+    /// `run`/`run_and_test` to continue that run from where it stopped, with the
+    /// same data order and loss records. The schedule and settings must match
+    /// the original run, other than `start_superbatch`, which is ignored.
+    pub fn resume(&mut self, path: &str) {
+        let state = RunState::read_from_file(&format!("{path}/run_state.txt"))
+            .unwrap_or_else(|e| panic!("Failed to read run state of [{path}]: {e}"));
+
+        self.load_from_checkpoint(path);
+        self.resume_state = Some(state);
+    }
+
     pub fn eval_raw_output(&mut self, fen: &str) -> Vec<f32>
     where
         Inp::RequiredDataType: std::str::FromStr<Err = String>,
     {
         let pos = format!("{fen} | 0 | 0.0").parse::<Inp::RequiredDataType>().unwrap();
-
-        let prepared = DefaultDataPreparer::prepare(
-            self.input_getter.clone(),
-            self.output_getter,
-            self.additional_inputs.wdl,
-            &[pos],
-            1,
-            1.0,
-            1.0,
-        );
-
-        self.load_batch(&prepared);
-        self.optimiser.graph_mut().forward();
-
-        let eval = self.optimiser.graph().get_node(self.output_node);
-
-        let mut vals = vec![0.0; eval.values.dense().shape().size()];
-        eval.values.dense().write_to_slice(&mut vals);
-        vals
+        self.eval_raw_outputs(&[pos]).pop().unwrap()
     }
 
     pub fn eval(&mut self, fen: &str) -> f32
     where
         Inp::RequiredDataType: std::str::FromStr<Err = String>,
     {
-        let vals = self.eval_raw_output(fen);
+        expected_score(&self.eval_raw_output(fen))
+    }
 
-        match &vals[..] {
-            [mut loss, mut draw, mut win] => {
-                let max = win.max(draw).max(loss);
-                win = (win - max).exp();
-                draw = (draw - max).exp();
-                loss = (loss - max).exp();
+    /// The raw output of the network for each position, as `eval_raw_output`,
+    /// evaluated in batches of up to `EVAL_BATCH_SIZE` positions.
+    pub fn eval_raw_outputs(&mut self, positions: &[Inp::RequiredDataType]) -> Vec<Vec<f32>> {
+        let mut outputs = Vec::with_capacity(positions.len());
 
-                (win + draw / 2.0) / (win + draw + loss)
-            }
-            [score] => *score,
-            _ => panic!("Invalid output size!"),
+        for batch in positions.chunks(EVAL_BATCH_SIZE) {
+            let prepared = DefaultDataPreparer::prepare(
+                self.input_getter.clone(),
+                self.output_getter,
+                self.additional_inputs.wdl,
+                batch,
+                1,
+                1.0,
+                1.0,
+            );
+
+            self.load_batch(&prepared);
+            self.optimiser.graph_mut().forward();
+
+            let eval = self.optimiser.graph().get_node(self.output_node);
+
+            let mut vals = vec![0.0; eval.values.dense().shape().size()];
+            eval.values.dense().write_to_slice(&mut vals);
+
+            outputs.extend(vals.chunks_exact(vals.len() / batch.len()).map(<[f32]>::to_vec));
         }
+
+        outputs
+    }
+
+    /// The output of `eval` for each position, evaluated in batches as `eval_raw_outputs`.
+    pub fn eval_positions(&mut self, positions: &[Inp::RequiredDataType]) -> Vec<f32> {
+        self.eval_raw_outputs(positions).iter().map(|vals| expected_score(vals)).collect()
     }
 
     pub fn profile_all_operations(&mut self) {
@@ -251,50 +339,526 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
         self.optimiser.set_params(params);
     }
 
+    /// See `Optimiser::set_lr_multiplier`.
+    pub fn set_lr_multiplier(&mut self, pattern: &str, multiplier: f32) {
+        self.optimiser.set_lr_multiplier(pattern, multiplier);
+    }
+
+    /// See `Optimiser::add_constraint`.
+    pub fn add_constraint(&mut self, id: &str, constraint: impl WeightConstraint + 'static) {
+        self.optimiser.add_constraint(id, Box::new(constraint));
+    }
+
+    /// See `Optimiser::set_weight_decay_enabled`.
+    pub fn set_weight_decay_enabled(&mut self, pattern: &str, enabled: bool) {
+        self.optimiser.set_weight_decay_enabled(pattern, enabled);
+    }
+
+    /// Sets the rounding and saturation used by `save_quantised`.
+    pub fn set_quant_options(&mut self, options: QuantOptions) {
+        self.quant_options = options;
+    }
+
+    /// Sets the metadata written after the network by `save_quantised`, if any, see `Metadata`.
+    /// The net id, superbatch, size and hash are filled in when saving.
+    pub fn set_metadata(&mut self, metadata: Option<Metadata>) {
+        self.metadata = metadata;
+    }
+
     pub fn save_quantised(&self, path: &str) -> io::Result<()> {
         let mut file = File::create(path).unwrap();
 
         let mut buf = Vec::new();
 
-        for SavedFormat { id, quant, layout } in &self.saved_format {
-            let weights = self.optimiser.graph().get_weights(id);
-            let weights = weights.values.dense();
+        for tensor in self.quantised_tensors()? {
+            buf.extend_from_slice(&tensor.bytes);
+        }
 
-            let mut weight_buf = vec![0.0; weights.shape().size()];
-            let written = weights.write_to_slice(&mut weight_buf);
-            assert_eq!(written, weights.shape().size());
+        let weights_size = buf.len();
+        save::pad(&mut buf);
 
-            if let Some(factorised) = &self.factorised_weights {
-                if factorised.contains(id) {
-                    assert!(self.input_getter.is_factorised(), "Attempting to merge in unfactorised weights!");
-                    weight_buf = self.input_getter.merge_factoriser(weight_buf);
+        if let Some(metadata) = &self.metadata {
+            let metadata =
+                Metadata { weights_size, hash: bullet_inference::fnv1a(&buf[..weights_size]), ..metadata.clone() };
+            metadata.write_trailer(&mut buf);
+        }
 
-                    if *layout == Layout::Transposed {
-                        unimplemented!(
-                            "Transposing post-factoriser merge is not currently supported - why do you want to do this?"
-                        );
-                    }
-                }
+        file.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// Saves the network as a C/C++ header, with an array for each tensor in the same
+    /// layout and quantisation as `save_quantised`, and `#define`s for the layer sizes,
+    /// bucket counts and quantisation factors given to `TrainerBuilder`. Every name is
+    /// prefixed with `prefix`, in upper case, e.g. `MYNET_L0W`, so it must be a C identifier
+    /// starting with a letter. Fails if a float tensor contains NaN or infinity.
+    pub fn save_c_header(&self, path: &str, prefix: &str) -> io::Result<()> {
+        let header = source::c_header(prefix, &self.constants, &self.quantised_tensors()?)?;
+        File::create(path)?.write_all(header.as_bytes())
+    }
+
+    /// Saves the network as a Rust module, with a static array for each tensor in the
+    /// same layout and quantisation as `save_quantised`, and constants as in `save_c_header`.
+    pub fn save_rust_module(&self, path: &str) -> io::Result<()> {
+        let module = source::rust_module(&self.constants, &self.quantised_tensors()?)?;
+        File::create(path)?.write_all(module.as_bytes())
+    }
+
+    /// Writes a description of how `save_quantised` saves the network, so that `bullet-utils quantise`
+    /// can quantise a checkpoint of it without this program. It can be edited to try other quantisations.
+    /// Transforms other than merging the factoriser are not supported.
+    pub fn save_format_description(&self, path: &str) -> io::Result<()> {
+        let mut description = FormatDescription { options: self.quant_options, ..Default::default() };
+
+        if self.saved_format.iter().any(SavedFormat::is_factorised) {
+            // with one row, each merged input is its own weight plus that of its factoriser input
+            let inputs = self.input_getter.num_inputs();
+            let offset = inputs - self.input_getter.merge_factoriser(vec![0.0; inputs]).len();
+            let probe = (0..inputs).map(|i| if i < offset { (i + 1) as f32 } else { 0.0 }).collect();
+            let map = self.input_getter.merge_factoriser(probe).iter().map(|&x| x as i64 - 1).collect();
+            description.factoriser = Some((offset, map));
+        }
+
+        for format in &self.saved_format {
+            if format.transforms.iter().any(|transform| !matches!(transform, Transform::MergeFactoriser)) {
+                let msg = format!("Cannot describe the transforms of [{}]!", format.id);
+                return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
             }
 
+            description.tensors.push(TensorFormat {
+                id: format.id.clone(),
+                quant: format.quant,
+                transposed: format.layout == Layout::Transposed,
+                merge: format.is_factorised(),
+            });
+        }
+
+        File::create(path)?.write_all(description.to_string().as_bytes())
+    }
+
+    /// Each saved tensor, transformed and quantised as in `quantised.bin`.
+    fn quantised_tensors(&self) -> io::Result<Vec<SourceTensor>> {
+        let mut tensors = Vec::new();
+        let mut clipped = Vec::new();
+
+        for format @ SavedFormat { id, quant, layout, .. } in &self.saved_format {
+            let (shape, mut weight_buf) = self.transformed_weights(id);
+
             if let Layout::Transposed = layout {
-                weight_buf = save::transpose(weights.shape(), &weight_buf);
+                weight_buf = save::transpose(shape, &weight_buf);
             }
 
-            let quantised = quant.quantise(&weight_buf)?;
-            buf.extend_from_slice(&quantised);
+            let bytes = format.quantise_with(&weight_buf, self.quant_options, &mut clipped)?;
+
+            let transposed = *layout == Layout::Transposed;
+            tensors.push(SourceTensor { id: id.clone(), quant: *quant, shape, transposed, bytes });
+        }
+
+        for (id, clipping) in clipped {
+            println!(
+                "Warning: Clipped {} values of [{id}] when quantising, by up to {}",
+                clipping.count, clipping.max_excess
+            );
+        }
+
+        Ok(tensors)
+    }
+
+    /// Saves the network in the Stockfish `.nnue` format, with the LEB128 compressed
+    /// feature transformer. `feature_hash` is the engine's hash of the input features,
+    /// e.g. `nnue::HALFKA_V2_HM_HASH`. Each output bucket becomes a layer stack.
+    ///
+    /// Layer weights are written as `i8` and biases as `i32`, with the same quantisations
+    /// as `save_quantised`, so they must fit, and the layer weights must be saved as `I8`.
+    /// The PSQT subnet, if there is one, is added to the output, so it is quantised by the
+    /// factor of the last layer's bias. The file has PSQT weights for each output bucket,
+    /// so the one subnet is repeated in each of them.
+    ///
+    /// Only networks built by `TrainerBuilder` with a dual perspective feature transformer,
+    /// activated by a CReLU and then a pairwise mul, followed by affine layers with a CReLU
+    /// between each pair, can be saved, as this is how Stockfish evaluates them. Others
+    /// fail with `InvalidInput`.
+    pub fn save_nnue(&self, path: &str, description: &str, feature_hash: u32) -> io::Result<()> {
+        let (arch, nnue) = self.to_nnue(description, feature_hash)?;
+
+        let mut buf = Vec::new();
+        nnue.write(&arch, true, &mut buf)?;
+
+        File::create(path)?.write_all(&buf)
+    }
+
+    /// Exports the network (up to the output, without the loss) to an ONNX model, see `Graph::export_onnx`.
+    pub fn export_onnx(&self, path: &str) -> io::Result<()> {
+        self.optimiser.graph().export_onnx(self.output_node, path)
+    }
+
+    /// Reads the `.nnue` file at `path` and checks that it matches the current weights,
+    /// as saved by `save_nnue`.
+    pub fn verify_nnue(&self, path: &str, feature_hash: u32) -> io::Result<()> {
+        let (arch, expected) = self.to_nnue("", feature_hash)?;
+
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        let found = NnueFile::read(&arch, true, &mut bytes.as_slice())
+            .map_err(|e| io::Error::new(e.kind(), format!("[{path}]: {e}")))?;
+
+        let mismatch = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("[{path}]: {what} differ!"));
+
+        if found.ft_weights != expected.ft_weights || found.ft_biases != expected.ft_biases {
+            return Err(mismatch("Feature transformer weights"));
+        }
+
+        if found.psqt_weights != expected.psqt_weights {
+            return Err(mismatch("PSQT weights"));
+        }
+
+        if found.layer_stacks != expected.layer_stacks {
+            return Err(mismatch("Layer weights"));
+        }
+
+        Ok(())
+    }
+
+    fn to_nnue(&self, description: &str, feature_hash: u32) -> io::Result<(NnueArch, NnueFile)> {
+        // this also rules out single perspective networks, and those without any layers
+        self.nnue_support
+            .as_ref()
+            .map_err(|msg| io::Error::new(io::ErrorKind::InvalidInput, format!("Cannot save as .nnue: {msg}!")))?;
+
+        let graph = self.optimiser.graph();
+        let quant = |id: &str| self.saved_format(id).quant;
+
+        // quantised exactly as when saving, and read back as integers
+        let ints = |id: &str, quant: QuantTarget| {
+            quant
+                .quantise_with(&self.transformed_weights(id).1, self.quant_options)
+                .and_then(|(bytes, _)| quant.integers(&bytes))
+                .map_err(|e| io::Error::new(e.kind(), format!("[{id}]: {e}")))
+        };
+
+        let quant_factor = |id: &str| {
+            quant(id).factor().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("[{id}] is saved as floats, not integers!"))
+            })
+        };
+
+        let ft_quant = QuantTarget::I16(quant_factor("l0w")? as i16);
+        let ft_weights = ints("l0w", ft_quant)?;
+        let ft_biases = ints("l0b", ft_quant)?;
+
+        let ft_outputs = ft_biases.len();
+        let ft_inputs = ft_weights.len() / ft_outputs;
+
+        let buckets = Out::BUCKETS;
+        let mut arch = NnueArch {
+            feature_hash,
+            ft_inputs,
+            ft_outputs,
+            psqt_buckets: buckets,
+            layer_stacks: buckets,
+            layers: Vec::new(),
+        };
+        let mut layer_stacks = vec![Vec::new(); buckets];
+
+        for layer in 1.. {
+            let (w, b) = (format!("l{layer}w"), format!("l{layer}b"));
+
+            if !graph.weight_ids().contains(&w) {
+                break;
+            }
+
+            let shape = graph.get_weights(&w).values.shape();
+            let (inputs, outputs) = (shape.cols(), shape.rows() / buckets);
+            arch.layers.push((inputs, outputs));
+
+            let QuantTarget::I8(factor) = quant(&w) else {
+                let msg = format!("[{w}]: The .nnue format stores layer weights as i8, so they must be saved as I8!");
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            };
+
+            let weights = ints(&w, QuantTarget::I8(factor))?;
+            let biases = ints(&b, QuantTarget::I32(quant_factor(&b)? as i32))?;
+
+            for (bucket, stack) in layer_stacks.iter_mut().enumerate() {
+                let rows = bucket * outputs..(bucket + 1) * outputs;
+
+                stack.push(NnueLayer {
+                    biases: biases[rows.clone()].iter().map(|&x| x as i32).collect(),
+                    weights: rows
+                        .flat_map(|row| {
+                            let weights = &weights;
+                            (0..nnue::padded_inputs(inputs)).map(move |col| {
+                                if col < inputs {
+                                    weights[shape.rows() * col + row] as i8
+                                } else {
+                                    0
+                                }
+                            })
+                        })
+                        .collect(),
+                });
+            }
+        }
+
+        // the PSQT subnet is in the units of the output
+        let last = arch.layers.len();
+        let psqt = if graph.weight_ids().iter().any(|id| id == "pst") {
+            let b = format!("l{last}b");
+            ints("pst", QuantTarget::I32(quant_factor(&b)? as i32))?
+        } else {
+            vec![0; ft_inputs]
+        };
+
+        let nnue = NnueFile {
+            description: description.to_string(),
+            ft_biases: ft_biases.into_iter().map(|x| x as i16).collect(),
+            ft_weights: ft_weights.into_iter().map(|x| x as i16).collect(),
+            psqt_weights: psqt.into_iter().flat_map(|x| std::iter::repeat_n(x as i32, buckets)).collect(),
+            layer_stacks,
+        };
+
+        Ok((arch, nnue))
+    }
+
+    fn saved_format(&self, id: &str) -> &SavedFormat {
+        self.saved_format.iter().find(|format| format.id == id).unwrap_or_else(|| panic!("No saved format for [{id}]!"))
+    }
+
+    fn weight_values(&self, id: &str) -> Vec<f32> {
+        let weights = self.optimiser.graph().get_weights(id);
+        let weights = weights.values.dense();
+
+        let mut weight_buf = vec![0.0; weights.shape().size()];
+        let written = weights.write_to_slice(&mut weight_buf);
+        assert_eq!(written, weights.shape().size());
+
+        weight_buf
+    }
+
+    /// The shape and values of the weights `id` after the transforms in its saved format.
+    fn transformed_weights(&self, id: &str) -> (Shape, Vec<f32>) {
+        let shape = self.optimiser.graph().get_weights(id).values.shape();
+
+        let merge_factoriser = |values| {
+            assert!(self.input_getter.is_factorised(), "Attempting to merge in unfactorised weights!");
+            self.input_getter.merge_factoriser(values)
+        };
+
+        self.saved_format(id).apply_transforms(
+            shape,
+            self.weight_values(id),
+            &|id| self.weight_values(id),
+            &merge_factoriser,
+        )
+    }
+
+    /// Evaluates the first `positions` positions of `data_loader` (in batches of up to
+    /// `EVAL_BATCH_SIZE`) with the float network and with the quantised network in integer
+    /// arithmetic, as `bullet_inference` does, and reports the difference in eval (scaled by
+    /// `eval_scale`) and any integer overflows. Only supports networks built by `TrainerBuilder`
+    /// with the built-in input types and `i8` or `i16` quantisations, and fails with
+    /// `InvalidInput` otherwise.
+    pub fn quantisation_report<D: DataLoader<Inp::RequiredDataType>>(
+        &mut self,
+        data_loader: &D,
+        positions: usize,
+        eval_scale: f32,
+    ) -> io::Result<QuantisationReport>
+    where
+        Inp: Features<Position = Inp::RequiredDataType>,
+    {
+        let unsupported = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_string());
+
+        let arch = self
+            .architecture
+            .clone()
+            .ok_or_else(|| unsupported("Only networks built by TrainerBuilder are supported!"))?;
+
+        let mut bytes = self.quantised_tensors()?.into_iter().flat_map(|tensor| tensor.bytes).collect::<Vec<_>>();
+        save::pad(&mut bytes);
+
+        let network = arch.read_quantised(&bytes)?;
+
+        if !network.supports_integer() {
+            return Err(unsupported("Integer inference requires i8 or i16 quantisations!"));
         }
 
-        let bytes = buf.len() % 64;
-        if bytes > 0 {
-            let chs = [b'b', b'u', b'l', b'l', b'e', b't'];
+        if positions == 0 {
+            return Err(unsupported("Need at least one position!"));
+        }
+
+        let mut report = QuantisationReport::default();
+        let mut remaining = positions;
+
+        data_loader.map_batches(0, EVAL_BATCH_SIZE.min(positions), |batch| {
+            let batch = &batch[..batch.len().min(remaining)];
+            remaining -= batch.len();
 
-            for i in 0..64 - bytes {
-                buf.push(chs[i % chs.len()]);
+            let prepared = DefaultDataPreparer::prepare(
+                self.input_getter.clone(),
+                self.output_getter,
+                self.additional_inputs.wdl,
+                batch,
+                1,
+                1.0,
+                eval_scale,
+            );
+
+            self.load_batch(&prepared);
+            self.optimiser.graph_mut().forward();
+
+            let output = self.optimiser.graph().get_node(self.output_node);
+            let output = output.values.dense();
+            let mut floats = vec![0.0; output.shape().size()];
+            output.write_to_slice(&mut floats);
+            assert_eq!(floats.len(), prepared.batch_size, "Network must have a single output!");
+
+            for (pos, &float) in batch.iter().zip(&floats) {
+                let (eval, ranges) = network.eval_with_ranges(pos);
+
+                // an engine truncates the scaled eval
+                report.record(f64::from(float * eval_scale), f64::from(eval[0] * eval_scale).trunc(), &ranges);
             }
+
+            remaining == 0
+        });
+
+        println!("{}", logger::ansi("Quantisation Report", "34;1"));
+        println!("{report}");
+
+        Ok(report)
+    }
+
+    /// Runs the network over `positions` positions from `data_loader`, in batches of up to
+    /// `EVAL_BATCH_SIZE`, and measures how often each neuron of the feature transformer is
+    /// active or saturated, for `save_pruned`.
+    /// Only supports networks built by `TrainerBuilder`.
+    pub fn neuron_stats<D: DataLoader<Inp::RequiredDataType>>(
+        &mut self,
+        data_loader: &D,
+        positions: usize,
+    ) -> NeuronStats {
+        let ft = self.ft_output.expect("Neuron statistics are only supported for networks built by TrainerBuilder!");
+        assert!(positions > 0, "Need at least one position!");
+
+        let mut counts = NeuronCounts::new(&ft);
+        let mut remaining = positions;
+
+        data_loader.map_batches(0, EVAL_BATCH_SIZE.min(positions), |batch| {
+            let batch = &batch[..batch.len().min(remaining)];
+            remaining -= batch.len();
+
+            let prepared = DefaultDataPreparer::prepare(
+                self.input_getter.clone(),
+                self.output_getter,
+                self.additional_inputs.wdl,
+                batch,
+                1,
+                1.0,
+                1.0,
+            );
+
+            self.load_batch(&prepared);
+            self.optimiser.graph_mut().forward();
+
+            let values = self.optimiser.graph().get_node(ft.node);
+            let values = values.values.dense();
+            let mut buf = vec![0.0; values.shape().size()];
+            values.write_to_slice(&mut buf);
+
+            counts.record(&ft, &buf);
+
+            remaining == 0
+        });
+
+        let stats = counts.finish(&ft);
+
+        println!("{}", logger::ansi("Feature Transformer Neurons", "34;1"));
+        println!("{stats}");
+
+        stats
+    }
+
+    /// Writes the weights to the file `path`, as in a checkpoint's `weights.bin`, with only the
+    /// feature transformer neurons in `order`, e.g. from `NeuronStats::pruned_order`, in that
+    /// order, removing the matching columns of `l1w`. This trainer's network is unchanged, as
+    /// its sizes are fixed when it is built, so this returns the new feature transformer size:
+    /// build the same network with that size and use `load_weights_partially` (with no
+    /// transfers) to load the pruned weights, to save or fine-tune.
+    pub fn save_pruned(&self, path: &str, order: &[usize]) -> io::Result<usize> {
+        let ft = self.ft_output.expect("Pruning is only supported for networks built by TrainerBuilder!");
+
+        let mut seen = vec![false; ft.neurons];
+        for &i in order {
+            assert!(i < ft.neurons && !seen[i], "Invalid neuron {i} in the order!");
+            seen[i] = true;
         }
 
-        file.write_all(&buf)?;
+        let graph = self.optimiser.graph();
+        let mut ids = graph.weight_ids();
+        ids.sort();
+
+        let tensors = ids
+            .iter()
+            .map(|id| ft.prune(utils::CheckpointTensor::from_matrix(id, graph.get_weights(id).values.dense()), order))
+            .collect::<Vec<_>>();
+
+        utils::write_tensors_to_file(&tensors, path)?;
+
+        Ok(ft.ft_size(order))
+    }
+
+    /// Loads a network saved with `save_quantised` (with the same saved format) back into
+    /// the weights, undoing the quantisation, transposition and padding, so it can be trained
+    /// further. A merged factoriser cannot be separated again, so the merged weights are
+    /// loaded into the unfactorised weights, and the factoriser weights are zeroed.
+    /// Other transforms are not supported. A metadata trailer is checked against the weights.
+    pub fn load_quantised(&mut self, path: &str) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("[{path}]: {msg}"));
+
+        if let Some((metadata, start)) = Metadata::read_trailer(&bytes).map_err(|e| invalid(e.to_string()))? {
+            metadata.verify(&bytes).map_err(|e| invalid(e.to_string()))?;
+            bytes.truncate(start);
+        }
+
+        if let Some(format) = self
+            .saved_format
+            .iter()
+            .find(|format| format.transforms.iter().any(|transform| !matches!(transform, Transform::MergeFactoriser)))
+        {
+            let msg = format!("Cannot undo the transforms of [{}]!", format.id);
+            return Err(io::Error::new(io::ErrorKind::Unsupported, msg));
+        }
+
+        let tensors = self
+            .saved_format
+            .iter()
+            .map(|format| {
+                let shape = self.optimiser.graph().get_weights(&format.id).values.shape();
+
+                // a merged factoriser occupies the columns after the factoriser
+                let merged_size = if format.is_factorised() {
+                    self.input_getter.merge_factoriser(vec![0.0; shape.size()]).len()
+                } else {
+                    shape.size()
+                };
+
+                (format, shape, merged_size)
+            })
+            .collect::<Vec<_>>();
+
+        let loaded =
+            save::read_quantised(&tensors, &bytes).map_err(|e| io::Error::new(e.kind(), format!("[{path}]: {e}")))?;
+
+        for (format, weights) in self.saved_format.iter().zip(loaded) {
+            let weights_mut = self.optimiser.graph_mut().get_weights_mut(&format.id).values.dense_mut();
+            weights_mut.load_from_slice(weights_mut.shape(), &weights);
+        }
 
         Ok(())
     }
@@ -321,6 +885,57 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
         Ok(())
     }
 
+    /// Resumes from the most recent complete checkpoint of the run in the output directory,
+    /// if there is one. Returns `false` if that checkpoint is the end of the run.
+    pub fn auto_resume<LR: LrScheduler, WDL: WdlScheduler>(
+        &mut self,
+        schedule: &TrainingSchedule<LR, WDL>,
+        settings: &LocalSettings,
+    ) -> bool {
+        let Some((superbatch, path)) = latest_checkpoint(settings.output_directory, &schedule.net_id) else {
+            println!("No checkpoint found to resume from, starting from scratch");
+            return true;
+        };
+
+        let weights_path = format!("{path}/optimiser_state/weights.bin");
+        let file = utils::read_tensors_from_file(&weights_path)
+            .unwrap_or_else(|e| panic!("Failed to read checkpoint file [{weights_path}]: {e}"));
+
+        // files in the legacy format have no architecture hash, so it is recomputed from their tensors
+        let graph = self.optimiser.graph();
+        let hash = utils::architecture_hash(file.tensors.iter().map(|tensor| (tensor.id.as_str(), tensor.shape)));
+        let expected = utils::graph_architecture_hash(graph);
+
+        if hash != expected {
+            let shapes = graph.weight_ids().into_iter().map(|id| {
+                let shape = graph.get_weights(&id).values.shape();
+                (id, shape)
+            });
+
+            let diff = utils::CheckpointDiff::new(&shapes.collect(), &file.tensors);
+            panic!("Refusing to resume from [{path}]: it does not match the network:\n{diff}");
+        }
+
+        if superbatch >= schedule.steps.end_superbatch {
+            println!("Run already finished at [{}]", logger::ansi(path, 31));
+            return false;
+        }
+
+        println!("Resuming from [{}]", logger::ansi(path.as_str(), 31));
+        self.resume(&path);
+
+        true
+    }
+
+    /// Reinitialises the weights from `seed`, if they have not been changed in any way
+    /// since they were initialised (e.g. loaded from a file, set through `graph_mut`,
+    /// or trained). The data loaders are seeded in `training_preamble`.
+    pub fn seed_run(&mut self, seed: u64) {
+        if !self.optimiser.graph().weights_modified_since_init() {
+            self.optimiser.graph_mut().initialise_weights(Some(seed));
+        }
+    }
+
     pub fn training_preamble<D, D2, LR: LrScheduler, WDL: WdlScheduler>(
         &self,
         schedule: &TrainingSchedule<LR, WDL>,
@@ -338,7 +953,7 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
         schedule.display();
         settings.display();
 
-        let preparer = DefaultDataLoader::new(
+        let mut preparer = DefaultDataLoader::new(
             self.input_getter.clone(),
             self.output_getter,
             self.additional_inputs.wdl,
@@ -346,14 +961,24 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
             data_loader.clone(),
         );
 
+        if let Some(seed) = settings.seed {
+            preparer.set_shuffle_seed(seed);
+        }
+
         let test_preparer = test_loader.as_ref().map(|loader| {
-            DefaultDataLoader::new(
+            let mut preparer = DefaultDataLoader::new(
                 self.input_getter.clone(),
                 self.output_getter,
                 self.additional_inputs.wdl,
                 schedule.eval_scale,
                 loader.clone(),
-            )
+            );
+
+            if let Some(seed) = settings.seed {
+                preparer.set_shuffle_seed(seed);
+            }
+
+            preparer
         });
 
         display_total_positions(data_loader, schedule.steps);
@@ -362,6 +987,27 @@ impl<Opt: Optimiser, Inp: SparseInputType, Out: OutputBuckets<Inp::RequiredDataT
     }
 }
 
+/// Finds the checkpoint `<out_dir>/<net_id>-N` with the highest `N`, that was
+/// completely written (the run state is written last).
+fn latest_checkpoint(out_dir: &str, net_id: &str) -> Option<(usize, String)> {
+    let prefix = format!("{net_id}-");
+
+    std::fs::read_dir(out_dir)
+        .ok()?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let superbatch = name.strip_prefix(&prefix)?.parse::<usize>().ok()?;
+            let path = format!("{out_dir}/{name}");
+
+            let state = RunState::read_from_file(&format!("{path}/run_state.txt")).ok()?;
+            let complete = state.superbatch == superbatch
+                && std::path::Path::new(&format!("{path}/optimiser_state/weights.bin")).exists();
+
+            complete.then_some((superbatch, path))
+        })
+        .max_by_key(|(superbatch, _)| *superbatch)
+}
+
 fn display_total_positions<T, D: DataLoader<T>>(data_loader: &D, steps: TrainingSteps) {
     if let Some(num) = data_loader.count_positions() {
         let pos_per_sb = steps.batch_size * steps.batches_per_superbatch;
@@ -384,6 +1030,14 @@ where
         settings: &LocalSettings,
         data_loader: &D,
     ) {
+        if let Some(seed) = settings.seed {
+            self.seed_run(seed);
+        }
+
+        if settings.auto_resume && !self.auto_resume(schedule, settings) {
+            return;
+        }
+
         let test_loader = settings.test_set.map(|test| DirectSequentialDataLoader::new(&[test.path]));
         let (preparer, test_preparer) = self.training_preamble(schedule, settings, data_loader, &test_loader);
 
@@ -397,6 +1051,14 @@ where
         data_loader: &D,
         testing: &TestSettings<T>,
     ) {
+        if let Some(seed) = settings.seed {
+            self.seed_run(seed);
+        }
+
+        if settings.auto_resume && !self.auto_resume(schedule, settings) {
+            return;
+        }
+
         let test_loader = settings.test_set.map(|test| DirectSequentialDataLoader::new(&[test.path]));
         let (preparer, test_preparer) = self.training_preamble(schedule, settings, data_loader, &test_loader);
 
@@ -422,3 +1084,20 @@ where
 }
 
 type PairedLoaders<Inp, Out, D, D2> = (DefaultDataLoader<Inp, Out, D>, Option<DefaultDataLoader<Inp, Out, D2>>);
+
+/// The expected score from the raw output of a network, which is either a single
+/// logit or the logits of a loss, draw and win.
+fn expected_score(vals: &[f32]) -> f32 {
+    match vals {
+        [mut loss, mut draw, mut win] => {
+            let max = win.max(draw).max(loss);
+            win = (win - max).exp();
+            draw = (draw - max).exp();
+            loss = (loss - max).exp();
+
+            (win + draw / 2.0) / (win + draw + loss)
+        }
+        [score] => *score,
+        _ => panic!("Invalid output size!"),
+    }
+}
@@ -1,18 +1,24 @@
+use bullet_inference::{quantise, Architecture};
+
 use crate::{
+    autograd::Node,
     default::{Layout, SavedFormat},
-    frontend::NetworkBuilder,
+    frontend::{Affine, NetworkBuilder},
     logger,
     nn::InitSettings,
-    optimiser::{self, Optimiser, OptimiserType},
-    rng,
+    operations::{FakeQuantise, FakeQuantiseSwitch},
+    optimiser::{self, Optimiser, OptimiserType, WeightConstraint},
     tensor::SparseMatrix,
-    trainer::save::QuantTarget,
+    trainer::save::{QuantOptions, QuantTarget, Rounding, Transform},
     Activation, ExecutionContext, Shape,
 };
 
 use super::{
     inputs::SparseInputType,
+    metadata::Metadata,
     outputs::{self, OutputBuckets},
+    prune::FeatureTransformerOutput,
+    source::NetworkConstant,
     AdditionalTrainerInputs, Trainer,
 };
 
@@ -41,11 +47,17 @@ pub struct TrainerBuilder<T, U = outputs::Single, O = optimiser::AdamW> {
     ft_out_size: usize,
     nodes: Vec<NodeType>,
     quantisations: Option<Vec<QuantTarget>>,
+    quant_options: QuantOptions,
+    fake_quantise_from: Option<usize>,
+    save_metadata: bool,
     perspective: bool,
     loss: Loss,
     optimiser: O,
     psqt_subnet: bool,
     allow_transpose: bool,
+    lr_multipliers: Vec<(String, f32)>,
+    no_decay: Vec<String>,
+    constraints: Vec<(String, Box<dyn WeightConstraint>)>,
 }
 
 impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType> Default for TrainerBuilder<T, U, O> {
@@ -56,11 +68,17 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
             ft_out_size: 0,
             nodes: Vec::new(),
             quantisations: None,
+            quant_options: QuantOptions::default(),
+            fake_quantise_from: None,
+            save_metadata: false,
             perspective: true,
             loss: Loss::None,
             optimiser: O::default(),
             psqt_subnet: false,
             allow_transpose: true,
+            lr_multipliers: Vec::new(),
+            no_decay: Vec::new(),
+            constraints: Vec::new(),
         }
     }
 }
@@ -89,6 +107,27 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
         self
     }
 
+    /// Scales the learning rate of every weight matching `pattern`, which is
+    /// either a weight id (e.g. `"l0w"`) or a prefix followed by `*` (e.g. `"l0*"`).
+    pub fn lr_multiplier(mut self, pattern: &str, multiplier: f32) -> Self {
+        self.lr_multipliers.push((pattern.to_string(), multiplier));
+        self
+    }
+
+    /// Disables weight decay for every weight matching `pattern`, which is
+    /// either a weight id (e.g. `"l0b"`) or a prefix followed by `*` (e.g. `"pst*"`).
+    pub fn disable_weight_decay(mut self, pattern: &str) -> Self {
+        self.no_decay.push(pattern.to_string());
+        self
+    }
+
+    /// Applies `constraint` to the weights `id` after every optimiser step,
+    /// e.g. `constraint("l1w", RowMaxNorm { norm: Norm::L1, max: 1.9 })`.
+    pub fn constraint(mut self, id: &str, constraint: impl WeightConstraint + 'static) -> Self {
+        self.constraints.push((id.to_string(), Box::new(constraint)));
+        self
+    }
+
     /// Sets the input featureset.
     pub fn input(mut self, input_getter: T) -> Self {
         assert!(self.input_getter.is_none(), "Cannot set the input features more than once!");
@@ -109,6 +148,34 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
         self
     }
 
+    /// Sets the rounding and saturation used when saving quantised networks,
+    /// e.g. `QuantOptions { rounding: Rounding::Nearest, saturate: true }`.
+    pub fn quant_options(mut self, options: QuantOptions) -> Self {
+        self.quant_options = options;
+        self
+    }
+
+    /// Enables quantisation-aware training from `superbatch` onwards: the weights, biases and
+    /// feature transformer activations are fake quantised according to the quantisations (and
+    /// rounding in `quant_options`) as they are used in training, so that the float network
+    /// matches the quantised one. Before `superbatch` the fake quantisation is skipped, so it
+    /// costs nothing.
+    ///
+    /// With factorised inputs, `l0w` is fake quantised before the factoriser is merged into it,
+    /// whereas `save_quantised` quantises the merged weights, so each feature transformer weight
+    /// of the saved network may still differ from the one trained with by a quantisation step.
+    pub fn quantisation_aware_from(mut self, superbatch: usize) -> Self {
+        self.fake_quantise_from = Some(superbatch);
+        self
+    }
+
+    /// Writes a `Metadata` trailer after quantised networks and their padding, with the
+    /// architecture, inputs, quantisations and checkpoint.
+    pub fn save_metadata(mut self) -> Self {
+        self.save_metadata = true;
+        self
+    }
+
     /// Provide a list of quantisations.
     pub fn advanced_quantisations(mut self, quants: &[QuantTarget]) -> Self {
         assert!(self.quantisations.is_none(), "Quantisations already set!");
@@ -188,12 +255,75 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
         self
     }
 
+    /// The same network described for `bullet_inference`, to evaluate the quantised network.
+    fn inference_architecture(&self, input: T) -> Architecture<T, U> {
+        let mut arch = Architecture::default().input(input).output_buckets(self.bucket_getter);
+
+        if !self.perspective {
+            arch = arch.single_perspective();
+        }
+
+        if let Some(quants) = &self.quantisations {
+            arch = arch.advanced_quantisations(quants);
+        }
+
+        if self.psqt_subnet {
+            arch = arch.psqt_subnet();
+        }
+
+        if !self.allow_transpose {
+            arch = arch.disallow_transpose_in_quantised_network();
+        }
+
+        arch = arch.feature_transformer(self.ft_out_size);
+
+        for node in &self.nodes {
+            arch = match node.op {
+                OpType::Activate(activation) => arch.activate(activation),
+                OpType::Affine => arch.add_layer(node.size),
+                OpType::PairwiseMul => arch.add_pairwise_mul(),
+            };
+        }
+
+        arch
+    }
+
+    /// Whether the network can be saved in the `.nnue` format, i.e. it has a dual perspective
+    /// feature transformer with a pairwise multiplied clipped ReLU, followed by affine layers
+    /// with a clipped ReLU between each pair, and if not, why.
+    fn nnue_support(&self) -> Result<(), String> {
+        if !self.perspective {
+            return Err(String::from("the feature transformer must be dual perspective"));
+        }
+
+        let ops = self.nodes.iter().map(|node| node.op).collect::<Vec<_>>();
+
+        let Some(layers) = ops.strip_prefix(&[OpType::Activate(Activation::CReLU), OpType::PairwiseMul]) else {
+            return Err(String::from("the feature transformer must be followed by a CReLU and a pairwise mul"));
+        };
+
+        let Some((OpType::Affine, hidden)) = layers.split_first() else {
+            return Err(String::from("there must be at least one layer after the feature transformer"));
+        };
+
+        if hidden.chunks(2).any(|pair| pair != [OpType::Activate(Activation::CReLU), OpType::Affine]) {
+            return Err(String::from(
+                "each layer after the first must follow a CReLU, and the output cannot be activated",
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn build(self) -> Trainer<O::Optimiser, T, U> {
         let builder = NetworkBuilder::default();
 
         let output_buckets = U::BUCKETS > 1;
 
-        let input_getter = self.input_getter.expect("Need to set the input features!");
+        let nnue_support = self.nnue_support();
+
+        let input_getter = self.input_getter.clone().expect("Need to set the input features!");
+        let architecture = self.inference_architecture(input_getter.clone());
 
         let input_size = input_getter.num_inputs();
         let input_shape = Shape::new(input_size, 1);
@@ -218,8 +348,6 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
 
         let l0 = builder.new_affine("l0", input_size, self.ft_out_size);
 
-        let mut net_quant = 1i16;
-
         //let input_buckets = self.input_getter.buckets();
         let mut ft_desc = format!("{} -> {}", input_getter.shorthand(), self.ft_out_size);
 
@@ -229,9 +357,19 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
 
         let mut out = builder.new_input("stm", input_shape);
 
+        // the factoriser is merged into the weights with a column per input
+        let factorised = input_getter.is_factorised();
+        let merge_factoriser = |format: SavedFormat| {
+            if factorised {
+                format.transform(Transform::MergeFactoriser)
+            } else {
+                format
+            }
+        };
+
         let pst = if self.psqt_subnet {
             let pst = builder.new_weights("pst", Shape::new(1, input_size), InitSettings::Zeroed);
-            saved_format.push(SavedFormat { id: "pst".to_string(), quant: QuantTarget::Float, layout: Layout::Normal });
+            saved_format.push(merge_factoriser(SavedFormat::new("pst", QuantTarget::Float, Layout::Normal)));
             Some(pst.matmul(out))
         } else {
             None
@@ -241,46 +379,66 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
             let w = format!("l{layer}w");
             let b = format!("l{layer}b");
 
-            if let Some(quants) = &self.quantisations {
+            let (w, b) = if let Some(quants) = &self.quantisations {
                 let layout = if self.allow_transpose && layer > 0 && output_buckets {
                     Layout::Transposed
                 } else {
                     Layout::Normal
                 };
 
-                saved_format.push(SavedFormat { id: w, quant: quants[layer], layout });
-
-                match quants[layer] {
-                    QuantTarget::Float => {
-                        net_quant = 1;
-                        saved_format.push(SavedFormat { id: b, quant: QuantTarget::Float, layout: Layout::Normal });
-                    }
-                    QuantTarget::I16(q) => {
-                        net_quant = net_quant.checked_mul(q).expect("Bias quantisation factor overflowed!");
-                        saved_format.push(SavedFormat {
-                            id: b,
-                            quant: QuantTarget::I16(net_quant),
-                            layout: Layout::Normal,
-                        });
-                    }
-                    QuantTarget::I8(q) => {
-                        net_quant = net_quant.checked_mul(q).expect("Bias quantisation factor overflowed!");
-                        saved_format.push(SavedFormat {
-                            id: b,
-                            quant: QuantTarget::I8(net_quant),
-                            layout: Layout::Normal,
-                        });
-                    }
-                    QuantTarget::I32(_) => unimplemented!("i32 quant is not implemented for TrainerBuilder!"),
-                }
+                (
+                    SavedFormat::new(&w, quants[layer], layout),
+                    SavedFormat::new(&b, bias_quant(quants, layer), Layout::Normal),
+                )
             } else {
-                saved_format.push(SavedFormat { id: w, quant: QuantTarget::Float, layout: Layout::Normal });
-                saved_format.push(SavedFormat { id: b, quant: QuantTarget::Float, layout: Layout::Normal });
+                (
+                    SavedFormat::new(&w, QuantTarget::Float, Layout::Normal),
+                    SavedFormat::new(&b, QuantTarget::Float, Layout::Normal),
+                )
+            };
+
+            saved_format.push(if layer == 0 { merge_factoriser(w) } else { w });
+            saved_format.push(b);
+        };
+
+        let fake_quantise = self.fake_quantise_from.map(|start| {
+            assert!(self.quantisations.is_some(), "Quantisation-aware training requires quantisations!");
+            (start, FakeQuantiseSwitch::default())
+        });
+
+        let fake_quantise_node = |node: Node, quant: QuantTarget| match (&fake_quantise, fake_quantise_params(quant)) {
+            (Some((_, switch)), Some((scale, bits))) => {
+                let truncate = self.quant_options.rounding == Rounding::Truncate;
+                let op = FakeQuantise { scale, bits, truncate, switch: Some(switch.clone()) };
+                builder.apply(op, &[node]).node()
             }
+            _ => node,
+        };
+
+        // weights and biases are quantised as they are saved
+        let fake_quantise_affine = |affine: Affine, layer: usize| match &self.quantisations {
+            Some(quants) => Affine {
+                weights: fake_quantise_node(affine.weights, quants[layer]),
+                bias: fake_quantise_node(affine.bias, bias_quant(quants, layer)),
+            },
+            None => affine,
+        };
+
+        // feature transformer activations are quantised by the feature transformer quantisation,
+        // which is squared for SCReLU
+        let fake_quantise_ft_activation = |node: Node, activation: Activation| match (&self.quantisations, activation) {
+            (_, Activation::Identity) | (None, _) => builder.node(node),
+            (Some(quants), Activation::SCReLU) => match fake_quantise_params(quants[0]) {
+                Some((scale, _)) => builder.node(fake_quantise_node(node, QuantTarget::I32((scale * scale) as i32))),
+                None => builder.node(node),
+            },
+            (Some(quants), _) => builder.node(fake_quantise_node(node, quants[0])),
         };
 
         push_saved_format(0);
 
+        let l0 = fake_quantise_affine(l0, 0);
+
         assert!(self.nodes.len() > 1, "Require at least 2 nodes for a working arch!");
 
         let (skip, activation) = if self.perspective {
@@ -295,7 +453,8 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
 
         out = if self.perspective {
             let ntm = builder.new_input("nstm", input_shape);
-            l0.forward_sparse_dual_with_activation(out, ntm, activation)
+            let out = l0.forward_sparse_dual_with_activation(out, ntm, activation);
+            fake_quantise_ft_activation(out.node(), activation)
         } else {
             l0.forward(out)
         };
@@ -304,14 +463,38 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
 
         let mut layer_sizes = Vec::new();
 
+        let mut ft_activation = activation;
+        let mut ft_pairwise = false;
+        let mut ft_output = None;
+
         let mut prev_size = self.ft_out_size * if self.perspective { 2 } else { 1 };
 
         for &NodeType { size, op } in self.nodes.iter().skip(skip) {
             match op {
                 OpType::Activate(activation) => {
                     out = out.activate(activation);
+
+                    if still_in_ft {
+                        out = fake_quantise_ft_activation(out.node(), activation);
+                        ft_activation = activation;
+                    }
                 }
                 OpType::Affine => {
+                    if still_in_ft {
+                        let perspectives = if self.perspective { 2 } else { 1 };
+
+                        ft_output = Some(FeatureTransformerOutput {
+                            node: out.node(),
+                            perspectives,
+                            neurons: prev_size / perspectives,
+                            pairwise: ft_pairwise,
+                            saturation: match ft_activation {
+                                Activation::CReLU | Activation::SCReLU => Some(1.0),
+                                _ => None,
+                            },
+                        });
+                    }
+
                     still_in_ft = false;
                     let raw_size = size * U::BUCKETS;
 
@@ -319,6 +502,8 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
 
                     push_saved_format(layer);
 
+                    let l = fake_quantise_affine(l, layer);
+
                     layer += 1;
 
                     out = l.forward(out);
@@ -331,6 +516,8 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
                     }
                 }
                 OpType::PairwiseMul => {
+                    ft_pairwise |= still_in_ft;
+
                     if still_in_ft && self.perspective {
                         out = out.pairwise_mul_post_affine_dual();
                     } else {
@@ -375,17 +562,40 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
             }
         }
 
-        let factorised_weights = if input_getter.is_factorised() {
-            let mut f = vec!["l0w".to_string()];
+        let merged_inputs = input_getter.merge_factoriser(vec![0.0; input_size]).len();
+        let mut constants = vec![
+            NetworkConstant::Size("INPUTS".to_string(), merged_inputs),
+            NetworkConstant::Size("FT_SIZE".to_string(), self.ft_out_size),
+            NetworkConstant::Size("OUTPUT_BUCKETS".to_string(), U::BUCKETS),
+        ];
 
-            if self.psqt_subnet {
-                f.push("pst".to_string());
-            }
+        for (i, size) in layer_sizes.iter().enumerate() {
+            constants.push(NetworkConstant::Size(format!("L{}_SIZE", i + 1), *size));
+        }
 
-            Some(f)
-        } else {
-            None
-        };
+        for format in &saved_format {
+            let quant = match format.quant {
+                QuantTarget::Float => continue,
+                QuantTarget::I8(q) | QuantTarget::I16(q) => i32::from(q),
+                QuantTarget::I32(q) => q,
+            };
+
+            constants.push(NetworkConstant::Quant(format!("{}_QUANT", format.id.to_uppercase()), quant));
+        }
+
+        let metadata = self.save_metadata.then(|| Metadata {
+            architecture: format!("{ft_desc} -> {output_desc}"),
+            inputs: input_getter.description(),
+            output_buckets: U::BUCKETS,
+            quantisations: constants
+                .iter()
+                .filter_map(|constant| match constant {
+                    NetworkConstant::Quant(name, q) => Some((name.trim_end_matches("_QUANT").to_lowercase(), *q)),
+                    NetworkConstant::Size(..) => None,
+                })
+                .collect(),
+            ..Metadata::default()
+        });
 
         let mut trainer = Trainer {
             optimiser: O::Optimiser::new(graph, Default::default()),
@@ -399,21 +609,39 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
                 dense_inputs: false,
             },
             saved_format: saved_format.clone(),
-            factorised_weights,
             sparse_scratch_space: SparseMatrix::default(),
+            resume_state: None,
+            quant_options: self.quant_options,
+            architecture: Some(architecture),
+            nnue_support,
+            fake_quantise,
+            constants,
+            metadata,
+            ft_output,
         };
 
         let graph = trainer.optimiser.graph_mut();
 
         for l in 0..layer {
-            let w = graph.get_weights_mut(&format!("l{l}w"));
-            let shape = w.values.shape();
-            let stdev = 1.0 / (shape.cols() as f32).sqrt();
-
-            let wv = rng::vec_f32(w.values.shape().size(), 0.0, stdev, true);
-            w.load_from_slice(&wv);
-            let wb = rng::vec_f32(w.values.shape().rows(), 0.0, stdev, true);
-            graph.get_weights_mut(&format!("l{l}b")).load_from_slice(&wb);
+            let shape = graph.get_weights(&format!("l{l}w")).values.shape();
+            let init = InitSettings::Normal { mean: 0.0, stdev: 1.0 / (shape.cols() as f32).sqrt() };
+
+            graph.set_weight_init(&format!("l{l}w"), init);
+            graph.set_weight_init(&format!("l{l}b"), init);
+        }
+
+        graph.initialise_weights(None);
+
+        for (pattern, multiplier) in &self.lr_multipliers {
+            trainer.optimiser.set_lr_multiplier(pattern, *multiplier);
+        }
+
+        for pattern in &self.no_decay {
+            trainer.optimiser.set_weight_decay_enabled(pattern, false);
+        }
+
+        for (id, constraint) in self.constraints {
+            trainer.optimiser.add_constraint(&id, constraint);
         }
 
         logger::clear_colours();
@@ -422,12 +650,12 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
         println!("Inputs                 : {}", input_getter.description());
 
         let num_params = trainer.optimiser.graph().get_num_params();
-        let fmt = if num_params >= 1_000_000 {
-            format!("{:.2}m", num_params as f64 / 1_000_000.0)
-        } else {
-            format!("{:.2}k", num_params as f64 / 1_000.0)
-        };
-        println!("Number of Weights      : {fmt}");
+        println!("Number of Weights      : {}", format_count(num_params));
+
+        if let Some(size) = trainer.optimiser.state_size() {
+            let mb = (size * std::mem::size_of::<f32>()) as f64 / (1024.0 * 1024.0);
+            println!("Optimiser State        : {} ({mb:.2} MB)", format_count(size));
+        }
 
         if input_getter.is_factorised() {
             println!("Factoriser             : Will be merged in quantised network for you");
@@ -467,3 +695,67 @@ impl<T: SparseInputType, U: OutputBuckets<T::RequiredDataType>, O: OptimiserType
         trainer
     }
 }
+
+fn format_count(num: usize) -> String {
+    if num >= 1_000_000 {
+        format!("{:.2}m", num as f64 / 1_000_000.0)
+    } else {
+        format!("{:.2}k", num as f64 / 1_000.0)
+    }
+}
+
+/// The quantisation of the bias of `layer`, which is the product of the
+/// quantisations of the layers up to it, since the last float layer.
+fn bias_quant(quants: &[QuantTarget], layer: usize) -> QuantTarget {
+    quantise::bias_quant(quants, layer).unwrap_or_else(|err| panic!("{err}"))
+}
+
+/// Scale and number of bits to fake quantise to, if not a float.
+fn fake_quantise_params(quant: QuantTarget) -> Option<(f32, u32)> {
+    match quant {
+        QuantTarget::Float => None,
+        QuantTarget::I8(q) => Some((f32::from(q), 8)),
+        QuantTarget::I16(q) => Some((f32::from(q), 16)),
+        QuantTarget::I32(q) => Some((q as f32, 32)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default::inputs::Chess768;
+
+    fn builder() -> TrainerBuilder<Chess768> {
+        TrainerBuilder::default().input(Chess768).feature_transformer(32)
+    }
+
+    #[test]
+    fn nnue_support() {
+        let stockfish = builder().activate(Activation::CReLU).add_pairwise_mul().add_layer(16);
+        let stockfish = stockfish.activate(Activation::CReLU).add_layer(32).activate(Activation::CReLU).add_layer(1);
+        assert!(stockfish.nnue_support().is_ok());
+
+        let single_layer = builder().activate(Activation::CReLU).add_pairwise_mul().add_layer(1);
+        assert!(single_layer.nnue_support().is_ok());
+
+        let not_pairwise = builder().activate(Activation::CReLU).add_layer(1);
+        assert!(not_pairwise.nnue_support().is_err());
+
+        let screlu = builder().activate(Activation::SCReLU).add_pairwise_mul().add_layer(1);
+        assert!(screlu.nnue_support().is_err());
+
+        let no_layers = builder().activate(Activation::CReLU).add_pairwise_mul();
+        assert!(no_layers.nnue_support().is_err());
+
+        let relu_hidden = builder().activate(Activation::CReLU).add_pairwise_mul().add_layer(16);
+        let relu_hidden = relu_hidden.activate(Activation::ReLU).add_layer(1);
+        assert!(relu_hidden.nnue_support().is_err());
+
+        let activated_output = builder().activate(Activation::CReLU).add_pairwise_mul().add_layer(1);
+        assert!(activated_output.activate(Activation::CReLU).nnue_support().is_err());
+
+        let single_perspective = TrainerBuilder::<Chess768>::default().single_perspective().input(Chess768);
+        let single_perspective = single_perspective.feature_transformer(32).activate(Activation::CReLU);
+        assert!(single_perspective.add_pairwise_mul().add_layer(1).nnue_support().is_err());
+    }
+}
@@ -1,51 +1,17 @@
-mod ataxx147;
-mod chess768;
-mod chess_buckets;
-mod chess_buckets_mk;
-mod factorised;
-
 #[allow(deprecated)]
 mod legacy;
 
 use super::loader::LoadableDataType;
 
-pub use ataxx147::{Ataxx147, Ataxx98};
-pub use chess768::Chess768;
-pub use chess_buckets::{ChessBuckets, ChessBucketsMirrored};
-pub use chess_buckets_mk::{ChessBucketsMergedKings, ChessBucketsMergedKingsMirrored};
-pub use factorised::{Factorised, Factorises};
+pub use bullet_inference::inputs::{
+    Ataxx147, Ataxx98, Chess768, ChessBuckets, ChessBucketsFactorised, ChessBucketsMergedKings,
+    ChessBucketsMergedKingsFactorised, ChessBucketsMergedKingsMirrored, ChessBucketsMergedKingsMirroredFactorised,
+    ChessBucketsMirrored, ChessBucketsMirroredFactorised, Factorised, Factorises, Features,
+};
 
 #[allow(deprecated)]
 pub use legacy::InputType;
 
-pub type ChessBucketsFactorised = Factorised<ChessBuckets, Chess768>;
-impl ChessBucketsFactorised {
-    pub fn new(buckets: [usize; 64]) -> Self {
-        Self::from_parts(ChessBuckets::new(buckets), Chess768)
-    }
-}
-
-pub type ChessBucketsMirroredFactorised = Factorised<ChessBucketsMirrored, Chess768>;
-impl ChessBucketsMirroredFactorised {
-    pub fn new(buckets: [usize; 32]) -> Self {
-        Self::from_parts(ChessBucketsMirrored::new(buckets), Chess768)
-    }
-}
-
-pub type ChessBucketsMergedKingsFactorised = Factorised<ChessBucketsMergedKings, Chess768>;
-impl ChessBucketsMergedKingsFactorised {
-    pub fn new(buckets: [usize; 64]) -> Self {
-        Self::from_parts(ChessBucketsMergedKings::new(buckets), Chess768)
-    }
-}
-
-pub type ChessBucketsMergedKingsMirroredFactorised = Factorised<ChessBucketsMergedKingsMirrored, Chess768>;
-impl ChessBucketsMergedKingsMirroredFactorised {
-    pub fn new(buckets: [usize; 32]) -> Self {
-        Self::from_parts(ChessBucketsMergedKingsMirrored::new(buckets), Chess768)
-    }
-}
-
 pub trait SparseInputType: Clone + Send + Sync + 'static {
     type RequiredDataType: LoadableDataType + Send + Sync;
 
@@ -73,10 +39,67 @@ pub trait SparseInputType: Clone + Send + Sync + 'static {
     }
 }
 
-fn get_num_buckets<const N: usize>(arr: &[usize; N]) -> usize {
-    let mut max = 0;
-    for &val in arr {
-        max = max.max(val)
-    }
-    max + 1
+/// The built-in featuresets live in `bullet_inference`, so that they can be evaluated without
+/// the trainer, and are trained with through these impls.
+macro_rules! delegate_to_features {
+    () => {
+        fn num_inputs(&self) -> usize {
+            Features::num_inputs(self)
+        }
+
+        fn max_active(&self) -> usize {
+            Features::max_active(self)
+        }
+
+        fn map_features<F: FnMut(usize, usize)>(&self, pos: &Self::RequiredDataType, f: F) {
+            Features::map_features(self, pos, f)
+        }
+
+        fn shorthand(&self) -> String {
+            Features::shorthand(self)
+        }
+
+        fn description(&self) -> String {
+            Features::description(self)
+        }
+
+        fn is_factorised(&self) -> bool {
+            Features::is_factorised(self)
+        }
+
+        fn merge_factoriser(&self, unmerged: Vec<f32>) -> Vec<f32> {
+            Features::merge_factoriser(self, unmerged)
+        }
+    };
+}
+
+macro_rules! sparse_input_type {
+    ($($ty:ty),* $(,)?) => {$(
+        impl SparseInputType for $ty {
+            type RequiredDataType = <Self as Features>::Position;
+
+            delegate_to_features!();
+        }
+    )*};
+}
+
+sparse_input_type!(
+    Ataxx147,
+    Ataxx98,
+    Chess768,
+    ChessBuckets,
+    ChessBucketsMirrored,
+    ChessBucketsMergedKings,
+    ChessBucketsMergedKingsMirrored,
+);
+
+impl<A, B> SparseInputType for Factorised<A, B>
+where
+    A: Features + Clone + Send + Sync + 'static,
+    B: Factorises<A> + Clone + Send + Sync + 'static,
+    A::Position: LoadableDataType + Send + Sync,
+{
+    type RequiredDataType = A::Position;
+
+    delegate_to_features!();
 }
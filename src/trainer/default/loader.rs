@@ -11,7 +11,10 @@ pub use text::InMemoryTextLoader;
 
 use super::{inputs::SparseInputType, outputs::OutputBuckets};
 
-use crate::{tensor::Shape, trainer::DataPreparer};
+use crate::{
+    tensor::Shape,
+    trainer::{DataPreparer, LoaderPosition},
+};
 
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -48,6 +51,31 @@ pub trait DataLoader<T>: Clone + Send + Sync + 'static {
     }
 
     fn map_batches<F: FnMut(&[T]) -> bool>(&self, start_batch: usize, batch_size: usize, f: F);
+
+    /// As `map_batches`, but starts from `start` and passes the position after
+    /// each batch to `f`. Loaders that shuffle through a buffer should override
+    /// this, as by default resuming skips over every batch before `start`.
+    fn map_batches_from<F: FnMut(&[T], LoaderPosition) -> bool>(
+        &self,
+        start: LoaderPosition,
+        batch_size: usize,
+        mut f: F,
+    ) {
+        let mut batches = start.batches;
+
+        self.map_batches(start.batches, batch_size, |batch| {
+            batches += 1;
+            f(batch, LoaderPosition { batches, ..Default::default() })
+        });
+    }
+
+    /// Seed used for shuffling the data, if the loader shuffles it.
+    /// Together with `start_batch` this should determine the data order.
+    fn shuffle_seed(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_shuffle_seed(&mut self, _seed: u64) {}
 }
 
 #[derive(Clone)]
@@ -86,6 +114,23 @@ where
         self.loader.map_batches(start_batch, batch_size, f);
     }
 
+    fn load_and_map_batches_from<F: FnMut(&[Self::DataType], LoaderPosition) -> bool>(
+        &self,
+        start: LoaderPosition,
+        batch_size: usize,
+        f: F,
+    ) {
+        self.loader.map_batches_from(start, batch_size, f);
+    }
+
+    fn shuffle_seed(&self) -> Option<u64> {
+        self.loader.shuffle_seed()
+    }
+
+    fn set_shuffle_seed(&mut self, seed: u64) {
+        self.loader.set_shuffle_seed(seed);
+    }
+
     fn prepare(&self, data: &[Self::DataType], threads: usize, blend: f32) -> Self::PreparedData {
         DefaultDataPreparer::prepare(
             self.input_getter.clone(),
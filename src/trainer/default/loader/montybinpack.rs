@@ -1,12 +1,13 @@
 use std::{
     fs::File,
-    io::{BufReader, Cursor},
+    io::{BufReader, Cursor, Seek, SeekFrom},
     sync::mpsc::{self, SyncSender},
 };
 
 use crate::{
     default::{formats::bulletformat::ChessBoard, loader::DataLoader},
     rng::SimpleRand,
+    trainer::LoaderPosition,
 };
 use montyformat::{
     chess::{Move, Position},
@@ -19,6 +20,7 @@ pub struct MontyBinpackLoader<T: Fn(&Position, Move, i16, f32) -> bool> {
     buffer_size: usize,
     threads: usize,
     filter: T,
+    seed: u64,
 }
 
 impl<T: Fn(&Position, Move, i16, f32) -> bool> MontyBinpackLoader<T> {
@@ -28,6 +30,7 @@ impl<T: Fn(&Position, Move, i16, f32) -> bool> MontyBinpackLoader<T> {
             buffer_size: buffer_size_mb * 1024 * 1024 / std::mem::size_of::<ChessBoard>() / 2,
             threads,
             filter,
+            seed: SimpleRand::with_seed().rng(),
         }
     }
 }
@@ -44,30 +47,63 @@ where
         None
     }
 
-    fn map_batches<F: FnMut(&[ChessBoard]) -> bool>(&self, _: usize, batch_size: usize, mut f: F) {
+    fn shuffle_seed(&self) -> Option<u64> {
+        Some(self.seed)
+    }
+
+    fn set_shuffle_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    fn map_batches<F: FnMut(&[ChessBoard]) -> bool>(&self, start_batch: usize, batch_size: usize, mut f: F) {
+        let start = LoaderPosition { batches: start_batch, ..Default::default() };
+        self.map_batches_from(start, batch_size, |batch, _| f(batch));
+    }
+
+    /// Positions are given by the byte offset of the first game in the chunk of games
+    /// that the shuffle buffer started in, and the number of positions from that chunk
+    /// which went into the previous buffer.
+    fn map_batches_from<F: FnMut(&[ChessBoard], LoaderPosition) -> bool>(
+        &self,
+        start: LoaderPosition,
+        batch_size: usize,
+        mut f: F,
+    ) {
+        let mut rng = SimpleRand::from_seed(if start.rng == 0 { self.seed } else { start.rng });
         let mut shuffle_buffer = Vec::new();
         shuffle_buffer.reserve_exact(self.buffer_size);
 
         let file_path = self.file_path[0].clone();
         let buffer_size = self.buffer_size;
 
-        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(256);
+        let (sender, receiver) = mpsc::sync_channel::<(u64, Vec<u8>)>(256);
         let (msg_sender, msg_receiver) = mpsc::sync_channel::<bool>(1);
 
-        std::thread::spawn(move || 'dataloading: loop {
-            let mut reader = BufReader::new(File::open(file_path.as_str()).unwrap());
+        std::thread::spawn(move || {
+            let mut offset = start.offset;
+
+            'dataloading: loop {
+                let mut reader = BufReader::new(File::open(file_path.as_str()).unwrap());
+                reader.seek(SeekFrom::Start(offset)).unwrap();
 
-            let mut buffer = Vec::new();
-            while let Ok(()) = MontyValueFormat::deserialise_fast_into_buffer(&mut reader, &mut buffer) {
-                if msg_receiver.try_recv().unwrap_or(false) || sender.send(buffer).is_err() {
-                    break 'dataloading;
+                let mut buffer = Vec::new();
+                while let Ok(()) = MontyValueFormat::deserialise_fast_into_buffer(&mut reader, &mut buffer) {
+                    // the buffer holds exactly the bytes of the game
+                    let len = buffer.len() as u64;
+
+                    if msg_receiver.try_recv().unwrap_or(false) || sender.send((offset, buffer)).is_err() {
+                        break 'dataloading;
+                    }
+
+                    offset += len;
+                    buffer = Vec::new();
                 }
 
-                buffer = Vec::new();
+                offset = 0;
             }
         });
 
-        let (game_sender, game_receiver) = mpsc::sync_channel::<Vec<ChessBoard>>(4 * self.threads);
+        let (game_sender, game_receiver) = mpsc::sync_channel::<(u64, Vec<ChessBoard>)>(4 * self.threads);
         let (game_msg_sender, game_msg_receiver) = mpsc::sync_channel::<bool>(1);
 
         let threads = self.threads;
@@ -75,13 +111,13 @@ where
 
         std::thread::spawn(move || {
             let mut reusable = Vec::new();
-            'dataloading: while let Ok(game_bytes) = receiver.recv() {
+            'dataloading: while let Ok(game) = receiver.recv() {
                 if game_msg_receiver.try_recv().unwrap_or(false) {
                     msg_sender.send(true).unwrap();
                     break 'dataloading;
                 }
 
-                reusable.push(game_bytes);
+                reusable.push(game);
 
                 if reusable.len() % (8192 * threads) == 0 {
                     convert_buffer(threads, &game_sender, &reusable, &filter);
@@ -90,27 +126,44 @@ where
             }
         });
 
-        let (buffer_sender, buffer_receiver) = mpsc::sync_channel::<Vec<ChessBoard>>(0);
+        let (buffer_sender, buffer_receiver) = mpsc::sync_channel::<(Vec<ChessBoard>, LoaderPosition)>(0);
         let (buffer_msg_sender, buffer_msg_receiver) = mpsc::sync_channel::<bool>(1);
 
         std::thread::spawn(move || {
-            'dataloading: while let Ok(game) = game_receiver.recv() {
+            // the first chunk is where the buffer being resumed started
+            let mut to_drop = start.skip as usize;
+            let mut buffer_start = (start.offset, start.skip);
+
+            'dataloading: while let Ok((offset, game)) = game_receiver.recv() {
                 if buffer_msg_receiver.try_recv().unwrap_or(false) {
                     game_msg_sender.send(true).unwrap();
                     break 'dataloading;
                 }
 
+                let dropped = to_drop.min(game.len());
+                let game = &game[dropped..];
+                to_drop -= dropped;
+
+                if shuffle_buffer.is_empty() {
+                    buffer_start = (offset, dropped as u64);
+                }
+
                 if shuffle_buffer.len() + game.len() < shuffle_buffer.capacity() {
-                    shuffle_buffer.extend_from_slice(&game);
+                    shuffle_buffer.extend_from_slice(game);
                 } else {
                     let diff = shuffle_buffer.capacity() - shuffle_buffer.len();
                     if diff > 0 {
                         shuffle_buffer.extend_from_slice(&game[..diff]);
                     }
 
-                    shuffle(&mut shuffle_buffer);
+                    let position =
+                        LoaderPosition { offset: buffer_start.0, skip: buffer_start.1, rng: rng.state(), batches: 0 };
 
-                    if buffer_msg_receiver.try_recv().unwrap_or(false) || buffer_sender.send(shuffle_buffer).is_err() {
+                    shuffle(&mut rng, &mut shuffle_buffer);
+
+                    if buffer_msg_receiver.try_recv().unwrap_or(false)
+                        || buffer_sender.send((shuffle_buffer, position)).is_err()
+                    {
                         game_msg_sender.send(true).unwrap();
                         break 'dataloading;
                     }
@@ -118,13 +171,23 @@ where
                     shuffle_buffer = Vec::new();
                     shuffle_buffer.reserve_exact(buffer_size);
                     shuffle_buffer.extend_from_slice(&game[diff..]);
+                    buffer_start = (offset, (dropped + diff) as u64);
                 }
             }
         });
 
-        'dataloading: while let Ok(shuffle_buffer) = buffer_receiver.recv() {
+        let mut to_skip = start.batches;
+
+        'dataloading: while let Ok((shuffle_buffer, mut position)) = buffer_receiver.recv() {
             for batch in shuffle_buffer.chunks(batch_size) {
-                let should_break = f(batch);
+                position.batches += 1;
+
+                if to_skip > 0 {
+                    to_skip -= 1;
+                    continue;
+                }
+
+                let should_break = f(batch, position);
 
                 if should_break {
                     buffer_msg_sender.send(true).unwrap();
@@ -139,24 +202,31 @@ where
 
 fn convert_buffer<T: Fn(&Position, Move, i16, f32) -> bool + Send + Sync>(
     threads: usize,
-    sender: &SyncSender<Vec<ChessBoard>>,
-    games: &[Vec<u8>],
+    sender: &SyncSender<(u64, Vec<ChessBoard>)>,
+    games: &[(u64, Vec<u8>)],
     filter: &T,
 ) {
     let chunk_size = games.len().div_ceil(threads);
 
     std::thread::scope(|s| {
-        for chunk in games.chunks(chunk_size) {
-            let this_sender = sender.clone();
-            s.spawn(move || {
-                let mut buffer = Vec::new();
+        let handles = games
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let mut buffer = Vec::new();
+
+                    for (_, game_bytes) in chunk {
+                        parse_into_buffer(game_bytes, &mut buffer, filter);
+                    }
 
-                for game_bytes in chunk {
-                    parse_into_buffer(game_bytes, &mut buffer, filter);
-                }
+                    (chunk[0].0, buffer)
+                })
+            })
+            .collect::<Vec<_>>();
 
-                this_sender.send(buffer)
-            });
+        // sent in order so that the data order only depends on the seed
+        for handle in handles {
+            let _ = sender.send(handle.join().unwrap());
         }
     });
 }
@@ -181,9 +251,7 @@ fn parse_into_buffer<T: Fn(&Position, Move, i16, f32) -> bool>(
     }
 }
 
-fn shuffle(data: &mut [ChessBoard]) {
-    let mut rng = SimpleRand::with_seed();
-
+fn shuffle(rng: &mut SimpleRand, data: &mut [ChessBoard]) {
     for i in (0..data.len()).rev() {
         let idx = rng.rng() as usize % (i + 1);
         data.swap(idx, i);
@@ -8,6 +8,7 @@ use sfbinpack::{
 use crate::{
     default::{formats::bulletformat::ChessBoard, loader::DataLoader},
     rng::SimpleRand,
+    trainer::LoaderPosition,
 };
 
 fn convert_to_bulletformat(entry: &TrainingDataEntry) -> ChessBoard {
@@ -43,6 +44,7 @@ pub struct SfBinpackLoader<T: Fn(&TrainingDataEntry) -> bool> {
     buffer_size: usize,
     threads: usize,
     filter: T,
+    seed: u64,
 }
 
 impl<T: Fn(&TrainingDataEntry) -> bool> SfBinpackLoader<T> {
@@ -52,6 +54,7 @@ impl<T: Fn(&TrainingDataEntry) -> bool> SfBinpackLoader<T> {
             buffer_size: buffer_size_mb * 1024 * 1024 / std::mem::size_of::<ChessBoard>() / 2,
             threads,
             filter,
+            seed: SimpleRand::with_seed().rng(),
         }
     }
 }
@@ -68,43 +71,80 @@ where
         None
     }
 
-    fn map_batches<F: FnMut(&[ChessBoard]) -> bool>(&self, _: usize, batch_size: usize, mut f: F) {
+    fn shuffle_seed(&self) -> Option<u64> {
+        Some(self.seed)
+    }
+
+    fn set_shuffle_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    fn map_batches<F: FnMut(&[ChessBoard]) -> bool>(&self, start_batch: usize, batch_size: usize, mut f: F) {
+        let start = LoaderPosition { batches: start_batch, ..Default::default() };
+        self.map_batches_from(start, batch_size, |batch, _| f(batch));
+    }
+
+    /// Positions are given by the index of the first entry in the chunk of entries that
+    /// the shuffle buffer started in, and the number of positions from that chunk which
+    /// went into the previous buffer. Binpacks are compressed in blocks, so resuming has
+    /// to decode the entries before the chunk, but does not convert or shuffle them.
+    fn map_batches_from<F: FnMut(&[ChessBoard], LoaderPosition) -> bool>(
+        &self,
+        start: LoaderPosition,
+        batch_size: usize,
+        mut f: F,
+    ) {
         let file_path = self.file_path[0].clone();
         let buffer_size = self.buffer_size;
         let threads = self.threads;
         let filter = self.filter.clone();
+        let mut rng = SimpleRand::from_seed(if start.rng == 0 { self.seed } else { start.rng });
 
         let reader_buffer_size = 16384 * threads;
-        let (reader_sender, reader_receiver) = mpsc::sync_channel::<Vec<TrainingDataEntry>>(8);
+        let (reader_sender, reader_receiver) = mpsc::sync_channel::<(u64, Vec<TrainingDataEntry>)>(8);
         let (reader_msg_sender, reader_msg_receiver) = mpsc::sync_channel::<bool>(1);
 
         std::thread::spawn(move || {
             let mut buffer = Vec::with_capacity(reader_buffer_size);
+            let mut to_skip = start.offset;
 
             'dataloading: loop {
                 let mut reader = CompressedTrainingDataEntryReader::new(&file_path).unwrap();
+                let mut offset = 0;
+
+                while to_skip > 0 && reader.has_next() {
+                    reader.next();
+                    offset += 1;
+                    to_skip -= 1;
+                }
+
+                let mut buffer_offset = offset;
 
                 while reader.has_next() {
                     buffer.push(reader.next());
+                    offset += 1;
 
                     if buffer.len() == reader_buffer_size || !reader.has_next() {
-                        if reader_msg_receiver.try_recv().unwrap_or(false) || reader_sender.send(buffer).is_err() {
+                        if reader_msg_receiver.try_recv().unwrap_or(false)
+                            || reader_sender.send((buffer_offset, buffer)).is_err()
+                        {
                             break 'dataloading;
                         }
 
                         buffer = Vec::with_capacity(reader_buffer_size);
+                        buffer_offset = offset;
                     }
                 }
             }
         });
 
-        let (converted_sender, converted_receiver) = mpsc::sync_channel::<Vec<ChessBoard>>(4 * threads);
+        let (converted_sender, converted_receiver) = mpsc::sync_channel::<(u64, Vec<ChessBoard>)>(4 * threads);
         let (converted_msg_sender, converted_msg_receiver) = mpsc::sync_channel::<bool>(1);
 
         std::thread::spawn(move || {
             let filter = &filter;
             let mut should_break = false;
-            'dataloading: while let Ok(unfiltered) = reader_receiver.recv() {
+            'dataloading: while let Ok((offset, unfiltered)) = reader_receiver.recv() {
                 if should_break || converted_msg_receiver.try_recv().unwrap_or(false) {
                     reader_msg_sender.send(true).unwrap();
                     break 'dataloading;
@@ -114,8 +154,7 @@ where
                     let chunk_size = unfiltered.len().div_ceil(threads);
                     let mut handles = Vec::new();
 
-                    for chunk in unfiltered.chunks(chunk_size) {
-                        let this_sender = converted_sender.clone();
+                    for (i, chunk) in unfiltered.chunks(chunk_size).enumerate() {
                         let handle = s.spawn(move || {
                             let mut buffer = Vec::with_capacity(chunk_size);
 
@@ -125,14 +164,15 @@ where
                                 }
                             }
 
-                            this_sender.send(buffer).is_err()
+                            (offset + (i * chunk_size) as u64, buffer)
                         });
 
                         handles.push(handle);
                     }
 
+                    // sent in order so that the data order only depends on the seed
                     for handle in handles {
-                        if handle.join().unwrap() {
+                        if !should_break && converted_sender.send(handle.join().unwrap()).is_err() {
                             reader_msg_sender.send(true).unwrap();
                             should_break = true;
                         }
@@ -141,21 +181,39 @@ where
             }
         });
 
-        let (buffer_sender, buffer_receiver) = mpsc::sync_channel::<Vec<ChessBoard>>(0);
+        let (buffer_sender, buffer_receiver) = mpsc::sync_channel::<(Vec<ChessBoard>, LoaderPosition)>(0);
         let (buffer_msg_sender, buffer_msg_receiver) = mpsc::sync_channel::<bool>(1);
 
         std::thread::spawn(move || {
             let mut shuffle_buffer = Vec::with_capacity(buffer_size);
 
-            'dataloading: while let Ok(converted) = converted_receiver.recv() {
-                for entry in converted {
+            // the first chunk is where the buffer being resumed started
+            let mut to_drop = start.skip as usize;
+            let mut buffer_start = (start.offset, start.skip);
+
+            'dataloading: while let Ok((offset, converted)) = converted_receiver.recv() {
+                let dropped = to_drop.min(converted.len());
+                to_drop -= dropped;
+
+                for (i, entry) in converted.into_iter().enumerate().skip(dropped) {
+                    if shuffle_buffer.is_empty() {
+                        buffer_start = (offset, i as u64);
+                    }
+
                     shuffle_buffer.push(entry);
 
                     if shuffle_buffer.len() == buffer_size {
-                        shuffle(&mut shuffle_buffer);
+                        let position = LoaderPosition {
+                            offset: buffer_start.0,
+                            skip: buffer_start.1,
+                            rng: rng.state(),
+                            batches: 0,
+                        };
+
+                        shuffle(&mut rng, &mut shuffle_buffer);
 
                         if buffer_msg_receiver.try_recv().unwrap_or(false)
-                            || buffer_sender.send(shuffle_buffer).is_err()
+                            || buffer_sender.send((shuffle_buffer, position)).is_err()
                         {
                             converted_msg_sender.send(true).unwrap();
                             break 'dataloading;
@@ -167,13 +225,24 @@ where
             }
         });
 
-        let (batch_sender, batch_reciever) = mpsc::sync_channel::<Vec<ChessBoard>>(16);
+        let (batch_sender, batch_reciever) = mpsc::sync_channel::<(Vec<ChessBoard>, LoaderPosition)>(16);
         let (batch_msg_sender, batch_msg_receiver) = mpsc::sync_channel::<bool>(1);
 
         std::thread::spawn(move || {
-            'dataloading: while let Ok(shuffle_buffer) = buffer_receiver.recv() {
+            let mut to_skip = start.batches;
+
+            'dataloading: while let Ok((shuffle_buffer, mut position)) = buffer_receiver.recv() {
                 for batch in shuffle_buffer.chunks(batch_size) {
-                    if batch_msg_receiver.try_recv().unwrap_or(false) || batch_sender.send(batch.to_vec()).is_err() {
+                    position.batches += 1;
+
+                    if to_skip > 0 {
+                        to_skip -= 1;
+                        continue;
+                    }
+
+                    if batch_msg_receiver.try_recv().unwrap_or(false)
+                        || batch_sender.send((batch.to_vec(), position)).is_err()
+                    {
                         buffer_msg_sender.send(true).unwrap();
                         break 'dataloading;
                     }
@@ -181,14 +250,12 @@ where
             }
         });
 
-        'dataloading: while let Ok(inputs) = batch_reciever.recv() {
-            for batch in inputs.chunks(batch_size) {
-                let should_break = f(batch);
+        'dataloading: while let Ok((batch, position)) = batch_reciever.recv() {
+            let should_break = f(&batch, position);
 
-                if should_break {
-                    batch_msg_sender.send(true).unwrap();
-                    break 'dataloading;
-                }
+            if should_break {
+                batch_msg_sender.send(true).unwrap();
+                break 'dataloading;
             }
         }
 
@@ -196,9 +263,7 @@ where
     }
 }
 
-fn shuffle(data: &mut [ChessBoard]) {
-    let mut rng = SimpleRand::with_seed();
-
+fn shuffle(rng: &mut SimpleRand, data: &mut [ChessBoard]) {
     for i in (0..data.len()).rev() {
         let idx = rng.rng() as usize % (i + 1);
         data.swap(idx, i);
@@ -30,13 +30,20 @@ where
         Some(BufReader::new(File::open(&self.file_path[0]).unwrap()).lines().count() as u64)
     }
 
-    fn map_batches<F: FnMut(&[T]) -> bool>(&self, _: usize, batch_size: usize, mut f: F) {
+    fn map_batches<F: FnMut(&[T]) -> bool>(&self, start_batch: usize, batch_size: usize, mut f: F) {
         let file = File::open(&self.file_path[0]).unwrap();
         let reader = BufReader::new(file);
         let data = reader.lines().map(|ln| ln.unwrap().parse::<T>().unwrap()).collect::<Vec<_>>();
 
+        let mut to_skip = start_batch % data.len().div_ceil(batch_size).max(1);
+
         'dataloading: loop {
             for batch in data.chunks(batch_size) {
+                if to_skip > 0 {
+                    to_skip -= 1;
+                    continue;
+                }
+
                 if f(batch) {
                     break 'dataloading;
                 }
@@ -44,3 +51,35 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trainer::LoaderPosition;
+
+    fn collect(loader: &InMemoryTextLoader, start: LoaderPosition, count: usize) -> Vec<(Vec<u32>, LoaderPosition)> {
+        let mut batches = Vec::new();
+
+        loader.map_batches_from(start, 3, |batch: &[u32], position| {
+            batches.push((batch.to_vec(), position));
+            batches.len() == count
+        });
+
+        batches
+    }
+
+    #[test]
+    fn resumes_from_position() {
+        let path = std::env::temp_dir().join(format!("bullet-text-loader-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, (0..10).map(|i| format!("{i}\n")).collect::<String>()).unwrap();
+
+        let loader = InMemoryTextLoader::new(path);
+        let full = collect(&loader, LoaderPosition::default(), 6);
+        let resumed = collect(&loader, full[2].1, 3);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(full[4].0, [0, 1, 2]);
+        assert_eq!(resumed, full[3..]);
+    }
+}
@@ -0,0 +1,406 @@
+use std::io::{self, Read, Write};
+
+/// Version written in the header of current Stockfish networks.
+pub const VERSION: u32 = 0x7AF3_2F20;
+
+/// Hash of Stockfish's `HalfKAv2_hm` feature set.
+pub const HALFKA_V2_HM_HASH: u32 = 0x7F23_4CB8;
+
+const LEB128_MAGIC: &[u8] = b"COMPRESSED_LEB128";
+
+/// The dimensions of a network in the `.nnue` format, which are not stored in the file.
+///
+/// The file consists of a header (version, hash and description), the feature transformer
+/// (with its PSQT weights), and then a stack of affine layers for each output bucket, with
+/// a clipped ReLU between each pair of layers. The hashes are those that Stockfish computes
+/// for such a stack, so an engine will only accept the file if it was compiled with the
+/// same architecture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NnueArch {
+    /// Hash of the feature set, e.g. `HALFKA_V2_HM_HASH`.
+    pub feature_hash: u32,
+    pub ft_inputs: usize,
+    /// Size of the feature transformer for each perspective.
+    pub ft_outputs: usize,
+    pub psqt_buckets: usize,
+    pub layer_stacks: usize,
+    /// The `(inputs, outputs)` of each affine layer in a stack.
+    pub layers: Vec<(usize, usize)>,
+}
+
+impl NnueArch {
+    pub fn ft_hash(&self) -> u32 {
+        self.feature_hash ^ (self.ft_outputs as u32 * 2)
+    }
+
+    pub fn stack_hash(&self) -> u32 {
+        let mut hash = 0xEC42_E90D ^ (self.ft_outputs as u32 * 2);
+
+        for (i, &(_, outputs)) in self.layers.iter().enumerate() {
+            if i > 0 {
+                hash = hash.wrapping_add(0x538D_24C7);
+            }
+
+            let prev = hash;
+            hash = 0xCC03_DAE4u32.wrapping_add(outputs as u32);
+            hash ^= prev >> 1;
+            hash ^= prev << 31;
+        }
+
+        hash
+    }
+
+    pub fn hash(&self) -> u32 {
+        self.ft_hash() ^ self.stack_hash()
+    }
+}
+
+/// An affine layer, with weights stored row-major and each row padded to a multiple of 32.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NnueLayer {
+    pub biases: Vec<i32>,
+    pub weights: Vec<i8>,
+}
+
+/// The contents of a `.nnue` file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NnueFile {
+    pub description: String,
+    pub ft_biases: Vec<i16>,
+    /// Stored as `[[i16; ft_outputs]; ft_inputs]`.
+    pub ft_weights: Vec<i16>,
+    /// Stored as `[[i32; psqt_buckets]; ft_inputs]`.
+    pub psqt_weights: Vec<i32>,
+    pub layer_stacks: Vec<Vec<NnueLayer>>,
+}
+
+pub fn padded_inputs(inputs: usize) -> usize {
+    inputs.div_ceil(32) * 32
+}
+
+impl NnueFile {
+    /// Writes the file, with the feature transformer LEB128 compressed if `compress` is set.
+    pub fn write(&self, arch: &NnueArch, compress: bool, writer: &mut impl Write) -> io::Result<()> {
+        self.check_sizes(arch);
+
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&arch.hash().to_le_bytes())?;
+        writer.write_all(&(self.description.len() as u32).to_le_bytes())?;
+        writer.write_all(self.description.as_bytes())?;
+
+        writer.write_all(&arch.ft_hash().to_le_bytes())?;
+
+        let ft_biases = self.ft_biases.iter().map(|&x| i64::from(x));
+        let ft_weights = self.ft_weights.iter().map(|&x| i64::from(x));
+        let psqt_weights = self.psqt_weights.iter().map(|&x| i64::from(x));
+
+        if compress {
+            write_leb128(writer, ft_biases)?;
+            write_leb128(writer, ft_weights)?;
+            write_leb128(writer, psqt_weights)?;
+        } else {
+            self.ft_biases.iter().try_for_each(|x| writer.write_all(&x.to_le_bytes()))?;
+            self.ft_weights.iter().try_for_each(|x| writer.write_all(&x.to_le_bytes()))?;
+            self.psqt_weights.iter().try_for_each(|x| writer.write_all(&x.to_le_bytes()))?;
+        }
+
+        for stack in &self.layer_stacks {
+            writer.write_all(&arch.stack_hash().to_le_bytes())?;
+
+            for layer in stack {
+                layer.biases.iter().try_for_each(|x| writer.write_all(&x.to_le_bytes()))?;
+                writer.write_all(&layer.weights.iter().map(|&x| x as u8).collect::<Vec<_>>())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a file written with the given architecture and compression of the feature
+    /// transformer, and fails if the hashes do not match.
+    pub fn read(arch: &NnueArch, compressed: bool, reader: &mut impl Read) -> io::Result<Self> {
+        let expect = |found: u32, expected: u32, what: &str| {
+            if found == expected {
+                Ok(())
+            } else {
+                Err(invalid(format!("Expected {what} {expected:#010x}, found {found:#010x}!")))
+            }
+        };
+
+        expect(read_u32(reader)?, VERSION, "version")?;
+        expect(read_u32(reader)?, arch.hash(), "network hash")?;
+
+        let len = read_u32(reader)? as usize;
+        let description =
+            String::from_utf8(read_bytes(reader, len)?).map_err(|_| invalid("Description is not valid UTF-8!"))?;
+
+        expect(read_u32(reader)?, arch.ft_hash(), "feature transformer hash")?;
+
+        let ft_biases = read_ft_values(reader, compressed, arch.ft_outputs, 2)?;
+        let ft_weights = read_ft_values(reader, compressed, arch.ft_inputs * arch.ft_outputs, 2)?;
+        let psqt_weights = read_ft_values(reader, compressed, arch.ft_inputs * arch.psqt_buckets, 4)?;
+
+        let mut layer_stacks = Vec::new();
+
+        for _ in 0..arch.layer_stacks {
+            expect(read_u32(reader)?, arch.stack_hash(), "layer stack hash")?;
+
+            let mut stack = Vec::new();
+
+            for &(inputs, outputs) in &arch.layers {
+                let biases = read_bytes(reader, 4 * outputs)?
+                    .chunks_exact(4)
+                    .map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+                    .collect();
+
+                let weights =
+                    read_bytes(reader, padded_inputs(inputs) * outputs)?.into_iter().map(|x| x as i8).collect();
+
+                stack.push(NnueLayer { biases, weights });
+            }
+
+            layer_stacks.push(stack);
+        }
+
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid("Unexpected data at the end of the file!"));
+        }
+
+        Ok(Self {
+            description,
+            ft_biases: ft_biases
+                .into_iter()
+                .map(|x| i16::try_from(x).map_err(|_| overflow()))
+                .collect::<Result<_, _>>()?,
+            ft_weights: ft_weights
+                .into_iter()
+                .map(|x| i16::try_from(x).map_err(|_| overflow()))
+                .collect::<Result<_, _>>()?,
+            psqt_weights: psqt_weights
+                .into_iter()
+                .map(|x| i32::try_from(x).map_err(|_| overflow()))
+                .collect::<Result<_, _>>()?,
+            layer_stacks,
+        })
+    }
+
+    fn check_sizes(&self, arch: &NnueArch) {
+        assert_eq!(self.ft_biases.len(), arch.ft_outputs, "Wrong number of feature transformer biases!");
+        assert_eq!(
+            self.ft_weights.len(),
+            arch.ft_inputs * arch.ft_outputs,
+            "Wrong number of feature transformer weights!"
+        );
+        assert_eq!(self.psqt_weights.len(), arch.ft_inputs * arch.psqt_buckets, "Wrong number of PSQT weights!");
+        assert_eq!(self.layer_stacks.len(), arch.layer_stacks, "Wrong number of layer stacks!");
+
+        for stack in &self.layer_stacks {
+            assert_eq!(stack.len(), arch.layers.len(), "Wrong number of layers!");
+
+            for (layer, &(inputs, outputs)) in stack.iter().zip(&arch.layers) {
+                assert_eq!(layer.biases.len(), outputs, "Wrong number of layer biases!");
+                assert_eq!(layer.weights.len(), padded_inputs(inputs) * outputs, "Wrong number of layer weights!");
+            }
+        }
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn overflow() -> io::Error {
+    invalid("Feature transformer value out of range!")
+}
+
+fn read_bytes(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Reads `count` values of `size` bytes, which may be LEB128 compressed.
+fn read_ft_values(reader: &mut impl Read, compressed: bool, count: usize, size: usize) -> io::Result<Vec<i64>> {
+    if compressed {
+        if read_bytes(reader, LEB128_MAGIC.len())? != LEB128_MAGIC {
+            return Err(invalid("Expected LEB128 compressed data!"));
+        }
+
+        let len = read_u32(reader)? as usize;
+        return decode_leb128(&read_bytes(reader, len)?, count);
+    }
+
+    Ok(read_bytes(reader, count * size)?
+        .chunks_exact(size)
+        .map(|x| match size {
+            2 => i64::from(i16::from_le_bytes([x[0], x[1]])),
+            _ => i64::from(i32::from_le_bytes([x[0], x[1], x[2], x[3]])),
+        })
+        .collect())
+}
+
+/// Writes the values as signed LEB128, preceded by the magic string and number of bytes.
+fn write_leb128(writer: &mut impl Write, values: impl Iterator<Item = i64>) -> io::Result<()> {
+    let mut bytes = Vec::new();
+
+    for mut value in values {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+
+            let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            bytes.push(if done { byte } else { byte | 0x80 });
+
+            if done {
+                break;
+            }
+        }
+    }
+
+    writer.write_all(LEB128_MAGIC)?;
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+fn decode_leb128(bytes: &[u8], count: usize) -> io::Result<Vec<i64>> {
+    let mut values = Vec::with_capacity(count);
+    let mut bytes = bytes.iter();
+
+    for _ in 0..count {
+        let mut value = 0i64;
+        let mut shift = 0;
+
+        loop {
+            let byte = *bytes.next().ok_or_else(|| invalid("LEB128 data ended early!"))?;
+
+            if shift >= 64 {
+                return Err(invalid("LEB128 value too large!"));
+            }
+
+            value |= i64::from(byte & 0x7F) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+
+                break;
+            }
+        }
+
+        values.push(value);
+    }
+
+    if bytes.next().is_some() {
+        return Err(invalid("Unexpected LEB128 data!"));
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leb128_bytes(values: &[i64]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_leb128(&mut buf, values.iter().copied()).unwrap();
+
+        assert_eq!(&buf[..LEB128_MAGIC.len()], LEB128_MAGIC);
+        let len = u32::from_le_bytes(buf[LEB128_MAGIC.len()..LEB128_MAGIC.len() + 4].try_into().unwrap());
+        let bytes = buf[LEB128_MAGIC.len() + 4..].to_vec();
+        assert_eq!(bytes.len(), len as usize);
+
+        bytes
+    }
+
+    #[test]
+    fn leb128_encoding() {
+        assert_eq!(leb128_bytes(&[0]), [0x00]);
+        assert_eq!(leb128_bytes(&[-1]), [0x7F]);
+        assert_eq!(leb128_bytes(&[63]), [0x3F]);
+        // 64 has the sign bit (0x40) of the first byte set, so needs a second byte
+        assert_eq!(leb128_bytes(&[64]), [0xC0, 0x00]);
+        assert_eq!(leb128_bytes(&[-64]), [0x40]);
+        assert_eq!(leb128_bytes(&[-65]), [0xBF, 0x7F]);
+        assert_eq!(leb128_bytes(&[i64::from(i16::MAX)]), [0xFF, 0xFF, 0x01]);
+        assert_eq!(leb128_bytes(&[i64::from(i16::MIN)]), [0x80, 0x80, 0x7E]);
+    }
+
+    #[test]
+    fn leb128_round_trip() {
+        let values = [0, 1, -1, 63, 64, -64, -65, 127, 128, -128, 1000, -1000, i16::MIN.into(), i16::MAX.into()]
+            .into_iter()
+            .chain([i32::MIN.into(), i32::MAX.into()])
+            .collect::<Vec<i64>>();
+
+        let bytes = leb128_bytes(&values);
+        assert_eq!(decode_leb128(&bytes, values.len()).unwrap(), values);
+
+        assert!(decode_leb128(&bytes, values.len() + 1).is_err());
+        assert!(decode_leb128(&bytes, values.len() - 1).is_err());
+        assert!(decode_leb128(&[0xFF; 11], 1).is_err());
+    }
+
+    fn test_arch() -> (NnueArch, NnueFile) {
+        let arch = NnueArch {
+            feature_hash: HALFKA_V2_HM_HASH,
+            ft_inputs: 3,
+            ft_outputs: 4,
+            psqt_buckets: 2,
+            layer_stacks: 2,
+            layers: vec![(8, 2), (2, 1)],
+        };
+
+        let layer = |seed: i32, inputs: usize, outputs: usize| NnueLayer {
+            biases: (0..outputs as i32).map(|i| seed * 1000 - i).collect(),
+            weights: (0..padded_inputs(inputs) * outputs).map(|i| (i as i32 * seed - 64) as i8).collect(),
+        };
+
+        let nnue = NnueFile {
+            description: "test network".to_string(),
+            ft_biases: vec![i16::MIN, -1, 0, i16::MAX],
+            ft_weights: (0..12).map(|i| i * 1000 - 6000).collect(),
+            psqt_weights: vec![i32::MIN, -1, 0, 1, 64, i32::MAX],
+            layer_stacks: (1..=2).map(|seed| vec![layer(seed, 8, 2), layer(seed + 2, 2, 1)]).collect(),
+        };
+
+        (arch, nnue)
+    }
+
+    #[test]
+    fn nnue_file_round_trip() {
+        let (arch, nnue) = test_arch();
+
+        for compress in [false, true] {
+            let mut buf = Vec::new();
+            nnue.write(&arch, compress, &mut buf).unwrap();
+
+            let read = NnueFile::read(&arch, compress, &mut buf.as_slice()).unwrap();
+            assert_eq!(read, nnue);
+
+            let mut trailing = buf.clone();
+            trailing.push(0);
+            assert!(NnueFile::read(&arch, compress, &mut trailing.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn nnue_file_checks_hashes() {
+        let (arch, nnue) = test_arch();
+
+        let mut buf = Vec::new();
+        nnue.write(&arch, true, &mut buf).unwrap();
+
+        let other = NnueArch { feature_hash: 0, ..arch.clone() };
+        assert!(NnueFile::read(&other, true, &mut buf.as_slice()).is_err());
+        assert!(NnueFile::read(&arch, false, &mut buf.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+
+use crate::{autograd::Node, optimiser::utils::CheckpointTensor, Shape};
+
+/// Where the feature transformer of a network built by `TrainerBuilder` feeds into `l1`.
+///
+/// The input of `l1` is made up of a block of `neurons` values for each perspective, and
+/// each value comes from FT neuron `i`, or from neurons `i` and `i + neurons` if they are
+/// pairwise multiplied.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FeatureTransformerOutput {
+    pub node: Node,
+    pub perspectives: usize,
+    pub neurons: usize,
+    pub pairwise: bool,
+    /// The value of a saturated activation, if the activation saturates.
+    pub saturation: Option<f32>,
+}
+
+impl FeatureTransformerOutput {
+    /// Rows of `l0w` and `l0b` that make up each (pairwise) neuron in `order`.
+    fn ft_rows(&self, order: &[usize]) -> Vec<usize> {
+        let mut rows = order.to_vec();
+
+        if self.pairwise {
+            rows.extend(order.iter().map(|&i| i + self.neurons));
+        }
+
+        rows
+    }
+
+    /// Columns of `l1w` that read each neuron in `order`.
+    fn l1_cols(&self, order: &[usize]) -> Vec<usize> {
+        (0..self.perspectives).flat_map(|p| order.iter().map(move |&i| p * self.neurons + i)).collect()
+    }
+
+    /// Selects the neurons in `order` from the weights of the network.
+    pub fn prune(&self, tensor: CheckpointTensor, order: &[usize]) -> CheckpointTensor {
+        let CheckpointTensor { id, shape, values } = tensor;
+
+        match id.as_str() {
+            "l0w" | "l0b" => {
+                let rows = self.ft_rows(order);
+                let values = (0..shape.cols())
+                    .flat_map(|col| rows.iter().map(move |&row| shape.rows() * col + row))
+                    .map(|i| values[i])
+                    .collect();
+
+                CheckpointTensor { id, shape: Shape::new(rows.len(), shape.cols()), values }
+            }
+            "l1w" => {
+                let cols = self.l1_cols(order);
+                let values = cols.iter().flat_map(|&col| &values[shape.rows() * col..shape.rows() * (col + 1)]);
+                let values = values.copied().collect();
+
+                CheckpointTensor { id, shape: Shape::new(shape.rows(), cols.len()), values }
+            }
+            _ => CheckpointTensor { id, shape, values },
+        }
+    }
+
+    /// Size of the feature transformer with only the neurons in `order`.
+    pub fn ft_size(&self, order: &[usize]) -> usize {
+        self.ft_rows(order).len()
+    }
+}
+
+/// How often each neuron of the feature transformer is active over a sample of positions,
+/// where a neuron that is pairwise multiplied is counted together with its partner.
+#[derive(Clone, Debug, Default)]
+pub struct NeuronStats {
+    pub positions: usize,
+    /// Fraction of activations (over positions and perspectives) of each neuron that are non-zero.
+    pub active: Vec<f64>,
+    /// Fraction of activations of each neuron that are saturated, e.g. `1.0` with `CReLU`.
+    pub saturated: Vec<f64>,
+}
+
+/// Counts of how often each neuron is active or saturated, accumulated
+/// over batches and then turned into `NeuronStats`.
+pub(crate) struct NeuronCounts {
+    positions: usize,
+    active: Vec<usize>,
+    saturated: Vec<usize>,
+}
+
+impl NeuronCounts {
+    pub fn new(ft: &FeatureTransformerOutput) -> Self {
+        Self { positions: 0, active: vec![0; ft.neurons], saturated: vec![0; ft.neurons] }
+    }
+
+    /// Adds the outputs of the feature transformer for a batch of positions.
+    pub fn record(&mut self, ft: &FeatureTransformerOutput, values: &[f32]) {
+        let rows = ft.perspectives * ft.neurons;
+        assert_eq!(values.len() % rows, 0);
+
+        self.positions += values.len() / rows;
+
+        for (i, &x) in values.iter().enumerate() {
+            let neuron = i % ft.neurons;
+            self.active[neuron] += usize::from(x != 0.0);
+            self.saturated[neuron] += usize::from(ft.saturation.is_some_and(|max| x >= max));
+        }
+    }
+
+    pub fn finish(self, ft: &FeatureTransformerOutput) -> NeuronStats {
+        let fraction = |count: usize| count as f64 / (self.positions * ft.perspectives) as f64;
+
+        NeuronStats {
+            positions: self.positions,
+            active: self.active.into_iter().map(fraction).collect(),
+            saturated: self.saturated.into_iter().map(fraction).collect(),
+        }
+    }
+}
+
+impl NeuronStats {
+    /// Neurons that were never active, and so can be removed without changing the output.
+    pub fn dead(&self) -> Vec<usize> {
+        (0..self.active.len()).filter(|&i| self.active[i] == 0.0).collect()
+    }
+
+    /// Neurons that were always saturated.
+    pub fn always_saturated(&self) -> Vec<usize> {
+        (0..self.saturated.len()).filter(|&i| self.saturated[i] == 1.0).collect()
+    }
+
+    /// The neurons that are not dead, for `Trainer::save_pruned`. If `reorder` is set, they are
+    /// sorted from most to least often active, which helps engines that skip inactive chunks.
+    pub fn pruned_order(&self, reorder: bool) -> Vec<usize> {
+        let mut order = (0..self.active.len()).filter(|&i| self.active[i] > 0.0).collect::<Vec<_>>();
+
+        if reorder {
+            order.sort_by(|&a, &b| self.active[b].total_cmp(&self.active[a]));
+        }
+
+        order
+    }
+}
+
+impl fmt::Display for NeuronStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mean = self.active.iter().sum::<f64>() / self.active.len().max(1) as f64;
+
+        writeln!(f, "Positions              : {}", self.positions)?;
+        writeln!(f, "Neurons                : {}", self.active.len())?;
+        writeln!(f, "Dead Neurons           : {}", self.dead().len())?;
+        writeln!(f, "Always Saturated       : {}", self.always_saturated().len())?;
+        write!(f, "Mean Activity          : {:.2}%", 100.0 * mean)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ft(perspectives: usize, neurons: usize, pairwise: bool) -> FeatureTransformerOutput {
+        FeatureTransformerOutput { node: Node(0), perspectives, neurons, pairwise, saturation: Some(1.0) }
+    }
+
+    fn tensor(id: &str, rows: usize, cols: usize, f: impl Fn(usize, usize) -> f32) -> CheckpointTensor {
+        let values = (0..rows * cols).map(|i| f(i % rows, i / rows)).collect();
+        CheckpointTensor { id: id.to_string(), shape: Shape::new(rows, cols), values }
+    }
+
+    /// `w * x`, with `w` stored column-major.
+    fn matmul(w: &CheckpointTensor, x: &[f32]) -> Vec<f32> {
+        let rows = w.shape.rows();
+        (0..rows).map(|row| x.iter().enumerate().map(|(col, &x)| w.values[rows * col + row] * x).sum()).collect()
+    }
+
+    /// The outputs of the feature transformer (as recorded by `NeuronCounts`) and of the
+    /// network, for one input per perspective, with a `CReLU` after the feature transformer.
+    fn forward(ft: &FeatureTransformerOutput, net: &[CheckpointTensor], inputs: &[Vec<f32>]) -> (Vec<f32>, f32) {
+        let [l0w, l0b, l1w, l1b] = net else { panic!("Expected 4 tensors!") };
+
+        let hidden = inputs
+            .iter()
+            .flat_map(|input| {
+                let acc: Vec<_> =
+                    matmul(l0w, input).iter().zip(&l0b.values).map(|(x, b)| (x + b).clamp(0.0, 1.0)).collect();
+
+                if ft.pairwise {
+                    (0..ft.neurons).map(|i| acc[i] * acc[i + ft.neurons]).collect()
+                } else {
+                    acc
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let output = matmul(l1w, &hidden)[0] + l1b.values[0];
+        (hidden, output)
+    }
+
+    #[test]
+    fn kept_rows_and_cols() {
+        let order = [2, 0];
+
+        let single = ft(1, 4, false);
+        assert_eq!(single.ft_rows(&order), [2, 0]);
+        assert_eq!(single.l1_cols(&order), [2, 0]);
+        assert_eq!(single.ft_size(&order), 2);
+
+        let dual_pairwise = ft(2, 4, true);
+        assert_eq!(dual_pairwise.ft_rows(&order), [2, 0, 6, 4]);
+        assert_eq!(dual_pairwise.l1_cols(&order), [2, 0, 6, 4]);
+        assert_eq!(dual_pairwise.ft_size(&order), 4);
+
+        // each value is its row and column in the unpruned tensor
+        let id = |row, col| (100 * row + col) as f32;
+        let l0w = dual_pairwise.prune(tensor("l0w", 8, 3, id), &order);
+        let l0b = dual_pairwise.prune(tensor("l0b", 8, 1, id), &order);
+        let l1w = dual_pairwise.prune(tensor("l1w", 2, 8, id), &order);
+        let l1b = dual_pairwise.prune(tensor("l1b", 2, 1, id), &order);
+
+        assert_eq!(l0w.shape, Shape::new(4, 3));
+        assert_eq!(l0w.values, tensor("", 4, 3, |row, col| id([2, 0, 6, 4][row], col)).values);
+        assert_eq!(l0b.shape, Shape::new(4, 1));
+        assert_eq!(l0b.values, [200.0, 0.0, 600.0, 400.0]);
+
+        assert_eq!(l1w.shape, Shape::new(2, 4));
+        assert_eq!(l1w.values, tensor("", 2, 4, |row, col| id(row, [2, 0, 6, 4][col])).values);
+        assert_eq!(l1b.shape, Shape::new(2, 1));
+        assert_eq!(l1b.values, [0.0, 100.0]);
+    }
+
+    #[test]
+    fn neuron_counts() {
+        let ft = ft(2, 2, false);
+        let mut counts = NeuronCounts::new(&ft);
+
+        // one position per batch, with the values of both perspectives
+        counts.record(&ft, &[0.0, 1.0, 0.5, 1.0]);
+        counts.record(&ft, &[0.0, 0.3, 0.0, 1.0]);
+
+        let stats = counts.finish(&ft);
+        assert_eq!(stats.positions, 2);
+        assert_eq!(stats.active, [0.25, 1.0]);
+        assert_eq!(stats.saturated, [0.0, 0.75]);
+        assert!(stats.dead().is_empty());
+        assert_eq!(stats.pruned_order(true), [1, 0]);
+    }
+
+    #[test]
+    fn pruning_dead_neurons_keeps_outputs() {
+        for (perspectives, pairwise) in [(1, false), (2, false), (2, true)] {
+            let ft = ft(perspectives, 4, pairwise);
+            let rows = if pairwise { 8 } else { 4 };
+
+            let weight = |row: usize, col: usize| ((7 * row + 3 * col) % 11) as f32 / 10.0 - 0.3;
+            // neuron 1 (and its partner, if pairwise) can never be active
+            let dead = |row: usize| row % 4 == 1;
+
+            let net = || {
+                [
+                    tensor("l0w", rows, 3, |row, col| if dead(row) { -1.0 } else { weight(row, col) }),
+                    tensor("l0b", rows, 1, |row, _| if dead(row) { -1.0 } else { 0.2 }),
+                    tensor("l1w", 1, 4 * perspectives, |_, col| weight(col, 5)),
+                    tensor("l1b", 1, 1, |_, _| 0.1),
+                ]
+            };
+
+            let positions = [[1.0, 0.0, 1.0], [0.0, 1.0, 1.0], [1.0, 1.0, 0.0]];
+            let inputs = |position: &[f32; 3]| -> Vec<Vec<f32>> {
+                (0..perspectives)
+                    .map(|p| position.iter().map(|x| if p == 0 { *x } else { 1.0 - x }).collect())
+                    .collect()
+            };
+
+            let mut counts = NeuronCounts::new(&ft);
+            for position in &positions {
+                counts.record(&ft, &forward(&ft, &net(), &inputs(position)).0);
+            }
+
+            let stats = counts.finish(&ft);
+            assert_eq!(stats.dead(), [1]);
+
+            let order = stats.pruned_order(true);
+            let pruned = net().map(|tensor| ft.prune(tensor, &order));
+            let pruned_ft = FeatureTransformerOutput { neurons: order.len(), ..ft };
+
+            assert_eq!(pruned[0].shape, Shape::new(ft.ft_size(&order), 3));
+
+            for position in &positions {
+                let (_, expected) = forward(&ft, &net(), &inputs(position));
+                let (_, output) = forward(&pruned_ft, &pruned, &inputs(position));
+                assert!((output - expected).abs() < 1e-6, "{output} != {expected}");
+            }
+        }
+    }
+}
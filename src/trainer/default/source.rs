@@ -0,0 +1,200 @@
+use std::{fmt::Write, io};
+
+use crate::{trainer::save::QuantTarget, Shape};
+
+/// A constant describing a network built by `TrainerBuilder`, written alongside its weights.
+#[derive(Clone, Debug)]
+pub(crate) enum NetworkConstant {
+    Size(String, usize),
+    Quant(String, i32),
+}
+
+/// A quantised tensor, as it is written to `quantised.bin`.
+pub(crate) struct SourceTensor {
+    pub id: String,
+    pub quant: QuantTarget,
+    pub shape: Shape,
+    pub transposed: bool,
+    pub bytes: Vec<u8>,
+}
+
+impl SourceTensor {
+    fn len(&self) -> usize {
+        self.bytes.len() / self.quant.size()
+    }
+
+    fn description(&self) -> String {
+        let layout = if self.transposed { "row-major" } else { "column-major" };
+        let quant = match self.quant {
+            QuantTarget::Float => String::from("unquantised"),
+            QuantTarget::I8(q) | QuantTarget::I16(q) => format!("quantised by {q}"),
+            QuantTarget::I32(q) => format!("quantised by {q}"),
+        };
+
+        format!("`{}`: {} x {}, {layout}, {quant}", self.id, self.shape.rows(), self.shape.cols())
+    }
+
+    /// Fails if a float is NaN or infinite, as neither can be written as a literal.
+    fn values(&self, float_suffix: &str) -> io::Result<Vec<String>> {
+        let values = self.bytes.chunks_exact(self.quant.size());
+
+        let float = |x: &[u8]| {
+            let x = f32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+
+            if x.is_finite() {
+                Ok(format!("{x:?}{float_suffix}"))
+            } else {
+                let msg = format!("Cannot write {x} in [{}] as a literal!", self.id);
+                Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+            }
+        };
+
+        match self.quant {
+            QuantTarget::Float => values.map(float).collect(),
+            QuantTarget::I8(_) => Ok(values.map(|x| (x[0] as i8).to_string()).collect()),
+            QuantTarget::I16(_) => Ok(values.map(|x| i16::from_le_bytes([x[0], x[1]]).to_string()).collect()),
+            QuantTarget::I32(_) => {
+                Ok(values.map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]).to_string()).collect())
+            }
+        }
+    }
+}
+
+/// A C/C++ header declaring each tensor as an array aligned to 64 bytes,
+/// with every name prefixed by `prefix`, which must be a C identifier starting with a letter.
+pub(crate) fn c_header(prefix: &str, constants: &[NetworkConstant], tensors: &[SourceTensor]) -> io::Result<String> {
+    let valid = prefix.starts_with(|c: char| c.is_ascii_alphabetic())
+        && prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !valid {
+        let msg = format!("Invalid prefix '{prefix}', it must be a C identifier starting with a letter!");
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    }
+
+    let prefix = prefix.to_uppercase();
+    let mut out = String::new();
+
+    writeln!(out, "// Generated by bullet, do not edit.").unwrap();
+    writeln!(out, "#ifndef {prefix}_NETWORK_H").unwrap();
+    writeln!(out, "#define {prefix}_NETWORK_H\n").unwrap();
+    writeln!(out, "#include <stdint.h>\n").unwrap();
+    writeln!(
+        out,
+        "#ifdef __cplusplus\n#define {prefix}_ALIGN alignas(64)\n#else\n#define {prefix}_ALIGN _Alignas(64)\n#endif\n"
+    )
+    .unwrap();
+
+    for constant in constants {
+        match constant {
+            NetworkConstant::Size(name, value) => writeln!(out, "#define {prefix}_{name} {value}").unwrap(),
+            NetworkConstant::Quant(name, value) => writeln!(out, "#define {prefix}_{name} {value}").unwrap(),
+        }
+    }
+
+    for tensor in tensors {
+        let ty = match tensor.quant {
+            QuantTarget::Float => "float",
+            QuantTarget::I8(_) => "int8_t",
+            QuantTarget::I16(_) => "int16_t",
+            QuantTarget::I32(_) => "int32_t",
+        };
+
+        let name = format!("{prefix}_{}", tensor.id.to_uppercase());
+
+        writeln!(out, "\n// {}", tensor.description()).unwrap();
+        writeln!(out, "{prefix}_ALIGN static const {ty} {name}[{}] = {{", tensor.len()).unwrap();
+        write_values(&mut out, &tensor.values("f")?);
+        writeln!(out, "}};").unwrap();
+    }
+
+    writeln!(out, "\n#endif").unwrap();
+
+    Ok(out)
+}
+
+/// A Rust module declaring each tensor as a static array aligned to 64 bytes.
+pub(crate) fn rust_module(constants: &[NetworkConstant], tensors: &[SourceTensor]) -> io::Result<String> {
+    let mut out = String::new();
+
+    writeln!(out, "// Generated by bullet, do not edit.\n").unwrap();
+
+    for constant in constants {
+        match constant {
+            NetworkConstant::Size(name, value) => writeln!(out, "pub const {name}: usize = {value};").unwrap(),
+            NetworkConstant::Quant(name, value) => writeln!(out, "pub const {name}: i32 = {value};").unwrap(),
+        }
+    }
+
+    writeln!(out, "\n#[repr(C, align(64))]").unwrap();
+    writeln!(out, "pub struct Aligned<T>(pub T);").unwrap();
+
+    for tensor in tensors {
+        let ty = match tensor.quant {
+            QuantTarget::Float => "f32",
+            QuantTarget::I8(_) => "i8",
+            QuantTarget::I16(_) => "i16",
+            QuantTarget::I32(_) => "i32",
+        };
+
+        writeln!(out, "\n/// {}", tensor.description()).unwrap();
+        writeln!(out, "pub static {}: Aligned<[{ty}; {}]> = Aligned([", tensor.id.to_uppercase(), tensor.len())
+            .unwrap();
+        write_values(&mut out, &tensor.values("")?);
+        writeln!(out, "]);").unwrap();
+    }
+
+    Ok(out)
+}
+
+fn write_values(out: &mut String, values: &[String]) {
+    for line in values.chunks(16) {
+        writeln!(out, "    {},", line.join(", ")).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floats(values: &[f32]) -> SourceTensor {
+        SourceTensor {
+            id: String::from("l0w"),
+            quant: QuantTarget::Float,
+            shape: Shape::new(values.len(), 1),
+            transposed: false,
+            bytes: values.iter().flat_map(|x| x.to_le_bytes()).collect(),
+        }
+    }
+
+    #[test]
+    fn writes_arrays() {
+        let constants = [NetworkConstant::Size(String::from("HIDDEN"), 2)];
+        let tensors = [floats(&[1.0, -0.5])];
+
+        let header = c_header("mynet", &constants, &tensors).unwrap();
+        assert!(header.contains("#define MYNET_HIDDEN 2"));
+        assert!(header.contains("MYNET_ALIGN static const float MYNET_L0W[2] = {\n    1.0f, -0.5f,\n};"));
+
+        let module = rust_module(&constants, &tensors).unwrap();
+        assert!(module.contains("pub const HIDDEN: usize = 2;"));
+        assert!(module.contains("pub static L0W: Aligned<[f32; 2]> = Aligned([\n    1.0, -0.5,\n]);"));
+    }
+
+    #[test]
+    fn rejects_non_finite_floats() {
+        for x in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let tensors = [floats(&[0.0, x])];
+            assert_eq!(c_header("net", &[], &tensors).unwrap_err().kind(), io::ErrorKind::InvalidData);
+            assert_eq!(rust_module(&[], &tensors).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_prefixes() {
+        for prefix in ["", "_net", "1net", "my-net", "my net"] {
+            assert_eq!(c_header(prefix, &[], &[]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+
+        assert!(c_header("net_2", &[], &[]).is_ok());
+    }
+}
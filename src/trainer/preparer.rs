@@ -14,13 +14,56 @@ pub trait DataPreparer: Clone + Send + Sync {
 
     fn load_and_map_batches<F: FnMut(&[Self::DataType]) -> bool>(&self, start_batch: usize, batch_size: usize, f: F);
 
+    /// As `load_and_map_batches`, but starts from `start` and passes the position
+    /// after each batch to `f`, so that loading can later be resumed from it.
+    fn load_and_map_batches_from<F: FnMut(&[Self::DataType], LoaderPosition) -> bool>(
+        &self,
+        start: LoaderPosition,
+        batch_size: usize,
+        mut f: F,
+    ) {
+        let mut batches = start.batches;
+
+        self.load_and_map_batches(start.batches, batch_size, |batch| {
+            batches += 1;
+            f(batch, LoaderPosition { batches, ..Default::default() })
+        });
+    }
+
     fn prepare(&self, data: &[Self::DataType], threads: usize, blend: f32) -> Self::PreparedData;
+
+    /// Seed used for shuffling the data, if it is shuffled.
+    fn shuffle_seed(&self) -> Option<u64> {
+        None
+    }
+
+    fn set_shuffle_seed(&mut self, _seed: u64) {}
+}
+
+/// Position of a data loader in its data, from which loading can be resumed.
+///
+/// Loaders that shuffle their data through a buffer report where the current
+/// buffer was filled from, so that resuming only needs to refill that buffer.
+/// Other loaders only use `batches`, counted from the start of the data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoaderPosition {
+    /// Loader-specific offset of the read that the current buffer starts in.
+    pub offset: u64,
+    /// Number of entries produced by that read which went into earlier buffers.
+    pub skip: u64,
+    /// State of the shuffling rng before the current buffer was shuffled,
+    /// or `0` to shuffle with the loader's seed.
+    pub rng: u64,
+    /// Number of batches already taken from the current buffer.
+    pub batches: usize,
 }
 
 pub fn create_dataloader<D: DataPreparer + 'static, WDL: WdlScheduler>(
     preparer: D,
-    sender: SyncSender<D::PreparedData>,
+    sender: SyncSender<(D::PreparedData, LoaderPosition)>,
     steps: TrainingSteps,
+    start: LoaderPosition,
+    micro_batches: usize,
     wdl: WDL,
     threads: usize,
 ) -> std::thread::JoinHandle<()> {
@@ -28,20 +71,22 @@ pub fn create_dataloader<D: DataPreparer + 'static, WDL: WdlScheduler>(
         let mut curr_superbatch = steps.start_superbatch;
         let mut curr_batch = 0;
 
-        let start_batch = steps.batches_per_superbatch * (steps.start_superbatch - 1);
+        // each batch is sent as `micro_batches` consecutive smaller batches
+        let batch_size = steps.batch_size / micro_batches;
+        let batches_per_superbatch = steps.batches_per_superbatch * micro_batches;
 
-        preparer.load_and_map_batches(start_batch, steps.batch_size, |batch| {
-            let blend = wdl.blend(curr_batch, curr_superbatch, steps.end_superbatch);
+        preparer.load_and_map_batches_from(start, batch_size, |batch, position| {
+            let blend = wdl.blend(curr_batch / micro_batches, curr_superbatch, steps.end_superbatch);
 
             let prepared_data = preparer.prepare(batch, threads, blend);
 
-            sender.send(prepared_data).unwrap();
+            sender.send((prepared_data, position)).unwrap();
 
             curr_batch += 1;
 
             let mut should_break = false;
 
-            if curr_batch % steps.batches_per_superbatch == 0 {
+            if curr_batch % batches_per_superbatch == 0 {
                 if curr_superbatch == steps.end_superbatch {
                     should_break = true;
                 }
@@ -1,19 +1,56 @@
-use std::io::{self, Write};
+use std::{io, sync::Arc};
 
 use crate::{tensor::DenseMatrix, Shape};
 
+pub use bullet_inference::quantise::{Clipping, QuantOptions, QuantTarget, Rounding};
+
+pub(super) use bullet_inference::quantise::pad;
+
 #[derive(Clone)]
 pub struct SavedFormat {
     pub(super) id: String,
     pub(super) quant: QuantTarget,
     pub(super) layout: Layout,
+    pub(super) transforms: Vec<Transform>,
 }
 
 impl SavedFormat {
     pub fn new(id: &str, quant: QuantTarget, layout: Layout) -> Self {
-        SavedFormat { id: id.to_string(), quant, layout }
+        SavedFormat { id: id.to_string(), quant, layout, transforms: Vec::new() }
+    }
+
+    /// Adds a transform, applied after any previous transforms and before quantisation.
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.transforms.push(transform);
+        self
+    }
+
+    /// Adds a custom transform, see `Transform::Custom`.
+    pub fn transform_with(self, f: impl Fn(&TransformContext, Vec<f32>) -> Vec<f32> + Send + Sync + 'static) -> Self {
+        self.transform(Transform::Custom(Arc::new(f)))
+    }
+
+    /// Applies the transforms to `values` of the given shape, returning the new shape and values.
+    pub(crate) fn apply_transforms(
+        &self,
+        mut shape: Shape,
+        mut values: Vec<f32>,
+        weights: &dyn Fn(&str) -> Vec<f32>,
+        merge_factoriser: &dyn Fn(Vec<f32>) -> Vec<f32>,
+    ) -> (Shape, Vec<f32>) {
+        for transform in &self.transforms {
+            let ctx = TransformContext { shape, weights, merge_factoriser };
+            (shape, values) = transform.apply(&ctx, values);
+        }
+
+        (shape, values)
+    }
+
+    pub(crate) fn is_factorised(&self) -> bool {
+        self.transforms.iter().any(|transform| matches!(transform, Transform::MergeFactoriser))
     }
 
+    /// Does not apply any transforms, as they need the rest of the network.
     pub fn write_to_byte_buffer(&self, weights: &DenseMatrix) -> io::Result<Vec<u8>> {
         let mut weight_buf = vec![0.0; weights.shape().size()];
         let written = weights.write_to_slice(&mut weight_buf);
@@ -25,6 +62,134 @@ impl SavedFormat {
 
         self.quant.quantise(&weight_buf)
     }
+
+    /// Quantises `weights`, already in this format's layout, with errors naming this
+    /// tensor. If any values are clamped, this tensor's id is added to `clipped`.
+    pub(super) fn quantise_with(
+        &self,
+        weights: &[f32],
+        options: QuantOptions,
+        clipped: &mut Vec<(String, Clipping)>,
+    ) -> io::Result<Vec<u8>> {
+        let (quantised, clipping) = self
+            .quant
+            .quantise_with(weights, options)
+            .map_err(|e| io::Error::new(e.kind(), format!("[{}]: {e}", self.id)))?;
+
+        if clipping.count > 0 {
+            clipped.push((self.id.clone(), clipping));
+        }
+
+        Ok(quantised)
+    }
+
+    /// Inverse of `write_to_byte_buffer`, for weights of the given (graph) shape.
+    pub fn read_from_byte_buffer(&self, shape: Shape, bytes: &[u8]) -> io::Result<Vec<f32>> {
+        let weight_buf = self.quant.dequantise(bytes)?;
+
+        if weight_buf.len() != shape.size() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Expected {} values!", shape.size())));
+        }
+
+        Ok(match self.layout {
+            Layout::Normal => weight_buf,
+            Layout::Transposed => transpose(Shape::new(shape.cols(), shape.rows()), &weight_buf),
+        })
+    }
+}
+
+pub type CustomTransform = Arc<dyn Fn(&TransformContext, Vec<f32>) -> Vec<f32> + Send + Sync>;
+
+/// A transform applied to weights before they are quantised. Weights are column-major,
+/// and every transform other than `SplitRows` keeps the number of rows.
+#[derive(Clone)]
+pub enum Transform {
+    /// Merges the factoriser of the input features into the weights, which have a column per input.
+    MergeFactoriser,
+    /// Row `i` of the result is row `order[i]`, e.g. to reorder feature transformer neurons
+    /// in `l0w` and `l0b` for SIMD packing.
+    PermuteRows(Vec<usize>),
+    /// Column `i` of the result is column `order[i]`, e.g. to reorder the inputs of `l1w` to match.
+    PermuteCols(Vec<usize>),
+    /// Adds the (untransformed) weights `id`, which must be the same size.
+    Add(String),
+    /// Multiplies by the (untransformed) weights `id`, which must either be the same size,
+    /// or a single value.
+    Scale(String),
+    /// Splits the rows into the given number of equal chunks, each of which is stored
+    /// column-major, one after the other, e.g. to store an output bucketed layer bucket by bucket.
+    SplitRows(usize),
+    /// Returns new values from the current ones, which must have the same number of rows.
+    Custom(CustomTransform),
+}
+
+/// What a `Transform` can see of the network.
+pub struct TransformContext<'a> {
+    /// Shape of the weights being transformed, after any previous transforms.
+    pub shape: Shape,
+    weights: &'a dyn Fn(&str) -> Vec<f32>,
+    merge_factoriser: &'a dyn Fn(Vec<f32>) -> Vec<f32>,
+}
+
+impl TransformContext<'_> {
+    /// The untransformed values of the weights `id`.
+    pub fn weights(&self, id: &str) -> Vec<f32> {
+        (self.weights)(id)
+    }
+}
+
+impl Transform {
+    fn apply(&self, ctx: &TransformContext, values: Vec<f32>) -> (Shape, Vec<f32>) {
+        let rows = ctx.shape.rows();
+        let cols = ctx.shape.cols();
+
+        let values = match self {
+            Self::MergeFactoriser => (ctx.merge_factoriser)(values),
+            Self::PermuteRows(order) => {
+                assert_permutation(order, rows, "rows");
+                (0..cols).flat_map(|col| order.iter().map(move |&row| rows * col + row)).map(|i| values[i]).collect()
+            }
+            Self::PermuteCols(order) => {
+                assert_permutation(order, cols, "columns");
+                order.iter().flat_map(|&col| &values[rows * col..rows * (col + 1)]).copied().collect()
+            }
+            Self::Add(id) => {
+                let other = ctx.weights(id);
+                assert_eq!(other.len(), values.len(), "Cannot add [{id}] of a different size!");
+                values.iter().zip(other).map(|(x, y)| x + y).collect()
+            }
+            Self::Scale(id) => match ctx.weights(id).as_slice() {
+                &[scale] => values.iter().map(|x| x * scale).collect(),
+                other => {
+                    assert_eq!(other.len(), values.len(), "Cannot scale by [{id}] of a different size!");
+                    values.iter().zip(other).map(|(x, y)| x * y).collect()
+                }
+            },
+            Self::SplitRows(chunks) => {
+                assert!(*chunks > 0 && rows % chunks == 0, "Cannot split {rows} rows into {chunks} chunks!");
+                let chunk = rows / chunks;
+
+                let values = (0..*chunks)
+                    .flat_map(|i| (0..cols).map(move |col| rows * col + chunk * i))
+                    .flat_map(|start| &values[start..start + chunk])
+                    .copied()
+                    .collect();
+
+                return (Shape::new(chunk, cols * chunks), values);
+            }
+            Self::Custom(f) => f(ctx, values),
+        };
+
+        assert_eq!(values.len() % rows, 0, "Transform did not preserve the number of rows!");
+
+        (Shape::new(rows, values.len() / rows), values)
+    }
+}
+
+fn assert_permutation(order: &[usize], len: usize, dim: &str) {
+    let mut sorted = order.to_vec();
+    sorted.sort_unstable();
+    assert!(sorted.iter().copied().eq(0..len), "Not a permutation of the {len} {dim}!");
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -35,73 +200,179 @@ pub enum Layout {
     Transposed,
 }
 
-#[derive(Clone, Copy)]
-pub enum QuantTarget {
-    Float,
-    /// This takes an `i16` because it is common to want to use a quantisation
-    /// value of, say, 128 with weights clipped to [-0.99, 0.99]
-    I8(i16),
-    I16(i16),
-    I32(i32),
-}
+/// Reads back the tensors of a quantised network, as weights in the layout of the graph.
+/// Each format is given with the shape of its weights and the number of values it was
+/// saved with, which is fewer if a factoriser was merged into it, in which case the
+/// factoriser weights (which come first) are zeroed.
+pub(super) fn read_quantised(tensors: &[(&SavedFormat, Shape, usize)], bytes: &[u8]) -> io::Result<Vec<Vec<f32>>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
 
-impl QuantTarget {
-    pub fn quantise(self, buf: &[f32]) -> io::Result<Vec<u8>> {
-        let mut quantised = Vec::<u8>::new();
+    let mut offset = 0;
+    let mut loaded = Vec::new();
 
-        for &float in buf {
-            let to_write = match self {
-                Self::Float => float.to_le_bytes().to_vec(),
-                Self::I8(q) => {
-                    let qf = (f64::from(q) * f64::from(float)).trunc();
-                    let x = qf as i8;
+    for &(format, shape, merged_size) in tensors {
+        let merged_shape = Shape::new(shape.rows(), merged_size / shape.rows());
+        let len = merged_size * format.quant.size();
 
-                    if qf != f64::from(x) {
-                        return Err(io::Error::new(io::ErrorKind::InvalidData, "Failed quantisation from f32 to i8!"));
-                    }
+        let chunk =
+            bytes.get(offset..offset + len).ok_or_else(|| invalid(format!("ran out of data at [{}]", format.id)))?;
+        let values =
+            format.read_from_byte_buffer(merged_shape, chunk).map_err(|e| invalid(format!("[{}]: {e}", format.id)))?;
 
-                    x.to_le_bytes().to_vec()
-                }
-                Self::I16(q) => {
-                    let qf = (f64::from(q) * f64::from(float)).trunc();
-                    let x = qf as i16;
+        let mut weights = vec![0.0; shape.size() - merged_size];
+        weights.extend_from_slice(&values);
 
-                    if qf != f64::from(x) {
-                        return Err(io::Error::new(io::ErrorKind::InvalidData, "Failed quantisation from f32 to i16!"));
-                    }
+        loaded.push(weights);
+        offset += len;
+    }
 
-                    x.to_le_bytes().to_vec()
-                }
-                Self::I32(q) => {
-                    let x = (q as f32 * float) as i32;
+    let padding = &bytes[offset..];
+    let expected = b"bullet".iter().cycle().take(padding.len());
 
-                    if (f64::from(float) * f64::from(q)).trunc() != f64::from(x) {
-                        return Err(io::Error::new(io::ErrorKind::InvalidData, "Failed quantisation from f32 to i32!"));
-                    }
+    if padding.len() >= 64 || !padding.iter().eq(expected) {
+        return Err(invalid(format!("{} bytes of unexpected trailing data", padding.len())));
+    }
 
-                    x.to_le_bytes().to_vec()
-                }
-            };
+    Ok(loaded)
+}
+
+pub(super) fn transpose(shape: Shape, weights: &[f32]) -> Vec<f32> {
+    bullet_inference::quantise::transpose(shape.rows(), shape.cols(), weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            quantised.write_all(&to_write)?;
+    const WEIGHTS: [f32; 4] = [0.5, -0.5, 1.3, -1.3];
+
+    #[test]
+    fn clipping_reports_ids() {
+        let options = QuantOptions { rounding: Rounding::Truncate, saturate: true };
+        let mut clipped = Vec::new();
+
+        let formats = [
+            SavedFormat::new("l0w", QuantTarget::I8(100), Layout::Normal),
+            SavedFormat::new("l0b", QuantTarget::I16(100), Layout::Normal),
+            SavedFormat::new("l1w", QuantTarget::I8(100), Layout::Normal),
+        ];
+
+        for format in &formats {
+            format.quantise_with(&WEIGHTS, options, &mut clipped).unwrap();
         }
 
-        Ok(quantised)
+        let ids = clipped.iter().map(|(id, clipping)| (id.as_str(), clipping.count)).collect::<Vec<_>>();
+        assert_eq!(ids, [("l0w", 2), ("l1w", 2)]);
+
+        let options = QuantOptions { saturate: false, ..options };
+        let err = formats[0].quantise_with(&WEIGHTS, options, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("[l0w]"));
     }
-}
 
-pub(super) fn transpose(shape: Shape, weights: &[f32]) -> Vec<f32> {
-    assert_eq!(shape.size(), weights.len());
+    #[test]
+    fn read_quantised_round_trip() {
+        let l0w = SavedFormat::new("l0w", QuantTarget::I16(255), Layout::Normal);
+        let l1w = SavedFormat::new("l1w", QuantTarget::I8(64), Layout::Transposed);
+        let l1b = SavedFormat::new("l1b", QuantTarget::I32(255 * 64), Layout::Normal);
 
-    let rows = shape.rows();
-    let cols = shape.cols();
-    let mut new_buf = vec![0.0; shape.size()];
+        // 2 factoriser columns, followed by 4 columns that are each merged with one of them
+        let l0w_shape = Shape::new(2, 6);
+        let l0w_values = [0.1, -0.2, 0.3, 0.05, 0.5, 0.25, -0.33, 0.1, 0.01, 0.9, -0.7, -0.45];
+        let merged = (4..12).map(|i| l0w_values[i] + l0w_values[i % 4]).collect::<Vec<_>>();
 
-    for i in 0..rows {
-        for j in 0..cols {
-            new_buf[cols * i + j] = weights[rows * j + i];
-        }
+        let l1w_shape = Shape::new(2, 3);
+        let l1w_values = [0.7, -0.3, 0.123, 0.456, -0.9, 0.01];
+        let l1b_values = [0.4, -0.6];
+
+        let options = QuantOptions::default();
+        let mut buf = Vec::new();
+        let mut clipped = Vec::new();
+
+        buf.extend(l0w.quantise_with(&merged, options, &mut clipped).unwrap());
+        buf.extend(l1w.quantise_with(&transpose(l1w_shape, &l1w_values), options, &mut clipped).unwrap());
+        buf.extend(l1b.quantise_with(&l1b_values, options, &mut clipped).unwrap());
+        pad(&mut buf);
+
+        assert!(clipped.is_empty());
+        assert_eq!(buf.len() % 64, 0);
+
+        let tensors = [(&l0w, l0w_shape, 8), (&l1w, l1w_shape, 6), (&l1b, Shape::new(2, 1), 2)];
+        let loaded = read_quantised(&tensors, &buf).unwrap();
+
+        let within_a_step = |loaded: &[f32], expected: &[f32], q: f32| {
+            assert_eq!(loaded.len(), expected.len());
+            assert!(loaded.iter().zip(expected).all(|(x, y)| (x - y).abs() < 1.0 / q), "{loaded:?} != {expected:?}");
+        };
+
+        assert_eq!(loaded[0][..4], [0.0; 4]);
+        within_a_step(&loaded[0][4..], &merged, 255.0);
+        within_a_step(&loaded[1], &l1w_values, 64.0);
+        within_a_step(&loaded[2], &l1b_values, 255.0 * 64.0);
+
+        buf.extend_from_slice(&[0; 64]);
+        assert!(read_quantised(&tensors, &buf).is_err());
+        assert!(read_quantised(&tensors, &buf[..16]).is_err());
     }
 
-    new_buf
+    // 2x3, column-major: columns [1, 2], [3, 4], [5, 6]
+    const VALUES: [f32; 6] = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+
+    fn apply(transform: Transform, shape: Shape, values: &[f32]) -> (Shape, Vec<f32>) {
+        let weights = |id: &str| match id {
+            "other" => vec![10.0, 20.0, 30.0, 40.0, 50.0, 60.0],
+            "scalar" => vec![0.5],
+            _ => panic!("No weights [{id}]!"),
+        };
+        let merge_factoriser = |values| values;
+
+        let ctx = TransformContext { shape, weights: &weights, merge_factoriser: &merge_factoriser };
+        transform.apply(&ctx, values.to_vec())
+    }
+
+    #[test]
+    fn permute_rows() {
+        let (shape, values) = apply(Transform::PermuteRows(vec![1, 0]), Shape::new(2, 3), &VALUES);
+        assert_eq!(shape, Shape::new(2, 3));
+        assert_eq!(values, [2.0, 1.0, 4.0, 3.0, 6.0, 5.0]);
+    }
+
+    #[test]
+    fn permute_cols() {
+        let (shape, values) = apply(Transform::PermuteCols(vec![2, 0, 1]), Shape::new(2, 3), &VALUES);
+        assert_eq!(shape, Shape::new(2, 3));
+        assert_eq!(values, [5.0, 6.0, 1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not a permutation")]
+    fn permute_rows_rejects_non_permutation() {
+        apply(Transform::PermuteRows(vec![0, 0]), Shape::new(2, 3), &VALUES);
+    }
+
+    #[test]
+    fn split_rows() {
+        // 4x2, columns [1, 2, 3, 4] and [5, 6, 7, 8], split into two 2-row chunks
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+
+        let (shape, values) = apply(Transform::SplitRows(2), Shape::new(4, 2), &values);
+        assert_eq!(shape, Shape::new(2, 4));
+        assert_eq!(values, [1.0, 2.0, 5.0, 6.0, 3.0, 4.0, 7.0, 8.0]);
+    }
+
+    #[test]
+    fn add() {
+        let (shape, values) = apply(Transform::Add("other".to_string()), Shape::new(2, 3), &VALUES);
+        assert_eq!(shape, Shape::new(2, 3));
+        assert_eq!(values, [11.0, 22.0, 33.0, 44.0, 55.0, 66.0]);
+    }
+
+    #[test]
+    fn scale() {
+        let (_, values) = apply(Transform::Scale("scalar".to_string()), Shape::new(2, 3), &VALUES);
+        assert_eq!(values, [0.5, 1.0, 1.5, 2.0, 2.5, 3.0]);
+
+        let (shape, values) = apply(Transform::Scale("other".to_string()), Shape::new(2, 3), &VALUES);
+        assert_eq!(shape, Shape::new(2, 3));
+        assert_eq!(values, [10.0, 40.0, 90.0, 160.0, 250.0, 360.0]);
+    }
 }
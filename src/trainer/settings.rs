@@ -14,6 +14,23 @@ impl<'a> TestDataset<'a> {
     }
 }
 
+/// Which checkpoints to keep on disk during training. The checkpoint with the
+/// lowest validation loss is always kept as `<net_id>-best`, if there is a test set.
+#[derive(Clone, Copy, Debug)]
+pub struct CheckpointRetention {
+    /// Number of most recent checkpoints to keep in full, `None` keeps all of them.
+    pub keep_last: Option<usize>,
+    /// Keep the `quantised.bin` of checkpoints older than the last `keep_last`,
+    /// rather than removing them entirely.
+    pub keep_old_quantised: bool,
+}
+
+impl Default for CheckpointRetention {
+    fn default() -> Self {
+        Self { keep_last: None, keep_old_quantised: true }
+    }
+}
+
 pub struct LocalSettings<'a> {
     /// Number of threads to make available for training, in addition
     /// to the main trainer thread (used only for loading data if training
@@ -26,11 +43,60 @@ pub struct LocalSettings<'a> {
     /// Number of batches that the dataloader can prepare and put in a queue before
     /// they are processed in training.
     pub batch_queue_size: usize,
+    /// Number of micro-batches each batch is split into, with gradients
+    /// accumulated over all of them before a single optimiser step. Use
+    /// this when a batch does not fit in memory. Defaults to `1`.
+    pub micro_batches: usize,
+    /// Which checkpoints to keep on disk.
+    pub checkpoint_retention: CheckpointRetention,
+    /// Continue from the most recent complete checkpoint of the run in `output_directory`,
+    /// if there is one. Refuses to start if it was saved from a different architecture.
+    pub auto_resume: bool,
+    /// Seed for weight initialisation and data shuffling (of both the training and test data),
+    /// so that runs with the same data and thread count start from the same weights and see
+    /// the same batches. If `None`, a different seed is used every time. Weights changed in any
+    /// way before `run` (e.g. loaded from a file) are kept, rather than reinitialised from the seed.
+    ///
+    /// Training itself is not bit-for-bit reproducible, as the sparse affine backward pass and
+    /// gather kernels accumulate gradients with atomic adds, whose order varies between runs.
+    pub seed: Option<u64>,
+}
+
+impl Default for LocalSettings<'_> {
+    fn default() -> Self {
+        Self {
+            threads: 4,
+            test_set: None,
+            output_directory: "checkpoints",
+            batch_queue_size: 512,
+            micro_batches: 1,
+            checkpoint_retention: CheckpointRetention::default(),
+            auto_resume: false,
+            seed: None,
+        }
+    }
 }
 
 impl LocalSettings<'_> {
     pub fn display(&self) {
         println!("Threads                : {}", ansi(self.threads, 31));
         println!("Output Path            : {}", ansi(self.output_directory, "32;1"));
+
+        if let Some(seed) = self.seed {
+            println!("Seed                   : {}", ansi(seed, 31));
+        }
+
+        if self.auto_resume {
+            println!("Auto Resume            : {}", ansi("enabled", 31));
+        }
+
+        if let Some(keep_last) = self.checkpoint_retention.keep_last {
+            let older = if self.checkpoint_retention.keep_old_quantised { "quantised only" } else { "removed" };
+            println!("Checkpoints Kept       : last {} in full, older {older}", ansi(keep_last, 31));
+        }
+
+        if self.micro_batches > 1 {
+            println!("Micro-Batches / Batch  : {}", ansi(self.micro_batches, 31));
+        }
     }
 }
@@ -0,0 +1,255 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use super::{schedule::TrainingSteps, LoaderPosition};
+
+const MAGIC: &str = "bullet run state";
+const VERSION: u32 = 3;
+
+/// Everything, other than the weights and optimiser state, needed to continue a
+/// run exactly from the end of a given superbatch. Written alongside checkpoints
+/// as `run_state.txt`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunState {
+    pub net_id: String,
+    /// Last completed superbatch.
+    pub superbatch: usize,
+    pub batch_size: usize,
+    pub batches_per_superbatch: usize,
+    pub micro_batches: usize,
+    /// Seed used by the data loader for shuffling, if it shuffles.
+    pub seed: Option<u64>,
+    /// Seed used by the validation data loader for shuffling, if there is one and it shuffles.
+    pub test_seed: Option<u64>,
+    /// Position of the data loader after the last batch of `superbatch`.
+    pub loader_position: LoaderPosition,
+    /// Position of the validation data loader after the last validation batch, if there is one.
+    pub test_position: Option<LoaderPosition>,
+    pub error_record: Vec<(usize, usize, f32)>,
+    pub validation_record: Vec<(usize, usize, f32)>,
+}
+
+impl RunState {
+    /// Panics if the run cannot be continued with the given steps.
+    pub fn check_compatible(&self, steps: TrainingSteps, micro_batches: usize) {
+        let check = |name: &str, saved: usize, current: usize| {
+            assert_eq!(saved, current, "Cannot resume run: {name} was {saved}, but is now {current}!");
+        };
+
+        check("batch size", self.batch_size, steps.batch_size);
+        check("batches per superbatch", self.batches_per_superbatch, steps.batches_per_superbatch);
+        check("micro-batches per batch", self.micro_batches, micro_batches);
+
+        assert!(
+            self.superbatch < steps.end_superbatch,
+            "Cannot resume run: superbatch {} is already the final superbatch!",
+            self.superbatch
+        );
+    }
+
+    pub fn write_to_file(&self, path: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writeln!(writer, "{MAGIC}")?;
+        writeln!(writer, "version {VERSION}")?;
+        writeln!(writer, "net_id {}", self.net_id)?;
+        writeln!(writer, "superbatch {}", self.superbatch)?;
+        writeln!(writer, "batch_size {}", self.batch_size)?;
+        writeln!(writer, "batches_per_superbatch {}", self.batches_per_superbatch)?;
+        writeln!(writer, "micro_batches {}", self.micro_batches)?;
+
+        for (name, seed) in [("seed", self.seed), ("test_seed", self.test_seed)] {
+            match seed {
+                Some(seed) => writeln!(writer, "{name} {seed}")?,
+                None => writeln!(writer, "{name} none")?,
+            }
+        }
+
+        for (name, position) in [("loader_position", Some(self.loader_position)), ("test_position", self.test_position)]
+        {
+            match position {
+                Some(LoaderPosition { offset, skip, rng, batches }) => {
+                    writeln!(writer, "{name} {offset} {skip} {rng} {batches}")?
+                }
+                None => writeln!(writer, "{name} none")?,
+            }
+        }
+
+        for (name, record) in [("error_record", &self.error_record), ("validation_record", &self.validation_record)] {
+            writeln!(writer, "{name} {}", record.len())?;
+
+            for (superbatch, batch, loss) in record {
+                writeln!(writer, "{superbatch},{batch},{loss}")?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    pub fn read_from_file(path: &str) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let mut lines = contents.lines();
+
+        if next_line(&mut lines)? != MAGIC {
+            return Err(invalid("not a run state file"));
+        }
+
+        // version 1 stored a count of loaded batches rather than the loader positions, and
+        // no test seed, while version 2 stored neither, so the loaders skip to the position
+        let version = parse::<u32>(field(&mut lines, "version")?)?;
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid(&format!("unsupported version {version}")));
+        }
+
+        let net_id = field(&mut lines, "net_id")?.to_string();
+        let superbatch = parse(field(&mut lines, "superbatch")?)?;
+        let batch_size = parse(field(&mut lines, "batch_size")?)?;
+        let batches_per_superbatch = parse(field(&mut lines, "batches_per_superbatch")?)?;
+        let micro_batches = parse(field(&mut lines, "micro_batches")?)?;
+
+        let loader_cursor = if version == 1 { Some(parse(field(&mut lines, "loader_cursor")?)?) } else { None };
+
+        let seed = parse_seed(field(&mut lines, "seed")?)?;
+        let test_seed = if version == 1 { None } else { parse_seed(field(&mut lines, "test_seed")?)? };
+
+        let (loader_position, test_position) = if version < 3 {
+            let batches = loader_cursor.unwrap_or(superbatch * batches_per_superbatch * micro_batches);
+            (LoaderPosition { batches, ..Default::default() }, None)
+        } else {
+            let loader_position = parse_position(field(&mut lines, "loader_position")?)?;
+            let loader_position = loader_position.ok_or_else(|| invalid("missing loader position"))?;
+            (loader_position, parse_position(field(&mut lines, "test_position")?)?)
+        };
+
+        let mut records = [Vec::new(), Vec::new()];
+
+        for (name, record) in ["error_record", "validation_record"].iter().zip(records.iter_mut()) {
+            let len = parse::<usize>(field(&mut lines, name)?)?;
+
+            for _ in 0..len {
+                let line = next_line(&mut lines)?;
+                let entry = line.split(',').collect::<Vec<_>>();
+
+                if entry.len() != 3 {
+                    return Err(invalid(&format!("malformed record [{line}]")));
+                }
+
+                record.push((parse(entry[0])?, parse(entry[1])?, parse(entry[2])?));
+            }
+        }
+
+        let [error_record, validation_record] = records;
+
+        Ok(Self {
+            net_id,
+            superbatch,
+            batch_size,
+            batches_per_superbatch,
+            micro_batches,
+            seed,
+            test_seed,
+            loader_position,
+            test_position,
+            error_record,
+            validation_record,
+        })
+    }
+}
+
+fn next_line<'a>(lines: &mut std::str::Lines<'a>) -> io::Result<&'a str> {
+    lines.next().ok_or_else(|| invalid("unexpected end of file"))
+}
+
+fn field<'a>(lines: &mut std::str::Lines<'a>, name: &str) -> io::Result<&'a str> {
+    let line = next_line(lines)?;
+    let value = line.strip_prefix(name).and_then(|x| x.strip_prefix(' '));
+    value.ok_or_else(|| invalid(&format!("expected [{name}], found [{line}]")))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid run state: {msg}!"))
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> io::Result<T> {
+    value.parse().map_err(|_| invalid(&format!("could not parse [{value}]")))
+}
+
+fn parse_seed(value: &str) -> io::Result<Option<u64>> {
+    match value {
+        "none" => Ok(None),
+        seed => parse(seed).map(Some),
+    }
+}
+
+fn parse_position(value: &str) -> io::Result<Option<LoaderPosition>> {
+    if value == "none" {
+        return Ok(None);
+    }
+
+    let fields = value.split(' ').collect::<Vec<_>>();
+
+    if fields.len() != 4 {
+        return Err(invalid(&format!("malformed loader position [{value}]")));
+    }
+
+    Ok(Some(LoaderPosition {
+        offset: parse(fields[0])?,
+        skip: parse(fields[1])?,
+        rng: parse(fields[2])?,
+        batches: parse(fields[3])?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_state_round_trip() {
+        let state = RunState {
+            net_id: "net with spaces".to_string(),
+            superbatch: 12,
+            batch_size: 16_384,
+            batches_per_superbatch: 6104,
+            micro_batches: 2,
+            seed: Some(u64::MAX),
+            test_seed: None,
+            loader_position: LoaderPosition { offset: 1 << 40, skip: 17, rng: u64::MAX, batches: 3 },
+            test_position: Some(LoaderPosition { batches: 96, ..Default::default() }),
+            error_record: vec![(1, 32, 0.5), (12, 6080, 0.012_345_678)],
+            validation_record: vec![(3, 0, 1.0e-7)],
+        };
+
+        let path = std::env::temp_dir().join(format!("bullet-run-state-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        state.write_to_file(path).unwrap();
+        let read = RunState::read_from_file(path);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(read.unwrap(), state);
+    }
+
+    #[test]
+    fn run_state_reads_version_1() {
+        let path = std::env::temp_dir().join(format!("bullet-run-state-v1-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let contents = "bullet run state\nversion 1\nnet_id net\nsuperbatch 3\nbatch_size 16384\n\
+            batches_per_superbatch 6104\nmicro_batches 1\nloader_cursor 18312\nseed 7\n\
+            error_record 1\n1,32,0.5\nvalidation_record 0\n";
+        std::fs::write(path, contents).unwrap();
+        let read = RunState::read_from_file(path);
+        std::fs::remove_file(path).unwrap();
+
+        let read = read.unwrap();
+        assert_eq!(read.superbatch, 3);
+        assert_eq!(read.seed, Some(7));
+        assert_eq!(read.test_seed, None);
+        assert_eq!(read.loader_position, LoaderPosition { batches: 18312, ..Default::default() });
+        assert_eq!(read.test_position, None);
+        assert_eq!(read.error_record, vec![(1, 32, 0.5)]);
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use super::settings::CheckpointRetention;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kept {
+    Full,
+    Quantised,
+    Removed,
+}
+
+impl Kept {
+    fn name(self) -> &'static str {
+        match self {
+            Kept::Full => "full",
+            Kept::Quantised => "quantised",
+            Kept::Removed => "removed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Kept::Full, Kept::Quantised, Kept::Removed].into_iter().find(|kept| kept.name() == name)
+    }
+}
+
+struct Entry {
+    superbatch: usize,
+    training_loss: f32,
+    validation_loss: Option<f32>,
+    kept: Kept,
+}
+
+/// Tracks every checkpoint saved in a run, written to `<out_dir>/<net_id>-summary.txt`.
+pub struct CheckpointSummary {
+    path: String,
+    entries: Vec<Entry>,
+}
+
+impl CheckpointSummary {
+    /// If resuming from `start_superbatch`, the entries from the existing summary file (if any)
+    /// are kept, except for those at or after `start_superbatch`, which will be saved again.
+    pub fn new(out_dir: &str, net_id: &str, start_superbatch: Option<usize>) -> Self {
+        let path = format!("{out_dir}/{net_id}-summary.txt");
+
+        let entries = if let Some(start) = start_superbatch {
+            let entries = std::fs::read_to_string(&path).map(|contents| parse_entries(&contents)).unwrap_or_default();
+            entries.into_iter().filter(|entry| entry.superbatch < start).collect()
+        } else {
+            Vec::new()
+        };
+
+        Self { path, entries }
+    }
+
+    /// Records a newly saved checkpoint, returning true if it has the
+    /// lowest validation loss of any checkpoint so far. Non-finite
+    /// validation losses are never the best.
+    pub fn record(&mut self, superbatch: usize, training_loss: f32, validation_loss: Option<f32>) -> bool {
+        let prev_best = self.best().and_then(|entry| entry.validation_loss);
+
+        self.entries.push(Entry { superbatch, training_loss, validation_loss, kept: Kept::Full });
+
+        match (validation_loss.filter(|loss| loss.is_finite()), prev_best) {
+            (Some(loss), Some(best)) => loss < best,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    /// Prunes the oldest full checkpoints, where `path` gives the directory of
+    /// the checkpoint for a given superbatch.
+    pub fn prune<F: Fn(usize) -> String>(&mut self, retention: &CheckpointRetention, path: F) {
+        let Some(keep_last) = retention.keep_last else {
+            return;
+        };
+
+        let full = self.entries.iter().filter(|entry| entry.kept == Kept::Full).count();
+
+        for entry in
+            self.entries.iter_mut().filter(|entry| entry.kept == Kept::Full).take(full.saturating_sub(keep_last))
+        {
+            let dir = path(entry.superbatch);
+
+            let result = if retention.keep_old_quantised {
+                entry.kept = Kept::Quantised;
+                remove_all_but_quantised(&dir)
+            } else {
+                entry.kept = Kept::Removed;
+                std::fs::remove_dir_all(&dir)
+            };
+
+            if let Err(e) = result {
+                println!("Warning: Failed to prune checkpoint [{dir}]: {e}");
+            }
+        }
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+
+        writeln!(writer, "superbatch,training loss,validation loss,checkpoint")?;
+
+        for entry in &self.entries {
+            let validation_loss = entry.validation_loss.map_or("-".to_string(), |loss| loss.to_string());
+            writeln!(writer, "{},{},{validation_loss},{}", entry.superbatch, entry.training_loss, entry.kept.name())?;
+        }
+
+        if let Some(best) = self.best() {
+            writeln!(writer, "best,{}", best.superbatch)?;
+        }
+
+        writer.flush()
+    }
+
+    fn best(&self) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.validation_loss.is_some_and(f32::is_finite))
+            .min_by(|a, b| a.validation_loss.unwrap().total_cmp(&b.validation_loss.unwrap()))
+    }
+}
+
+fn parse_entries(contents: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+
+    for line in contents.lines().skip(1) {
+        let split = line.split(',').collect::<Vec<_>>();
+
+        let entry = match split[..] {
+            [superbatch, training_loss, validation_loss, kept] => (|| {
+                Some(Entry {
+                    superbatch: superbatch.parse().ok()?,
+                    training_loss: training_loss.parse().ok()?,
+                    validation_loss: validation_loss.parse().ok(),
+                    kept: Kept::from_name(kept)?,
+                })
+            })(),
+            _ => None,
+        };
+
+        if let Some(entry) = entry {
+            entries.push(entry);
+        }
+    }
+
+    entries
+}
+
+fn remove_all_but_quantised(dir: &str) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if entry.file_name() == "quantised.bin" {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(entries: &[(usize, Option<f32>)]) -> CheckpointSummary {
+        let mut summary = CheckpointSummary { path: String::new(), entries: Vec::new() };
+
+        for &(superbatch, validation_loss) in entries {
+            summary.record(superbatch, 1.0, validation_loss);
+        }
+
+        summary
+    }
+
+    #[test]
+    fn best_skips_non_finite_losses() {
+        let mut summary = summary(&[(1, Some(f32::NAN)), (2, Some(0.5)), (3, None), (4, Some(f32::NEG_INFINITY))]);
+        assert_eq!(summary.best().map(|entry| entry.superbatch), Some(2));
+
+        assert!(!summary.record(5, 1.0, Some(f32::NAN)));
+        assert!(!summary.record(6, 1.0, Some(0.6)));
+        assert!(summary.record(7, 1.0, Some(0.4)));
+        assert_eq!(summary.best().map(|entry| entry.superbatch), Some(7));
+    }
+
+    #[test]
+    fn resume_drops_later_entries() {
+        let dir = std::env::temp_dir().join(format!("bullet-summary-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+
+        let mut summary = summary(&[(1, Some(0.5)), (2, Some(0.4)), (3, Some(0.3))]);
+        summary.path = format!("{dir}/net-summary.txt");
+        summary.write().unwrap();
+
+        let resumed = CheckpointSummary::new(dir, "net", Some(2));
+        let fresh = CheckpointSummary::new(dir, "net", None);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(resumed.entries.iter().map(|entry| entry.superbatch).collect::<Vec<_>>(), [1]);
+        assert!(fresh.entries.is_empty());
+    }
+}
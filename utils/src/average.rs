@@ -0,0 +1,112 @@
+use anyhow::{bail, Context};
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+
+use bullet_inference::checkpoint::{self, Tensor};
+
+/// Writes a checkpoint with the weighted average of the weights (and optimiser state) of
+/// several checkpoints of the same network, e.g. the last few of a run, or two runs to interpolate.
+#[derive(StructOpt)]
+pub struct AverageOptions {
+    /// Checkpoint directories, each containing `optimiser_state/`.
+    #[structopt(required = true, min_values = 1)]
+    pub inputs: Vec<PathBuf>,
+    /// Weight of each checkpoint, normalised to sum to 1. Defaults to equal weights.
+    #[structopt(short, long)]
+    pub weights: Vec<f32>,
+    /// Checkpoint directory to write to.
+    #[structopt(required = true, short, long)]
+    pub output: PathBuf,
+}
+
+impl AverageOptions {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let weights = if self.weights.is_empty() { vec![1.0; self.inputs.len()] } else { self.weights.clone() };
+
+        if weights.len() != self.inputs.len() {
+            bail!("Got {} weights for {} checkpoints!", weights.len(), self.inputs.len());
+        }
+
+        let total = weights.iter().sum::<f32>();
+        if total == 0.0 {
+            bail!("Weights sum to zero!");
+        }
+
+        let weights = weights.iter().map(|w| w / total).collect::<Vec<_>>();
+
+        let state_dir = self.inputs[0].join("optimiser_state");
+        let mut files = std::fs::read_dir(&state_dir)
+            .with_context(|| format!("Failed to read {}", state_dir.display()))?
+            .map(|entry| Ok(entry?.file_name()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        files.retain(|name| name.to_string_lossy().ends_with(".bin"));
+        files.sort();
+
+        if files.is_empty() {
+            bail!("No checkpoint files in {}!", state_dir.display());
+        }
+
+        let mut averages = Vec::new();
+
+        for name in files {
+            let mut average = Vec::new();
+
+            for (input, &weight) in self.inputs.iter().zip(&weights) {
+                let path = input.join("optimiser_state").join(&name);
+                let tensors = checkpoint::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+                if average.is_empty() {
+                    average = tensors
+                        .iter()
+                        .map(|t| Tensor {
+                            id: t.id.clone(),
+                            rows: t.rows,
+                            cols: t.cols,
+                            values: vec![0.0; t.values.len()],
+                        })
+                        .collect();
+                }
+
+                accumulate(&mut average, &tensors, weight)
+                    .with_context(|| format!("{} does not match", path.display()))?;
+            }
+
+            averages.push((name, average));
+        }
+
+        let output_dir = self.output.join("optimiser_state");
+        std::fs::create_dir_all(&output_dir).with_context(|| format!("Failed to create {}", output_dir.display()))?;
+
+        for (name, average) in averages {
+            let path = output_dir.join(&name);
+            checkpoint::write(&path, &average).with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Averaged {} from {} checkpoints", name.to_string_lossy(), self.inputs.len());
+        }
+
+        Ok(())
+    }
+}
+
+fn accumulate(average: &mut [Tensor], tensors: &[Tensor], weight: f32) -> anyhow::Result<()> {
+    if tensors.len() != average.len() {
+        bail!("Expected {} tensors, found {}", average.len(), tensors.len());
+    }
+
+    for avg in average {
+        let Some(tensor) = tensors.iter().find(|t| t.id == avg.id) else {
+            bail!("Missing [{}]", avg.id);
+        };
+
+        if (tensor.rows, tensor.cols) != (avg.rows, avg.cols) {
+            bail!("Expected [{}] to be {}x{}, found {}x{}", avg.id, avg.rows, avg.cols, tensor.rows, tensor.cols);
+        }
+
+        for (a, &x) in avg.values.iter_mut().zip(&tensor.values) {
+            *a += weight * x;
+        }
+    }
+
+    Ok(())
+}
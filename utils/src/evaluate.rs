@@ -0,0 +1,393 @@
+use anyhow::{bail, Context};
+use bullet_inference::{inputs::Features, outputs::OutputBuckets, Architecture, Mode, Network};
+use bulletformat::{AtaxxBoard, BulletFormat, ChessBoard, DataLoader};
+use structopt::StructOpt;
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::PathBuf,
+};
+
+use crate::network::{NetworkOptions, WithArchitecture};
+
+const BATCH_SIZE: usize = 16_384;
+
+/// Evaluates every position in a data file with the `raw.bin` of a checkpoint, writing the
+/// output of the network and its error against the recorded score and result for each position
+/// to a CSV file, and printing summary statistics for each output bucket.
+#[derive(StructOpt)]
+pub struct EvaluateOptions {
+    /// Checkpoint directory, containing `raw.bin`.
+    #[structopt(required = true, short, long)]
+    checkpoint: PathBuf,
+    /// Data file, in bulletformat.
+    #[structopt(required = true, short, long)]
+    input: PathBuf,
+    /// Read the data file as text, one position per line, either as `<fen> | <score> | <result>`
+    /// or, for chess, as EPD with the result in `c9` and optionally the score in `ce`.
+    #[structopt(long)]
+    text: bool,
+    #[structopt(required = true, short, long)]
+    output: PathBuf,
+    #[structopt(flatten)]
+    network: NetworkOptions,
+    /// Eval scale the network was trained with.
+    #[structopt(long, default_value = "400")]
+    scale: f32,
+    /// Number of the worst positions (by error against the score) to print, with their FENs.
+    #[structopt(long, default_value = "10")]
+    worst: usize,
+    #[structopt(short, long, default_value = "1")]
+    threads: usize,
+}
+
+impl EvaluateOptions {
+    pub fn run(&self) -> anyhow::Result<()> {
+        self.network.run(self)
+    }
+}
+
+impl WithArchitecture for &EvaluateOptions {
+    fn run<F, B>(self, arch: Architecture<F, B>) -> anyhow::Result<()>
+    where
+        F: Features + Clone + Sync,
+        F::Position: BulletFormat + ToFen,
+        B: OutputBuckets<F::Position>,
+    {
+        let raw = self.checkpoint.join("raw.bin");
+        let network = arch.load_raw(&raw).with_context(|| format!("Failed to load {}", raw.display()))?;
+
+        let mut output = BufWriter::new(File::create(&self.output).with_context(|| "Provide a correct path!")?);
+        let mut stats = vec![BucketStats::default(); B::BUCKETS];
+        let mut worst = Vec::new();
+        let mut index = 0;
+        let mut header = false;
+
+        let mut process = |batch: &[F::Position]| -> anyhow::Result<()> {
+            for (pos, raw) in batch.iter().zip(network.eval_batch(batch, Mode::Float, self.threads)) {
+                if !header {
+                    let columns = if raw.len() == 3 { "loss,draw,win" } else { "raw" };
+                    writeln!(output, "index,bucket,{columns},wdl,score,result,score_error,result_error")?;
+                    header = true;
+                }
+
+                let eval = Evaluation::new(&network, pos, &raw, self.scale);
+                let raw = raw.iter().map(f32::to_string).collect::<Vec<_>>().join(",");
+
+                writeln!(
+                    output,
+                    "{index},{},{raw},{},{},{},{},{}",
+                    eval.bucket,
+                    eval.wdl,
+                    pos.score(),
+                    pos.result(),
+                    eval.score_error,
+                    eval.result_error
+                )?;
+
+                stats[eval.bucket].record(&eval);
+
+                worst.push((eval.score_error.abs(), index, *pos));
+                if worst.len() > 2 * self.worst {
+                    worst.sort_by(|a, b| b.0.total_cmp(&a.0));
+                    worst.truncate(self.worst);
+                }
+
+                index += 1;
+            }
+
+            Ok(())
+        };
+
+        if self.text {
+            let file = BufReader::new(File::open(&self.input).with_context(|| "Provide a correct path!")?);
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+            for (line_number, line) in file.lines().enumerate() {
+                let line = line?;
+                let parsed = if line.contains('|') { Ok(line) } else { epd_to_text(&line) };
+
+                match parsed.and_then(|line| line.parse::<F::Position>()) {
+                    Ok(pos) => batch.push(pos),
+                    Err(message) => bail!("Error parsing line {}: {message}", line_number + 1),
+                }
+
+                if batch.len() == BATCH_SIZE {
+                    process(&batch)?;
+                    batch.clear();
+                }
+            }
+
+            process(&batch)?;
+        } else {
+            let loader =
+                DataLoader::<F::Position>::new(&self.input, 256).with_context(|| "Failed to create dataloader.")?;
+
+            let mut result = Ok(());
+            loader.map_batches(BATCH_SIZE, |batch| {
+                if result.is_ok() {
+                    result = process(batch);
+                }
+            });
+
+            result?;
+        }
+
+        output.flush()?;
+
+        println!("Evaluated {index} positions, written to {}", self.output.display());
+        println!();
+        println!("Bucket | Positions | Mean WDL | Mean Result | Score MAE | Score RMSE | Result RMSE");
+
+        for (bucket, stats) in stats.iter().enumerate() {
+            if stats.positions > 0 {
+                println!("{bucket:>6} | {stats}");
+            }
+        }
+
+        if B::BUCKETS > 1 {
+            let total = stats.iter().fold(BucketStats::default(), |total, stats| total.merge(stats));
+            println!("{:>6} | {total}", "all");
+        }
+
+        worst.sort_by(|a, b| b.0.total_cmp(&a.0));
+        worst.truncate(self.worst);
+
+        if !worst.is_empty() {
+            println!();
+            println!("Worst positions (by error against the score, from the side to move's view):");
+            println!("       Index |  Error | FEN");
+
+            for (error, index, pos) in worst {
+                println!("{index:>12} | {error:.4} | {}", pos.to_fen());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Evaluation {
+    bucket: usize,
+    wdl: f32,
+    result: f32,
+    score_error: f32,
+    result_error: f32,
+}
+
+impl Evaluation {
+    fn new<F: Features, B: OutputBuckets<F::Position>>(
+        network: &Network<F, B>,
+        pos: &F::Position,
+        raw: &[f32],
+        scale: f32,
+    ) -> Self
+    where
+        F::Position: BulletFormat,
+    {
+        // as in training, the output is either a single logit or the logits of a loss, draw and win
+        let wdl = match raw {
+            [loss, draw, win] => {
+                let max = win.max(*draw).max(*loss);
+                let (loss, draw, win) = ((loss - max).exp(), (draw - max).exp(), (win - max).exp());
+                (win + draw / 2.0) / (win + draw + loss)
+            }
+            [logit] => sigmoid(*logit),
+            _ => panic!("Invalid output size!"),
+        };
+
+        let score = sigmoid(f32::from(pos.score()) / scale);
+
+        Self {
+            bucket: network.bucket(pos),
+            wdl,
+            result: pos.result(),
+            score_error: wdl - score,
+            result_error: wdl - pos.result(),
+        }
+    }
+}
+
+/// Converts an EPD line, e.g. `<board> <stm> <castling> <ep> ce 35; c9 "1-0";`, to the text format
+/// `<fen> | <score> | <result>`. The result is read from `c9`, and the score from `ce`, which is from
+/// the side to move's view as usual in EPD, defaulting to 0. `hmvc` and `fmvn` give the move counters.
+fn epd_to_text(line: &str) -> Result<String, String> {
+    let mut rest = line.trim();
+    let mut fields = Vec::new();
+
+    for _ in 0..4 {
+        let (field, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+        if field.is_empty() {
+            return Err(format!("Expected 4 FEN fields in EPD: {line}"));
+        }
+
+        fields.push(field);
+        rest = tail.trim_start();
+    }
+
+    // operations are separated by semicolons, which may also appear in quoted operands
+    let mut operations = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in rest.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                operations.push(&rest[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    operations.push(&rest[start..]);
+
+    let (mut score, mut result, mut halfmove, mut fullmove) = (0i16, None, "0", "1");
+
+    for operation in operations.into_iter().map(str::trim).filter(|op| !op.is_empty()) {
+        let (opcode, operand) = operation.split_once(char::is_whitespace).unwrap_or((operation, ""));
+        let operand = operand.trim().trim_matches('"');
+
+        match opcode {
+            "ce" => score = operand.parse().map_err(|_| format!("Invalid score {operand}"))?,
+            "c9" => {
+                result = Some(match operand {
+                    "1-0" => "1.0",
+                    "1/2-1/2" => "0.5",
+                    "0-1" => "0.0",
+                    _ => return Err(format!("Invalid result {operand}")),
+                })
+            }
+            "hmvc" => halfmove = operand,
+            "fmvn" => fullmove = operand,
+            _ => {}
+        }
+    }
+
+    let result = result.ok_or_else(|| format!("No result (c9) in EPD: {line}"))?;
+
+    // the text format is white relative
+    if fields[1] == "b" {
+        score = score.checked_neg().ok_or_else(|| format!("Invalid score {score}"))?;
+    }
+
+    Ok(format!("{} {halfmove} {fullmove} | {score} | {result}", fields.join(" ")))
+}
+
+/// A FEN of a position as it is stored in bulletformat, from the side to move's view,
+/// without the castling rights or en passant square, which are not stored.
+pub trait ToFen {
+    fn to_fen(&self) -> String;
+}
+
+impl ToFen for ChessBoard {
+    fn to_fen(&self) -> String {
+        let mut board = [' '; 64];
+
+        for (piece, square) in *self {
+            let c = b"PNBRQK"[usize::from(piece & 7)] as char;
+            board[usize::from(square)] = if piece & 8 > 0 { c.to_ascii_lowercase() } else { c };
+        }
+
+        format!("{} w - - 0 1", board_fen(&board, 8))
+    }
+}
+
+impl ToFen for AtaxxBoard {
+    fn to_fen(&self) -> String {
+        let mut board = [' '; 49];
+
+        for (piece, square) in *self {
+            board[usize::from(square)] = ['x', 'o', '-'][usize::from(piece)];
+        }
+
+        format!("{} x 0 1", board_fen(&board, 7))
+    }
+}
+
+/// The board part of a FEN, with square 0 at the bottom left and empty squares as spaces.
+fn board_fen(board: &[char], size: usize) -> String {
+    let mut ranks = Vec::new();
+
+    for rank in board.chunks(size).rev() {
+        let mut fen = String::new();
+        let mut empty = 0;
+
+        for &c in rank {
+            if c == ' ' {
+                empty += 1;
+            } else {
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+
+                fen.push(c);
+            }
+        }
+
+        if empty > 0 {
+            fen.push_str(&empty.to_string());
+        }
+
+        ranks.push(fen);
+    }
+
+    ranks.join("/")
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[derive(Clone, Copy, Default)]
+struct BucketStats {
+    positions: usize,
+    wdl: f64,
+    result: f64,
+    score_abs_error: f64,
+    score_sq_error: f64,
+    result_sq_error: f64,
+}
+
+impl BucketStats {
+    fn record(&mut self, eval: &Evaluation) {
+        self.positions += 1;
+        self.wdl += f64::from(eval.wdl);
+        self.result += f64::from(eval.result);
+        self.score_abs_error += f64::from(eval.score_error.abs());
+        self.score_sq_error += f64::from(eval.score_error).powi(2);
+        self.result_sq_error += f64::from(eval.result_error).powi(2);
+    }
+
+    fn merge(self, other: &Self) -> Self {
+        Self {
+            positions: self.positions + other.positions,
+            wdl: self.wdl + other.wdl,
+            result: self.result + other.result,
+            score_abs_error: self.score_abs_error + other.score_abs_error,
+            score_sq_error: self.score_sq_error + other.score_sq_error,
+            result_sq_error: self.result_sq_error + other.result_sq_error,
+        }
+    }
+}
+
+impl std::fmt::Display for BucketStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let n = self.positions.max(1) as f64;
+
+        write!(
+            f,
+            "{:>9} | {:>8.4} | {:>11.4} | {:>9.4} | {:>10.4} | {:>11.4}",
+            self.positions,
+            self.wdl / n,
+            self.result / n,
+            self.score_abs_error / n,
+            (self.score_sq_error / n).sqrt(),
+            (self.result_sq_error / n).sqrt(),
+        )
+    }
+}
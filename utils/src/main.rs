@@ -1,8 +1,14 @@
+mod average;
 mod convert;
 mod count_buckets;
+mod evaluate;
 mod graph;
 mod interleave;
+mod metadata;
 mod montybinpack;
+mod network;
+mod quantisation_report;
+mod quantise;
 mod shuffle;
 mod validate;
 
@@ -17,6 +23,11 @@ pub enum Options {
     BucketCount(count_buckets::ValidateOptions),
     Graph(graph::GraphOptions),
     Montybinpack(montybinpack::MontyBinpackOptions),
+    Metadata(metadata::MetadataOptions),
+    Average(average::AverageOptions),
+    Quantise(quantise::QuantiseOptions),
+    QuantisationReport(quantisation_report::QuantisationReportOptions),
+    Evaluate(evaluate::EvaluateOptions),
 }
 
 fn main() -> anyhow::Result<()> {
@@ -28,6 +39,11 @@ fn main() -> anyhow::Result<()> {
         Options::BucketCount(options) => options.run(),
         Options::Graph(options) => options.run(),
         Options::Montybinpack(options) => options.run(),
+        Options::Metadata(options) => options.run(),
+        Options::Average(options) => options.run(),
+        Options::Quantise(options) => options.run(),
+        Options::QuantisationReport(options) => options.run(),
+        Options::Evaluate(options) => options.run(),
     }
 }
 
@@ -0,0 +1,30 @@
+use anyhow::{bail, Context};
+use bullet_inference::metadata::Metadata;
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+
+/// Prints the metadata trailer of a quantised network and checks it against the weights.
+#[derive(StructOpt)]
+pub struct MetadataOptions {
+    #[structopt(required = true, short, long)]
+    input: PathBuf,
+}
+
+impl MetadataOptions {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let bytes = std::fs::read(&self.input).with_context(|| "Failed to read network.")?;
+
+        let Some((metadata, _)) = Metadata::read_trailer(&bytes)? else {
+            bail!("Network has no metadata!");
+        };
+
+        println!("{metadata}");
+
+        metadata.verify(&bytes)?;
+
+        println!("Hash matches the weights.");
+
+        Ok(())
+    }
+}
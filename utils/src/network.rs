@@ -0,0 +1,176 @@
+use anyhow::{bail, Context};
+use bullet_inference::{
+    inputs::{self, Features},
+    outputs::{self, OutputBuckets},
+    Activation, Architecture,
+};
+use bulletformat::{BulletFormat, ChessBoard};
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+
+use crate::evaluate::ToFen;
+
+/// Describes a network in the same way as the `TrainerBuilder` it was trained with,
+/// for the commands that load its weights.
+#[derive(StructOpt)]
+pub struct NetworkOptions {
+    /// Input featureset: `chess768`, `ataxx147` or `ataxx98`,
+    /// or `chess768` with king buckets, see `king-buckets`.
+    #[structopt(long, default_value = "chess768")]
+    inputs: String,
+    /// File with the king bucket of each square (or of each square on the left half of the board,
+    /// for horizontally mirrored buckets), in the same format as for `bucket-count`.
+    #[structopt(long)]
+    king_buckets: Option<PathBuf>,
+    /// The king buckets share a plane for both kings, as `ChessBucketsMergedKings`.
+    #[structopt(long)]
+    merged_kings: bool,
+    /// The king buckets are factorised.
+    #[structopt(long)]
+    factorised: bool,
+    /// Number of `MaterialCount` output buckets.
+    #[structopt(long, default_value = "1")]
+    output_buckets: usize,
+    #[structopt(required = true, long)]
+    ft_size: usize,
+    /// Layers after the feature transformer, as given to `TrainerBuilder`, e.g. `screlu,pairwise,16,crelu,1`,
+    /// where each is an activation, `pairwise` or the size of an affine layer.
+    #[structopt(required = true, long)]
+    layers: String,
+    #[structopt(long)]
+    single_perspective: bool,
+    #[structopt(long)]
+    psqt_subnet: bool,
+    /// `i16` quantisation of each layer, as given to `TrainerBuilder::quantisations`, e.g. `255,64`.
+    /// Only needed to load quantised networks.
+    #[structopt(long)]
+    quantisations: Option<String>,
+    /// The network was trained with `disallow_transpose_in_quantised_network`.
+    #[structopt(long)]
+    no_transpose: bool,
+}
+
+/// A command run with the described network, for whichever inputs and output buckets it has.
+pub trait WithArchitecture {
+    fn run<F, B>(self, arch: Architecture<F, B>) -> anyhow::Result<()>
+    where
+        F: Features + Clone + Sync,
+        F::Position: BulletFormat + ToFen,
+        B: OutputBuckets<F::Position>;
+}
+
+impl NetworkOptions {
+    pub fn run<C: WithArchitecture>(&self, command: C) -> anyhow::Result<()> {
+        let Some(path) = &self.king_buckets else {
+            return match self.inputs.as_str() {
+                "chess768" => self.with_material_buckets(inputs::Chess768, command),
+                "ataxx147" => self.with_single_bucket(inputs::Ataxx147, command),
+                "ataxx98" => self.with_single_bucket(inputs::Ataxx98, command),
+                _ => bail!("Unrecognised inputs! Supported: 'chess768', 'ataxx147', 'ataxx98'."),
+            };
+        };
+
+        if self.inputs != "chess768" {
+            bail!("King buckets are only supported for 'chess768' inputs!");
+        }
+
+        let text = std::fs::read_to_string(path).with_context(|| "Couldn't find the bucket file!")?;
+        let buckets = text
+            .replace(',', " ")
+            .split_whitespace()
+            .map(|token| token.parse::<usize>().with_context(|| format!("Invalid bucket {token}")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        macro_rules! king_buckets {
+            ($inputs:ident, $mirrored:ident) => {
+                match buckets.len() {
+                    64 => self.with_material_buckets(inputs::$inputs::new(buckets.try_into().unwrap()), command),
+                    32 => self.with_material_buckets(inputs::$mirrored::new(buckets.try_into().unwrap()), command),
+                    n => bail!("Expected 64 (or 32 mirrored) king buckets, found {n}!"),
+                }
+            };
+        }
+
+        match (self.merged_kings, self.factorised) {
+            (false, false) => king_buckets!(ChessBuckets, ChessBucketsMirrored),
+            (false, true) => king_buckets!(ChessBucketsFactorised, ChessBucketsMirroredFactorised),
+            (true, false) => king_buckets!(ChessBucketsMergedKings, ChessBucketsMergedKingsMirrored),
+            (true, true) => king_buckets!(ChessBucketsMergedKingsFactorised, ChessBucketsMergedKingsMirroredFactorised),
+        }
+    }
+
+    fn with_material_buckets<F, C>(&self, inputs: F, command: C) -> anyhow::Result<()>
+    where
+        F: Features<Position = ChessBoard> + Clone + Sync,
+        C: WithArchitecture,
+    {
+        match self.output_buckets {
+            1 => command.run(self.architecture(inputs, outputs::Single)?),
+            2 => command.run(self.architecture(inputs, outputs::MaterialCount::<2>)?),
+            3 => command.run(self.architecture(inputs, outputs::MaterialCount::<3>)?),
+            4 => command.run(self.architecture(inputs, outputs::MaterialCount::<4>)?),
+            5 => command.run(self.architecture(inputs, outputs::MaterialCount::<5>)?),
+            6 => command.run(self.architecture(inputs, outputs::MaterialCount::<6>)?),
+            7 => command.run(self.architecture(inputs, outputs::MaterialCount::<7>)?),
+            8 => command.run(self.architecture(inputs, outputs::MaterialCount::<8>)?),
+            16 => command.run(self.architecture(inputs, outputs::MaterialCount::<16>)?),
+            n => bail!("Unsupported number of output buckets {n}!"),
+        }
+    }
+
+    fn with_single_bucket<F, C>(&self, inputs: F, command: C) -> anyhow::Result<()>
+    where
+        F: Features + Clone + Sync,
+        F::Position: BulletFormat + ToFen,
+        C: WithArchitecture,
+    {
+        if self.output_buckets != 1 {
+            bail!("Output buckets are only supported for chess!");
+        }
+
+        command.run(self.architecture(inputs, outputs::Single)?)
+    }
+
+    fn architecture<F, B: Default>(&self, inputs: F, buckets: B) -> anyhow::Result<Architecture<F, B>> {
+        let mut arch = Architecture::default().input(inputs).output_buckets(buckets);
+
+        if self.single_perspective {
+            arch = arch.single_perspective();
+        }
+
+        if let Some(quantisations) = &self.quantisations {
+            let quants = quantisations
+                .split(',')
+                .map(|q| q.trim().parse::<i16>().with_context(|| format!("Invalid quantisation {q}")))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            arch = arch.quantisations(&quants);
+        }
+
+        if self.no_transpose {
+            arch = arch.disallow_transpose_in_quantised_network();
+        }
+
+        arch = arch.feature_transformer(self.ft_size);
+
+        for layer in self.layers.split(',').map(str::trim) {
+            arch = match layer.to_lowercase().as_str() {
+                "identity" => arch.activate(Activation::Identity),
+                "relu" => arch.activate(Activation::ReLU),
+                "crelu" => arch.activate(Activation::CReLU),
+                "screlu" => arch.activate(Activation::SCReLU),
+                "sqrrelu" => arch.activate(Activation::SqrReLU),
+                "sigmoid" => arch.activate(Activation::Sigmoid),
+                "pairwise" => arch.add_pairwise_mul(),
+                size => arch.add_layer(size.parse().with_context(|| format!("Invalid layer {layer}"))?),
+            };
+        }
+
+        if self.psqt_subnet {
+            arch = arch.psqt_subnet();
+        }
+
+        Ok(arch)
+    }
+}
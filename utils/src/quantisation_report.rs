@@ -0,0 +1,77 @@
+use anyhow::{bail, Context};
+use bullet_inference::{inputs::Features, outputs::OutputBuckets, Architecture, Mode, QuantisationReport};
+use bulletformat::{BulletFormat, DataLoader};
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+
+use crate::network::{NetworkOptions, WithArchitecture};
+
+/// Evaluates the first positions of a data file with the `raw.bin` of a checkpoint and, in integer
+/// arithmetic as an engine would, with its `quantised.bin`, and reports the difference in eval and
+/// any integer overflows, as `Trainer::quantisation_report`.
+#[derive(StructOpt)]
+pub struct QuantisationReportOptions {
+    /// Checkpoint directory, containing `raw.bin` and `quantised.bin`.
+    #[structopt(required = true, short, long)]
+    checkpoint: PathBuf,
+    /// Data file, in bulletformat.
+    #[structopt(required = true, short, long)]
+    input: PathBuf,
+    #[structopt(long, default_value = "16384")]
+    positions: usize,
+    /// Eval scale the network was trained with.
+    #[structopt(long, default_value = "400")]
+    scale: f32,
+    #[structopt(flatten)]
+    network: NetworkOptions,
+}
+
+impl QuantisationReportOptions {
+    pub fn run(&self) -> anyhow::Result<()> {
+        if self.positions == 0 {
+            bail!("Need at least one position!");
+        }
+
+        self.network.run(self)
+    }
+}
+
+impl WithArchitecture for &QuantisationReportOptions {
+    fn run<F, B>(self, arch: Architecture<F, B>) -> anyhow::Result<()>
+    where
+        F: Features + Clone,
+        F::Position: BulletFormat,
+        B: OutputBuckets<F::Position>,
+    {
+        let raw = self.checkpoint.join("raw.bin");
+        let float = arch.clone().load_raw(&raw).with_context(|| format!("Failed to load {}", raw.display()))?;
+
+        let quantised = self.checkpoint.join("quantised.bin");
+        let integer =
+            arch.load_quantised(&quantised).with_context(|| format!("Failed to load {}", quantised.display()))?;
+
+        if !integer.supports_integer() {
+            bail!("Integer inference requires i8 or i16 quantisations!");
+        }
+
+        let loader =
+            DataLoader::<F::Position>::new(&self.input, 256).with_context(|| "Failed to create dataloader.")?;
+        let mut report = QuantisationReport::default();
+
+        loader.map_positions(|pos| {
+            if report.positions < self.positions {
+                let eval = float.eval(pos, Mode::Float)[0];
+                let (quantised, ranges) = integer.eval_with_ranges(pos);
+
+                // an engine truncates the scaled eval
+                let quantised = f64::from(quantised[0] * self.scale).trunc();
+                report.record(f64::from(eval * self.scale), quantised, &ranges);
+            }
+        });
+
+        println!("{report}");
+
+        Ok(())
+    }
+}
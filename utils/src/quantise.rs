@@ -0,0 +1,46 @@
+use anyhow::Context;
+use structopt::StructOpt;
+
+use std::path::PathBuf;
+
+use bullet_inference::{checkpoint, quantise::FormatDescription};
+
+/// Quantises the weights of a checkpoint as `Trainer::save_quantised` would, following a
+/// description written by `Trainer::save_format_description`.
+#[derive(StructOpt)]
+pub struct QuantiseOptions {
+    /// Checkpoint directory, containing `optimiser_state/weights.bin`.
+    #[structopt(required = true, short, long)]
+    pub checkpoint: PathBuf,
+    /// Saved format description.
+    #[structopt(required = true, short, long)]
+    pub format: PathBuf,
+    #[structopt(required = true, short, long)]
+    pub output: PathBuf,
+}
+
+impl QuantiseOptions {
+    pub fn run(&self) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(&self.format)
+            .with_context(|| format!("Failed to read {}", self.format.display()))?;
+        let description = FormatDescription::parse(&text)
+            .with_context(|| format!("Invalid description {}", self.format.display()))?;
+
+        let weights = self.checkpoint.join("optimiser_state").join("weights.bin");
+        let tensors = checkpoint::read(&weights).with_context(|| format!("Failed to read {}", weights.display()))?;
+
+        let (buf, clipped) = description.quantise(&tensors).context("Failed to quantise")?;
+
+        for (id, clipping) in clipped {
+            println!(
+                "Warning: Clipped {} values of [{id}] when quantising, by up to {}",
+                clipping.count, clipping.max_excess
+            );
+        }
+
+        std::fs::write(&self.output, buf).with_context(|| format!("Failed to write {}", self.output.display()))?;
+        println!("Written to {}", self.output.display());
+
+        Ok(())
+    }
+}